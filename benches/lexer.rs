@@ -0,0 +1,48 @@
+//! Lexes a generated source file of a few megabytes, mixing ASCII and Unicode
+//! identifiers, strings and comments, and reports the throughput.
+//!
+//! ```sh
+//! cargo bench --bench lexer
+//! ```
+
+use std::time::{Duration, Instant};
+
+use pecan::*;
+
+const RUNS: u32 = 5;
+const SIZE: usize = 4 << 20;
+
+/// Source of at least `size` bytes made of repeated small functions.
+fn generate(size: usize) -> String {
+  let mut source = String::with_capacity(size + 256);
+  let mut i = 0;
+  while source.len() < size {
+    source += &format!(
+      "// function {i}: doubles its argument /* and adds {i} */\nfn größe_{i}(wert, n_{i}) {{\n  \
+       naïve := \"héllo wörld ✓ {i}\\n\"\n  x := wert * 2 + n_{i} /* nested /* comment */ */\n  \
+       if x > {i} {{\n    return x - 0x{i:x}\n  }}\n  return x\n}}\n\n"
+    );
+    i += 1;
+  }
+  source
+}
+
+fn main() {
+  let source = generate(SIZE);
+  let mut tokens = 0;
+  let mut best = Duration::MAX;
+  for _ in 0..RUNS {
+    let start = Instant::now();
+    tokens = Lexer::new(&source).with_comments(true).lex().unwrap().len();
+    best = best.min(start.elapsed());
+  }
+
+  let megabytes = source.len() as f64 / (1024.0 * 1024.0);
+  println!(
+    "{:.1} MB, {} tokens in {:.1}ms: {:.1} MB/s",
+    megabytes,
+    tokens,
+    best.as_secs_f64() * 1000.0,
+    megabytes / best.as_secs_f64()
+  );
+}
@@ -0,0 +1,93 @@
+//! Compares the code generated for the examples with every variable and temp
+//! in the frame and with registers allocated: instructions in the output, and
+//! how long the executable takes to run.
+//!
+//! ```sh
+//! cargo bench --bench regalloc
+//! ```
+
+use std::{
+  os::unix::fs::PermissionsExt,
+  path::Path,
+  process::{Command, Stdio},
+  time::{Duration, Instant},
+};
+
+use pecan::{internal::*, *};
+
+const RUNS: u32 = 5;
+
+/// Lines of `asm` that are instructions rather than labels, directives, data or
+/// comments.
+fn instructions(asm: &str) -> usize {
+  asm
+    .lines()
+    .filter(|line| line.starts_with("  ") && !line.trim_start().starts_with(';'))
+    .filter(|line| !line.starts_with("  db ") && !line.starts_with("  dq "))
+    .count()
+}
+
+/// Builds `asm` into an executable at `path`, runs it `RUNS` times and returns
+/// its exit code and fastest time.
+fn run(asm: &str, path: &Path) -> (i32, Duration) {
+  let object = Assembler::new(asm).assemble().unwrap();
+  std::fs::write(path, write_executable(&object.image(), object.entry)).unwrap();
+  std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+  let mut code = 0;
+  let mut best = Duration::MAX;
+  for _ in 0..RUNS {
+    let start = Instant::now();
+    let status = Command::new(path).stdout(Stdio::null()).status().unwrap();
+    best = best.min(start.elapsed());
+    code = status.code().unwrap();
+  }
+
+  std::fs::remove_file(path).unwrap();
+  (code, best)
+}
+
+fn main() {
+  let mut files = std::fs::read_dir("examples")
+    .unwrap()
+    .map(|entry| entry.unwrap().path())
+    .filter(|path| path.extension().is_some_and(|ext| ext == "pn"))
+    .collect::<Vec<_>>();
+  files.sort();
+
+  println!("{:<12}{:^28}{:^24}", "", "instructions", "time");
+  println!(
+    "{:<12}{:>10}{:>10}{:>8}{:>12}{:>12}",
+    "program", "stack", "registers", "ratio", "stack", "registers"
+  );
+
+  for file in files {
+    let name = file.file_stem().unwrap().to_string_lossy().to_string();
+    let source = std::fs::read_to_string(&file).unwrap();
+    let module = Lowerer::new(check(&source, &name).unwrap()).lower();
+
+    let stack = Generator::new(module.clone())
+      .with_registers(false)
+      .generate();
+    let mut promoted = module;
+    for function in promoted.functions_mut() {
+      promote_locals(function);
+    }
+    let registers = Generator::new(promoted).generate();
+
+    let exe = std::env::temp_dir().join(format!("pn-bench-{}-{}", name, std::process::id()));
+    let (stack_code, stack_time) = run(&stack, &exe);
+    let (registers_code, registers_time) = run(&registers, &exe);
+    assert_eq!(stack_code, registers_code, "{} exits differently", name);
+
+    println!(
+      "{:<12}{:>10}{:>10}{:>8.2}{:>10.1}ms{:>10.1}ms",
+      name,
+      instructions(&stack),
+      instructions(&registers),
+      instructions(&registers) as f64 / instructions(&stack) as f64,
+      stack_time.as_secs_f64() * 1000.0,
+      registers_time.as_secs_f64() * 1000.0,
+    );
+  }
+}
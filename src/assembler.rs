@@ -0,0 +1,684 @@
+use std::collections::HashMap;
+
+use super::*;
+
+/// General purpose registers in encoding order, by name for each operand size
+/// of 1, 2, 4 and 8 bytes.
+const REGISTERS: [[&str; 4]; 16] = [
+  ["al", "ax", "eax", "rax"],
+  ["cl", "cx", "ecx", "rcx"],
+  ["dl", "dx", "edx", "rdx"],
+  ["bl", "bx", "ebx", "rbx"],
+  ["spl", "sp", "esp", "rsp"],
+  ["bpl", "bp", "ebp", "rbp"],
+  ["sil", "si", "esi", "rsi"],
+  ["dil", "di", "edi", "rdi"],
+  ["r8b", "r8w", "r8d", "r8"],
+  ["r9b", "r9w", "r9d", "r9"],
+  ["r10b", "r10w", "r10d", "r10"],
+  ["r11b", "r11w", "r11d", "r11"],
+  ["r12b", "r12w", "r12d", "r12"],
+  ["r13b", "r13w", "r13d", "r13"],
+  ["r14b", "r14w", "r14d", "r14"],
+  ["r15b", "r15w", "r15d", "r15"],
+];
+
+/// Condition codes in encoding order, as used by `jcc` and `setcc`.
+const CONDITIONS: [&str; 16] = [
+  "o", "no", "b", "ae", "e", "ne", "be", "a", "s", "ns", "p", "np", "l", "ge", "le", "g",
+];
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct Reg {
+  num:  u8,
+  /// Size in bytes.
+  size: u8,
+}
+
+impl Reg {
+  fn from_name(name: &str) -> Option<Self> {
+    REGISTERS.iter().enumerate().find_map(|(num, names)| {
+      let size = names.iter().position(|n| *n == name)?;
+      Some(Reg {
+        num:  num as u8,
+        size: 1 << size,
+      })
+    })
+  }
+
+  /// `spl`, `bpl`, `sil` and `dil` share their encoding with `ah`, `ch`, `dh`
+  /// and `bh`, a REX prefix selects them.
+  fn needs_rex(&self) -> bool {
+    self.size == 1 && (4..8).contains(&self.num)
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Operand {
+  Reg(Reg),
+  /// `[base + disp]`, with the size given by a `byte`, `word`, `dword` or
+  /// `qword` prefix if any.
+  Mem {
+    base: Reg,
+    disp: i32,
+    size: Option<u8>,
+  },
+  /// `[rel label]`, relative to the end of the instruction, which must be
+  /// where its displacement ends.
+  Rel(String),
+  Imm(i64),
+  Label(String),
+}
+
+impl Operand {
+  fn size(&self) -> Option<u8> {
+    match self {
+      Operand::Reg(reg) => Some(reg.size),
+      Operand::Mem { size, .. } => *size,
+      Operand::Rel(_) | Operand::Imm(_) | Operand::Label(_) => None,
+    }
+  }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Section {
+  Text,
+  Rodata,
+}
+
+/// Machine code and read-only data of a program.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Object {
+  pub text:        Vec<u8>,
+  pub rodata:      Vec<u8>,
+  /// Section of each label and its offset in it.
+  pub labels:      HashMap<String, (Section, usize)>,
+  /// Offset of `_start` in `text`.
+  pub entry:       usize,
+  /// Displacements in `text` to labels in `rodata` as `(position, label)`,
+  /// resolved for the layout of [`Object::image`] but to relocate if the
+  /// sections are placed apart.
+  pub relocations: Vec<(usize, String)>,
+}
+
+impl Object {
+  /// Offset of `rodata` in [`Object::image`].
+  pub fn rodata_offset(&self) -> usize {
+    self.text.len().next_multiple_of(8)
+  }
+
+  /// `text` followed by `rodata`, as they're loaded in memory.
+  pub fn image(&self) -> Vec<u8> {
+    let mut image = self.text.clone();
+    image.resize(self.rodata_offset(), 0);
+    image.extend(&self.rodata);
+    image
+  }
+}
+
+/// Encodes the NASM assembly written by [`Generator`] into x86_64 machine
+/// code, so no external assembler is needed.
+///
+/// Only the instructions and syntax the generator uses are supported. Every
+/// jump and call uses a 32-bit displacement, patched once all labels are known.
+pub struct Assembler<'a> {
+  input:   &'a str,
+  text:    Vec<u8>,
+  rodata:  Vec<u8>,
+  section: Section,
+  /// Labels by name, with their section and offset in it.
+  labels:  HashMap<String, (Section, usize)>,
+  /// Displacements to patch as `(position, label, line)`.
+  fixups:  Vec<(usize, String, usize)>,
+  /// Line being assembled, for errors.
+  line:    usize,
+}
+
+impl<'a> Assembler<'a> {
+  pub fn new(input: &'a str) -> Self {
+    Self {
+      input,
+      text: Vec::new(),
+      rodata: Vec::new(),
+      section: Section::Text,
+      labels: HashMap::new(),
+      fixups: Vec::new(),
+      line: 0,
+    }
+  }
+
+  fn error(&self, msg: &str) -> AssemblerError {
+    AssemblerError::new(self.line, msg)
+  }
+
+  fn parse_operand(&self, op: &str) -> Result<Operand, AssemblerError> {
+    let (size, rest) = match op.split_once(' ') {
+      Some(("byte", rest)) => (Some(1), rest.trim()),
+      Some(("word", rest)) => (Some(2), rest.trim()),
+      Some(("dword", rest)) => (Some(4), rest.trim()),
+      Some(("qword", rest)) => (Some(8), rest.trim()),
+      _ => (None, op),
+    };
+
+    if let Some(inner) = rest
+      .strip_prefix('[')
+      .and_then(|rest| rest.strip_suffix(']'))
+    {
+      if let Some(label) = inner.strip_prefix("rel ") {
+        return Ok(Operand::Rel(label.trim().to_string()));
+      }
+
+      let (base, disp) = match inner.find(['+', '-']) {
+        Some(i) => {
+          let disp = inner[i + 1..]
+            .trim()
+            .parse::<i32>()
+            .map_err(|_| self.error(&format!("Invalid displacement in '{}'", op)))?;
+          let disp = if inner.as_bytes()[i] == b'-' {
+            -disp
+          } else {
+            disp
+          };
+          (inner[..i].trim(), disp)
+        },
+        None => (inner.trim(), 0),
+      };
+
+      return match Reg::from_name(base) {
+        Some(base) if base.size == 8 => Ok(Operand::Mem { base, disp, size }),
+        _ => Err(self.error(&format!("Invalid base register in '{}'", op))),
+      };
+    }
+
+    if size.is_some() {
+      return Err(self.error(&format!("Expected memory operand, found '{}'", op)));
+    }
+
+    if let Some(reg) = Reg::from_name(op) {
+      return Ok(Operand::Reg(reg));
+    }
+
+    if let Ok(imm) = op.parse::<i64>() {
+      return Ok(Operand::Imm(imm));
+    }
+    // immediates above i64::MAX are written unsigned
+    if let Ok(imm) = op.parse::<u64>() {
+      return Ok(Operand::Imm(imm as i64));
+    }
+
+    if op.starts_with(|c: char| c == '.' || c == '_' || c.is_ascii_alphabetic()) {
+      return Ok(Operand::Label(op.to_string()));
+    }
+
+    Err(self.error(&format!("Invalid operand '{}'", op)))
+  }
+
+  /// Emits the optional operand size and REX prefixes, `opcode`, and a ModRM
+  /// byte addressing `rm` with `reg` as the register or opcode extension.
+  fn emit_rm(&mut self, size: u8, opcode: &[u8], reg: u8, rm: &Operand, rex: bool) {
+    if size == 2 {
+      self.text.push(0x66);
+    }
+
+    let base = match rm {
+      Operand::Reg(r) => r.num,
+      Operand::Mem { base, .. } => base.num,
+      Operand::Rel(_) => 0,
+      _ => unreachable!(),
+    };
+
+    let mut prefix = 0x40;
+    if size == 8 {
+      prefix |= 0x08;
+    }
+    if reg & 8 != 0 {
+      prefix |= 0x04;
+    }
+    if base & 8 != 0 {
+      prefix |= 0x01;
+    }
+    if prefix != 0x40 || rex {
+      self.text.push(prefix);
+    }
+
+    self.text.extend(opcode);
+
+    match rm {
+      Operand::Reg(r) => self.text.push(0xc0 | (reg & 7) << 3 | (r.num & 7)),
+      Operand::Mem { disp, .. } => {
+        // rbp and r13 without a displacement mean rip-relative addressing
+        let mode = if *disp == 0 && base & 7 != 5 {
+          0
+        } else if i8::try_from(*disp).is_ok() {
+          1
+        } else {
+          2
+        };
+        self.text.push(mode << 6 | (reg & 7) << 3 | (base & 7));
+        // rsp and r12 as base need a SIB byte
+        if base & 7 == 4 {
+          self.text.push(0x24);
+        }
+        match mode {
+          1 => self.text.push(*disp as i8 as u8),
+          2 => self.text.extend(disp.to_le_bytes()),
+          _ => (),
+        }
+      },
+      Operand::Rel(label) => {
+        // rip-relative: mode 0 with rbp as base
+        self.text.push((reg & 7) << 3 | 5);
+        self.emit_rel(label);
+      },
+      _ => unreachable!(),
+    }
+  }
+
+  /// Emits a 32-bit displacement to `label`, relative to the end of the
+  /// instruction.
+  fn emit_rel(&mut self, label: &str) {
+    self
+      .fixups
+      .push((self.text.len(), label.to_string(), self.line));
+    self.text.extend([0; 4]);
+  }
+
+  fn emit_inst(&mut self, mnemonic: &str, ops: &[Operand]) -> Result<(), AssemblerError> {
+    use Operand::*;
+
+    let rex = ops
+      .iter()
+      .any(|op| matches!(op, Reg(reg) if reg.needs_rex()));
+
+    // operand size of instructions with a register or sized memory operand
+    let size = ops.iter().find_map(|op| op.size()).unwrap_or(8);
+
+    let alu = match mnemonic {
+      "add" => Some(0),
+      "or" => Some(1),
+      "and" => Some(4),
+      "sub" => Some(5),
+      "xor" => Some(6),
+      "cmp" => Some(7),
+      _ => None,
+    };
+    let condition = |prefix: &str| {
+      mnemonic
+        .strip_prefix(prefix)
+        .and_then(|cc| CONDITIONS.iter().position(|c| *c == cc))
+        .map(|cc| cc as u8)
+    };
+
+    match (mnemonic, ops) {
+      ("mov", [dst @ (Reg(_) | Mem { .. }), Reg(src)]) => {
+        let opcode = if src.size == 1 { 0x88 } else { 0x89 };
+        self.emit_rm(src.size, &[opcode], src.num, dst, rex);
+      },
+      ("mov", [Reg(dst), src @ (Mem { .. } | Rel(_))]) => {
+        let opcode = if dst.size == 1 { 0x8a } else { 0x8b };
+        self.emit_rm(dst.size, &[opcode], dst.num, src, rex);
+      },
+      ("mov", [Reg(dst), Imm(imm)]) => match dst.size {
+        8 if i32::try_from(*imm).is_ok() => {
+          self.emit_rm(8, &[0xc7], 0, &ops[0], rex);
+          self.text.extend((*imm as i32).to_le_bytes());
+        },
+        _ => {
+          let mut prefix = 0x40;
+          if dst.size == 8 {
+            prefix |= 0x08;
+          }
+          if dst.num & 8 != 0 {
+            prefix |= 0x01;
+          }
+          if dst.size == 2 {
+            self.text.push(0x66);
+          }
+          if prefix != 0x40 || rex {
+            self.text.push(prefix);
+          }
+          let opcode = if dst.size == 1 { 0xb0 } else { 0xb8 };
+          self.text.push(opcode | (dst.num & 7));
+          self.text.extend(&imm.to_le_bytes()[..dst.size as usize]);
+        },
+      },
+      (
+        "mov",
+        [dst @ Mem {
+          size: Some(size), ..
+        }, Imm(imm)],
+      ) if i32::try_from(*imm).is_ok() => {
+        let opcode = if *size == 1 { 0xc6 } else { 0xc7 };
+        self.emit_rm(*size, &[opcode], 0, dst, rex);
+        self
+          .text
+          .extend(&imm.to_le_bytes()[..(*size).min(4) as usize]);
+      },
+      ("movsx" | "movzx", [Reg(dst), src @ (Reg(_) | Mem { .. })]) => {
+        let extend = match (mnemonic, src.size()) {
+          ("movsx", Some(1)) => 0xbe,
+          ("movsx", Some(2)) => 0xbf,
+          ("movzx", Some(1)) => 0xb6,
+          ("movzx", Some(2)) => 0xb7,
+          _ => return Err(self.error("Expected byte or word source operand")),
+        };
+        self.emit_rm(dst.size, &[0x0f, extend], dst.num, src, rex);
+      },
+      ("movsxd", [Reg(dst), src @ (Reg(_) | Mem { .. })]) => {
+        self.emit_rm(8, &[0x63], dst.num, src, rex);
+      },
+      ("lea", [Reg(dst), src @ (Mem { .. } | Rel(_))]) => {
+        self.emit_rm(dst.size, &[0x8d], dst.num, src, rex);
+      },
+      ("push", [Reg(reg)]) => {
+        if reg.num & 8 != 0 {
+          self.text.push(0x41);
+        }
+        self.text.push(0x50 | (reg.num & 7));
+      },
+      ("push", [src @ Mem { .. }]) => {
+        // push is 64-bit by default, no REX.W
+        self.emit_rm(4, &[0xff], 6, src, rex);
+      },
+      ("pop", [Reg(reg)]) => {
+        if reg.num & 8 != 0 {
+          self.text.push(0x41);
+        }
+        self.text.push(0x58 | (reg.num & 7));
+      },
+      (_, [dst @ (Reg(_) | Mem { .. }), Reg(src)]) if alu.is_some() => {
+        let opcode = alu.unwrap() << 3 | if src.size == 1 { 0x00 } else { 0x01 };
+        self.emit_rm(src.size, &[opcode], src.num, dst, rex);
+      },
+      (_, [Reg(dst), src @ Mem { .. }]) if alu.is_some() => {
+        let opcode = alu.unwrap() << 3 | if dst.size == 1 { 0x02 } else { 0x03 };
+        self.emit_rm(dst.size, &[opcode], dst.num, src, rex);
+      },
+      (_, [dst @ (Reg(_) | Mem { .. }), Imm(imm)]) if alu.is_some() => {
+        if size == 1 {
+          self.emit_rm(size, &[0x80], alu.unwrap(), dst, rex);
+          self.text.push(*imm as u8);
+        } else if i8::try_from(*imm).is_ok() {
+          self.emit_rm(size, &[0x83], alu.unwrap(), dst, rex);
+          self.text.push(*imm as u8);
+        } else if i32::try_from(*imm).is_ok() {
+          self.emit_rm(size, &[0x81], alu.unwrap(), dst, rex);
+          self
+            .text
+            .extend(&imm.to_le_bytes()[..(size as usize).min(4)]);
+        } else {
+          return Err(self.error("Immediate out of range"));
+        }
+      },
+      ("test", [dst @ (Reg(_) | Mem { .. }), Reg(src)]) => {
+        let opcode = if src.size == 1 { 0x84 } else { 0x85 };
+        self.emit_rm(src.size, &[opcode], src.num, dst, rex);
+      },
+      ("imul", [Reg(dst), src @ (Reg(_) | Mem { .. })]) => {
+        self.emit_rm(dst.size, &[0x0f, 0xaf], dst.num, src, rex);
+      },
+      ("not" | "neg" | "mul" | "div" | "idiv", [src @ (Reg(_) | Mem { .. })]) => {
+        let ext = match mnemonic {
+          "not" => 2,
+          "neg" => 3,
+          "mul" => 4,
+          "div" => 6,
+          _ => 7,
+        };
+        let opcode = if size == 1 { 0xf6 } else { 0xf7 };
+        self.emit_rm(size, &[opcode], ext, src, rex);
+      },
+      ("cqo", []) => self.text.extend([0x48, 0x99]),
+      ("ret", []) => self.text.push(0xc3),
+      ("syscall", []) => self.text.extend([0x0f, 0x05]),
+      ("rep movsb", []) => self.text.extend([0xf3, 0xa4]),
+      ("jmp", [Label(label)]) => {
+        self.text.push(0xe9);
+        self.emit_rel(label);
+      },
+      ("call", [Label(label)]) => {
+        self.text.push(0xe8);
+        self.emit_rel(label);
+      },
+      (_, [Label(label)]) if condition("j").is_some() => {
+        self.text.extend([0x0f, 0x80 | condition("j").unwrap()]);
+        self.emit_rel(label);
+      },
+      (_, [dst @ (Reg(_) | Mem { .. })]) if condition("set").is_some() && size == 1 => {
+        self.emit_rm(1, &[0x0f, 0x90 | condition("set").unwrap()], 0, dst, rex);
+      },
+      _ => {
+        return Err(self.error(&format!("Unsupported instruction '{}'", mnemonic)));
+      },
+    }
+
+    Ok(())
+  }
+
+  /// Emits the comma separated integers `ops` as `size` bytes each into the
+  /// current section.
+  fn emit_data(&mut self, size: usize, ops: &str) -> Result<(), AssemblerError> {
+    for op in ops.split(',') {
+      let val = match self.parse_operand(op.trim())? {
+        Operand::Imm(val) => val,
+        _ => return Err(self.error(&format!("Expected integer, found '{}'", op.trim()))),
+      };
+      let bytes = &val.to_le_bytes()[..size];
+      match self.section {
+        Section::Text => self.text.extend(bytes),
+        Section::Rodata => self.rodata.extend(bytes),
+      }
+    }
+    Ok(())
+  }
+
+  fn assemble_line(&mut self, line: &str) -> Result<(), AssemblerError> {
+    let line = match line.split_once(';') {
+      Some((code, _)) => code.trim(),
+      None => line.trim(),
+    };
+
+    if line.is_empty() || line.starts_with("global ") {
+      return Ok(());
+    }
+
+    if let Some(section) = line.strip_prefix("section ") {
+      self.section = match section.trim() {
+        ".text" => Section::Text,
+        ".rodata" => Section::Rodata,
+        section => return Err(self.error(&format!("Unsupported section '{}'", section))),
+      };
+      return Ok(());
+    }
+
+    if let Some(label) = line.strip_suffix(':') {
+      let offset = match self.section {
+        Section::Text => self.text.len(),
+        Section::Rodata => self.rodata.len(),
+      };
+      if self
+        .labels
+        .insert(label.to_string(), (self.section, offset))
+        .is_some()
+      {
+        return Err(self.error(&format!("Label '{}' already defined", label)));
+      }
+      return Ok(());
+    }
+
+    let (mnemonic, ops) = match line.split_once(' ') {
+      // a prefix is part of the mnemonic
+      Some(("rep", _)) => (line, ""),
+      Some((mnemonic, ops)) => (mnemonic, ops.trim()),
+      None => (line, ""),
+    };
+
+    if let Some(size) = match mnemonic {
+      "db" => Some(1),
+      "dq" => Some(8),
+      _ => None,
+    } {
+      return self.emit_data(size, ops);
+    }
+    if self.section != Section::Text {
+      return Err(self.error("Instructions are only supported in '.text'"));
+    }
+
+    let ops = if ops.is_empty() {
+      Vec::new()
+    } else {
+      ops
+        .split(',')
+        .map(|op| self.parse_operand(op.trim()))
+        .collect::<Result<Vec<_>, _>>()?
+    };
+
+    self.emit_inst(mnemonic, &ops)
+  }
+
+  pub fn assemble(&mut self) -> Result<Object, AssemblerError> {
+    for (i, line) in self.input.lines().enumerate() {
+      self.line = i + 1;
+      self.assemble_line(line)?;
+    }
+
+    // .rodata follows .text, see Object::image
+    let rodata_offset = self.text.len().next_multiple_of(8);
+
+    let mut relocations = Vec::new();
+    for (pos, label, line) in self.fixups.iter() {
+      let target = match self.labels.get(label) {
+        Some((Section::Text, offset)) => *offset,
+        Some((Section::Rodata, offset)) => {
+          relocations.push((*pos, label.clone()));
+          rodata_offset + offset
+        },
+        None => {
+          return Err(AssemblerError::new(
+            *line,
+            &format!("Undefined label '{}'", label),
+          ))
+        },
+      };
+      let rel = target as i64 - (*pos as i64 + 4);
+      self.text[*pos..*pos + 4].copy_from_slice(&(rel as i32).to_le_bytes());
+    }
+
+    let entry = match self.labels.get("_start") {
+      Some((Section::Text, entry)) => *entry,
+      _ => return Err(AssemblerError::new(0, "Missing '_start' label")),
+    };
+
+    Ok(Object {
+      text: std::mem::take(&mut self.text),
+      rodata: std::mem::take(&mut self.rodata),
+      labels: std::mem::take(&mut self.labels),
+      entry,
+      relocations,
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn encode(line: &str) -> Vec<u8> {
+    let mut assembler = Assembler::new(line);
+    assembler.assemble_line(line).unwrap();
+    assembler.text
+  }
+
+  #[test]
+  fn encodes_generated_instructions() {
+    // expected bytes as produced by GNU as
+    let cases: &[(&str, &[u8])] = &[
+      ("mov rax, 42", &[0x48, 0xc7, 0xc0, 0x2a, 0x00, 0x00, 0x00]),
+      ("mov rax, 18446744073709551615", &[
+        0x48, 0xc7, 0xc0, 0xff, 0xff, 0xff, 0xff,
+      ]),
+      ("mov rax, 4294967296", &[0x48, 0xb8, 0, 0, 0, 0, 1, 0, 0, 0]),
+      ("mov qword [rbp - 8], rax", &[0x48, 0x89, 0x45, 0xf8]),
+      ("mov [rbp - 1], al", &[0x88, 0x45, 0xff]),
+      ("mov [rbp - 12], ax", &[0x66, 0x89, 0x45, 0xf4]),
+      ("mov rax, qword [rbp + 16]", &[0x48, 0x8b, 0x45, 0x10]),
+      ("mov rdi, [rsp + 8]", &[0x48, 0x8b, 0x7c, 0x24, 0x08]),
+      ("mov r9, [rsp + 200]", &[
+        0x4c, 0x8b, 0x8c, 0x24, 0xc8, 0x00, 0x00, 0x00,
+      ]),
+      ("mov rax, r8", &[0x4c, 0x89, 0xc0]),
+      ("movsx rax, byte [rbp - 1]", &[0x48, 0x0f, 0xbe, 0x45, 0xff]),
+      ("movzx eax, word [rbp - 2]", &[0x0f, 0xb7, 0x45, 0xfe]),
+      ("movsxd rax, dword [rbp - 4]", &[0x48, 0x63, 0x45, 0xfc]),
+      ("movzx rax, al", &[0x48, 0x0f, 0xb6, 0xc0]),
+      ("mov eax, eax", &[0x89, 0xc0]),
+      ("movzx eax, dil", &[0x40, 0x0f, 0xb6, 0xc7]),
+      ("push qword [rsp + 16]", &[0xff, 0x74, 0x24, 0x10]),
+      ("push r9", &[0x41, 0x51]),
+      ("pop rcx", &[0x59]),
+      ("sub rsp, 8", &[0x48, 0x83, 0xec, 0x08]),
+      ("sub rsp, 4096", &[0x48, 0x81, 0xec, 0x00, 0x10, 0x00, 0x00]),
+      ("cmp rax, rcx", &[0x48, 0x39, 0xc8]),
+      ("xor edx, edx", &[0x31, 0xd2]),
+      ("imul rax, rcx", &[0x48, 0x0f, 0xaf, 0xc1]),
+      ("idiv rcx", &[0x48, 0xf7, 0xf9]),
+      ("div rcx", &[0x48, 0xf7, 0xf1]),
+      ("neg rax", &[0x48, 0xf7, 0xd8]),
+      ("setbe al", &[0x0f, 0x96, 0xc0]),
+      ("cqo", &[0x48, 0x99]),
+      ("syscall", &[0x0f, 0x05]),
+      ("rep movsb", &[0xf3, 0xa4]),
+    ];
+
+    for (line, bytes) in cases {
+      assert_eq!(encode(line), *bytes, "{}", line);
+    }
+  }
+
+  #[test]
+  fn jumps_are_relative_to_the_next_instruction() {
+    let object = Assembler::new(
+      "_start:
+  jmp .L1
+  je _start
+.L1:
+  call _start
+",
+    )
+    .assemble()
+    .unwrap();
+
+    assert_eq!(object.text, vec![
+      0xe9, 0x06, 0x00, 0x00, 0x00, // jmp +6
+      0x0f, 0x84, 0xf5, 0xff, 0xff, 0xff, // je -11
+      0xe8, 0xf0, 0xff, 0xff, 0xff, // call -16
+    ]);
+    assert_eq!(object.entry, 0);
+  }
+
+  #[test]
+  fn rodata_follows_text_and_is_relocated() {
+    let object = Assembler::new(
+      "section .text
+_start:
+  lea rdi, [rel _str0]
+  ret
+section .rodata
+_str0:
+  dq 2
+  db 104, 105
+",
+    )
+    .assemble()
+    .unwrap();
+
+    // rodata starts at 8, 1 byte after the end of the lea
+    assert_eq!(object.text, vec![
+      0x48, 0x8d, 0x3d, 0x01, 0x00, 0x00, 0x00, 0xc3
+    ]);
+    assert_eq!(object.rodata, vec![2, 0, 0, 0, 0, 0, 0, 0, b'h', b'i']);
+    assert_eq!(object.labels["_str0"], (Section::Rodata, 0));
+    assert_eq!(object.relocations, vec![(3, "_str0".to_string())]);
+    assert_eq!(&object.image()[8..], &object.rodata[..]);
+  }
+}
@@ -1,19 +1,325 @@
+use std::{cell::OnceCell, fmt, rc::Rc};
+
 use super::*;
 
-#[derive(Debug, Copy, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Type {
-  Integer, // 8 bytes
+  I8,
+  I16,
+  I32,
+  I64,
+  U8,
+  U16,
+  U32,
+  U64,
+  Bool,
+  /// Address of a string in `.rodata`: its length as 8 bytes, then its bytes.
+  Str,
+  /// `[elem; len]`, the elements one after the other. Kept in memory, a value
+  /// of the type is the address of the first element.
+  Array(Box<Type>, usize),
+  /// A declared struct, kept in memory like arrays.
+  Struct(Rc<Struct>),
+  /// A declared enum, kept in memory like structs.
+  Enum(Rc<Enum>),
+  /// `*T`, the address of a value of type `T`.
+  Pointer(Box<Type>),
+}
+
+impl Type {
+  /// The builtin type called `name`, where `int` is another name for `i64`.
+  pub fn from_name(name: &str) -> Option<Self> {
+    match name {
+      "i8" => Some(Type::I8),
+      "i16" => Some(Type::I16),
+      "i32" => Some(Type::I32),
+      "i64" | "int" => Some(Type::I64),
+      "u8" => Some(Type::U8),
+      "u16" => Some(Type::U16),
+      "u32" => Some(Type::U32),
+      "u64" => Some(Type::U64),
+      "bool" => Some(Type::Bool),
+      "str" => Some(Type::Str),
+      _ => None,
+    }
+  }
+
+  pub fn size(&self) -> usize {
+    match self {
+      Type::I8 | Type::U8 | Type::Bool => 1,
+      Type::I16 | Type::U16 => 2,
+      Type::I32 | Type::U32 => 4,
+      Type::I64 | Type::U64 | Type::Str | Type::Pointer(_) => 8,
+      Type::Array(elem, len) => elem.size() * len,
+      Type::Struct(def) => def.size(),
+      Type::Enum(def) => def.size,
+    }
+  }
+
+  /// Alignment in bytes, the size of scalars.
+  pub fn align(&self) -> usize {
+    match self {
+      Type::Array(elem, _) => elem.align(),
+      Type::Struct(def) => def.align(),
+      Type::Enum(def) => def.align,
+      _ => self.size(),
+    }
+  }
+
+  pub fn is_integer(&self) -> bool {
+    self.is_scalar() && !matches!(self, Type::Bool | Type::Str | Type::Pointer(_))
+  }
+
+  /// Whether values of the type hold a `def` in their own bytes, not behind a
+  /// pointer.
+  pub fn holds(&self, def: &Struct) -> bool {
+    match self {
+      Type::Struct(other) => **other == *def,
+      Type::Array(elem, _) => elem.holds(def),
+      _ => false,
+    }
+  }
+
+  /// Whether values of the type fit in a register, as opposed to being kept in
+  /// memory and handled by address.
+  pub fn is_scalar(&self) -> bool {
+    !matches!(self, Type::Array(..) | Type::Struct(_) | Type::Enum(_))
+  }
+
+  pub fn is_signed(&self) -> bool {
+    matches!(self, Type::I8 | Type::I16 | Type::I32 | Type::I64)
+  }
+
+  /// Truncates `val` to the width of the type and extends it back to 64 bits,
+  /// which is how values of the type are kept in registers.
+  pub fn wrap(&self, val: i64) -> i64 {
+    match self {
+      Type::I8 => val as i8 as i64,
+      Type::I16 => val as i16 as i64,
+      Type::I32 => val as i32 as i64,
+      Type::U8 => val as u8 as i64,
+      Type::U16 => val as u16 as i64,
+      Type::U32 => val as u32 as i64,
+      Type::I64
+      | Type::U64
+      | Type::Str
+      | Type::Array(..)
+      | Type::Struct(_)
+      | Type::Enum(_)
+      | Type::Pointer(_) => val,
+      Type::Bool => (val != 0) as i64,
+    }
+  }
 }
 
+impl fmt::Display for Type {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Type::I8 => write!(f, "i8"),
+      Type::I16 => write!(f, "i16"),
+      Type::I32 => write!(f, "i32"),
+      Type::I64 => write!(f, "i64"),
+      Type::U8 => write!(f, "u8"),
+      Type::U16 => write!(f, "u16"),
+      Type::U32 => write!(f, "u32"),
+      Type::U64 => write!(f, "u64"),
+      Type::Bool => write!(f, "bool"),
+      Type::Str => write!(f, "str"),
+      Type::Array(elem, len) => write!(f, "[{}; {}]", elem, len),
+      Type::Struct(def) => write!(f, "{}", def.name),
+      Type::Enum(def) => write!(f, "{}", def.name),
+      Type::Pointer(ty) => write!(f, "*{}", ty),
+    }
+  }
+}
+
+/// A field of a [`Struct`], `offset` bytes after the start of it.
 #[derive(Debug, Clone, PartialEq)]
-pub struct SymbolTableEntry {
+pub struct Field {
   pub name:   String,
   pub ty:     Type,
   pub offset: usize,
 }
 
+/// A declared struct. Fields are laid out in order, each aligned for its type,
+/// and the size is padded to the alignment of the struct, the largest of its
+/// fields', so the fields of every element of an array stay aligned. The struct
+/// exists before its fields, see [`Struct::declare`], so they can point to it.
+#[derive(Clone)]
+pub struct Struct {
+  pub name: String,
+  layout:   OnceCell<Layout>,
+}
+
+#[derive(Debug, Clone)]
+struct Layout {
+  fields: Vec<Field>,
+  size:   usize,
+  align:  usize,
+}
+
+// structs are declared once so their names tell them apart, and one pointing
+// to itself isn't compared or printed field by field forever
+impl PartialEq for Struct {
+  fn eq(&self, other: &Self) -> bool {
+    self.name == other.name
+  }
+}
+
+impl fmt::Debug for Struct {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.debug_struct("Struct")
+      .field("name", &self.name)
+      .finish_non_exhaustive()
+  }
+}
+
+/// Lays values of the types out one after the other from `start`, each aligned
+/// for its type. Gives their offsets, where the last one ends and the largest
+/// alignment.
+fn lay_out(start: usize, types: &[Type]) -> (Vec<usize>, usize, usize) {
+  let mut end = start;
+  let mut align = 1;
+  let offsets = types
+    .iter()
+    .map(|ty| {
+      let offset = end.next_multiple_of(ty.align());
+      end = offset + ty.size();
+      align = align.max(ty.align());
+      offset
+    })
+    .collect();
+  (offsets, end, align)
+}
+
+impl Struct {
+  pub fn new(name: String, fields: Vec<(String, Type)>) -> Self {
+    let def = Self::declare(name);
+    def.define(fields);
+    def
+  }
+
+  /// A struct whose fields aren't known yet, it has none and takes no bytes
+  /// until [`Struct::define`] lays them out.
+  pub fn declare(name: String) -> Self {
+    Self {
+      name,
+      layout: OnceCell::new(),
+    }
+  }
+
+  /// Lays out the fields of a struct made by [`Struct::declare`].
+  pub fn define(&self, fields: Vec<(String, Type)>) {
+    let types = fields.iter().map(|(_, ty)| ty.clone()).collect::<Vec<_>>();
+    let (offsets, size, align) = lay_out(0, &types);
+    let fields = fields
+      .into_iter()
+      .zip(offsets)
+      .map(|((name, ty), offset)| Field { name, ty, offset })
+      .collect();
+
+    let layout = Layout {
+      fields,
+      size: size.next_multiple_of(align),
+      align,
+    };
+    if self.layout.set(layout).is_err() {
+      panic!("struct {} defined twice", self.name);
+    }
+  }
+
+  pub fn fields(&self) -> &[Field] {
+    self.layout.get().map_or(&[], |layout| &layout.fields)
+  }
+
+  pub fn size(&self) -> usize {
+    self.layout.get().map_or(0, |layout| layout.size)
+  }
+
+  pub fn align(&self) -> usize {
+    self.layout.get().map_or(1, |layout| layout.align)
+  }
+
+  pub fn field(&self, name: &str) -> Option<&Field> {
+    self.fields().iter().find(|field| field.name == name)
+  }
+}
+
+/// A variant of an [`Enum`] and the types of the values it holds, each
+/// `offsets` bytes after the start of the enum.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Variant {
+  pub name:    String,
+  pub fields:  Vec<Type>,
+  pub offsets: Vec<usize>,
+}
+
+/// A declared enum. The [`Enum::TAG`] at the start is the index of the variant,
+/// its values follow laid out like the fields of a struct. Variants overlap,
+/// the size is the largest one's padded to the alignment of the enum.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Enum {
+  pub name:     String,
+  pub variants: Vec<Variant>,
+  pub size:     usize,
+  pub align:    usize,
+}
+
+impl Enum {
+  /// Type of the tag, which limits enums to 256 variants.
+  pub const TAG: Type = Type::U8;
+
+  pub fn new(name: String, variants: Vec<(String, Vec<Type>)>) -> Self {
+    let mut size = Self::TAG.size();
+    let mut align = Self::TAG.align();
+    let variants = variants
+      .into_iter()
+      .map(|(name, fields)| {
+        let (offsets, end, variant_align) = lay_out(Self::TAG.size(), &fields);
+        size = size.max(end);
+        align = align.max(variant_align);
+        Variant {
+          name,
+          fields,
+          offsets,
+        }
+      })
+      .collect();
+
+    Self {
+      name,
+      variants,
+      size: size.next_multiple_of(align),
+      align,
+    }
+  }
+
+  /// Index of the variant, its tag.
+  pub fn variant(&self, name: &str) -> Option<usize> {
+    self
+      .variants
+      .iter()
+      .position(|variant| variant.name == name)
+  }
+
+  /// `Enum::Variant`, how the variant at `index` is written.
+  pub fn path(&self, index: usize) -> String {
+    format!("{}::{}", self.name, self.variants[index].name)
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SymbolTableEntry {
+  pub name:   String,
+  pub ty:     Type,
+  /// Distance below `rbp` of the lowest byte of the variable, so it occupies
+  /// `[rbp - offset, rbp - offset + size)`. Negative for arguments the caller
+  /// passed on the stack, which live above the return address.
+  pub offset: isize,
+}
+
 impl SymbolTableEntry {
-  pub fn new(name: String, ty: Type, offset: usize) -> Self {
+  pub fn new(name: String, ty: Type, offset: isize) -> Self {
     Self { name, ty, offset }
   }
 }
@@ -21,6 +327,8 @@ impl SymbolTableEntry {
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub enum ScopeType {
   Global,
+  Block,
+  Function,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -41,42 +349,98 @@ impl SymbolTable {
     }
   }
 
-  fn size(&self) -> usize {
-    let mut size = 0;
-    for entry in self.entries.iter() {
-      size += match entry.ty {
-        Type::Integer => 8,
-      }
-    }
-    size
+  /// Bytes this table occupies in the stack frame, including padding. Stack
+  /// passed arguments are owned by the caller's frame and don't count.
+  pub fn size(&self) -> usize {
+    self
+      .entries
+      .iter()
+      .filter(|entry| entry.offset >= 0)
+      .map(|entry| entry.offset as usize - self.offset)
+      .max()
+      .unwrap_or(0)
+  }
+
+  fn get(&self, name: &str) -> Option<usize> {
+    self.entries.iter().position(|entry| entry.name == name)
+  }
+
+  /// Offset of a new variable of type `ty` placed below everything in the
+  /// table, aligned for its type.
+  fn next_offset(&self, ty: &Type) -> isize {
+    (self.offset + self.size() + ty.size()).next_multiple_of(ty.align()) as isize
+  }
+
+  fn add(&mut self, name: String, ty: Type) {
+    let offset = self.next_offset(&ty);
+    self.entries.push(SymbolTableEntry::new(name, ty, offset));
   }
 
-  fn get(&self, name: &str) -> Option<&SymbolTableEntry> {
-    for entry in self.entries.iter() {
-      if entry.name == name {
-        return Some(entry);
+  /// Places the variables again after their types changed, starting at
+  /// `offset`.
+  fn layout(&mut self, offset: usize) {
+    self.offset = offset;
+    let entries = std::mem::take(&mut self.entries);
+    for mut entry in entries {
+      if entry.offset >= 0 {
+        entry.offset = self.next_offset(&entry.ty);
       }
+      self.entries.push(entry);
     }
-    None
   }
 
-  fn add(&mut self, name: String, ty: Type) {
-    let offset = self.offset + self.size();
+  fn add_at(&mut self, name: String, ty: Type, offset: isize) {
     self.entries.push(SymbolTableEntry::new(name, ty, offset));
   }
 }
 
+/// Registers holding the first integer arguments in the System V AMD64 calling
+/// convention. Further arguments are passed on the stack.
+pub const ARG_REGISTERS: [&str; 6] = ["rdi", "rsi", "rdx", "rcx", "r8", "r9"];
+
+/// A variable resolved to its declaration.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VarRef {
+  pub name:  String,
+  pub table: usize,
+  pub index: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Function {
+  pub name:   String,
+  pub params: Vec<(String, Type)>,
+  pub ret:    Type,
+  /// Body of the function, whose table is the `ScopeType::Function` table
+  /// holding the parameters.
+  pub body:   Block,
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct Program {
   pub stmts:         Vec<Stmt>,
+  pub functions:     Vec<Function>,
+  /// Structs in the order they're declared, each only using the ones before.
+  pub structs:       Vec<Rc<Struct>>,
+  /// Enums in the order they're declared, like structs.
+  pub enums:         Vec<Rc<Enum>>,
   pub symbol_tables: Vec<SymbolTable>,
   pub curr_table:    usize,
 }
 
+impl Default for Program {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
 impl Program {
   pub fn new() -> Self {
     Self {
       stmts:         Vec::new(),
+      functions:     Vec::new(),
+      structs:       Vec::new(),
+      enums:         Vec::new(),
       symbol_tables: vec![SymbolTable::new(ScopeType::Global, 0, None)],
       curr_table:    0,
     }
@@ -87,33 +451,357 @@ impl Program {
   }
 
   pub fn push_entry(&mut self, name: String, ty: Type) {
-    self.symbol_tables.last_mut().unwrap().add(name, ty);
+    self.symbol_tables[self.curr_table].add(name, ty);
   }
 
-  pub fn find_entry(&self, name: &str, local: bool) -> Option<&SymbolTableEntry> {
-    if local {
-      return self.symbol_tables[self.curr_table].get(name);
+  /// Declares a parameter of the function whose table is current. The first
+  /// six are spilled into the frame by the prologue, the rest stay where the
+  /// caller pushed them, above the saved `rbp` and return address.
+  pub fn push_param(&mut self, name: String, ty: Type, index: usize) {
+    if index < ARG_REGISTERS.len() {
+      self.push_entry(name, ty);
+    } else {
+      let offset = -(16 + 8 * (index - ARG_REGISTERS.len()) as isize);
+      self.symbol_tables[self.curr_table].add_at(name, ty, offset);
+    }
+  }
+
+  /// Opens a child scope of the current one and makes it current. Its slots
+  /// start after everything the parent has declared so far, so a block's
+  /// variables never alias live variables of the enclosing scopes.
+  ///
+  /// A function scope starts a new frame instead: it has no parent, so the
+  /// body can't see the caller's variables.
+  pub fn push_scope(&mut self, ty: ScopeType) -> usize {
+    let table = match ty {
+      ScopeType::Function => SymbolTable::new(ty, 0, None),
+      _ => {
+        let parent = &self.symbol_tables[self.curr_table];
+        let offset = parent.offset + parent.size();
+        SymbolTable::new(ty, offset, Some(self.curr_table))
+      },
+    };
+    self.symbol_tables.push(table);
+    self.curr_table = self.symbol_tables.len() - 1;
+    self.curr_table
+  }
+
+  /// Closes the current scope. Functions can only be declared at the top level
+  /// so closing a function scope returns to the global one.
+  pub fn pop_scope(&mut self) {
+    self.curr_table = self.symbol_tables[self.curr_table].parent.unwrap_or(0);
+  }
+
+  /// Outermost table of the frame `table` belongs to: the global table or the
+  /// table of the enclosing function.
+  pub fn frame_root(&self, table: usize) -> usize {
+    let mut table = table;
+    while let Some(parent) = self.symbol_tables[table].parent {
+      table = parent;
     }
+    table
+  }
+
+  /// Bytes the frame rooted at `root` reserves below `rbp` to hold the
+  /// variables of every scope in it, rounded up so `rsp` stays 16-byte aligned.
+  pub fn frame_size(&self, root: usize) -> usize {
+    (0..self.symbol_tables.len())
+      .filter(|i| self.frame_root(*i) == root)
+      .map(|i| self.symbol_tables[i].offset + self.symbol_tables[i].size())
+      .max()
+      .unwrap_or(0)
+      .next_multiple_of(16)
+  }
+
+  pub fn find_function(&self, name: &str) -> Option<&Function> {
+    self.functions.iter().find(|f| f.name == name)
+  }
+
+  pub fn find_struct(&self, name: &str) -> Option<&Rc<Struct>> {
+    self.structs.iter().find(|s| s.name == name)
+  }
+
+  pub fn find_enum(&self, name: &str) -> Option<&Rc<Enum>> {
+    self.enums.iter().find(|e| e.name == name)
+  }
+
+  /// Finds the declaration `name` refers to from the current scope, looking
+  /// only at the current table if `local`.
+  pub fn resolve(&self, name: &str, local: bool) -> Option<VarRef> {
     let mut table = Some(self.curr_table);
     while let Some(i) = table {
-      if let Some(entry) = self.symbol_tables[i].get(name) {
-        return Some(entry);
+      if let Some(index) = self.symbol_tables[i].get(name) {
+        return Some(VarRef {
+          name: name.to_string(),
+          table: i,
+          index,
+        });
+      }
+      if local {
+        break;
       }
       table = self.symbol_tables[i].parent;
     }
     None
   }
+
+  pub fn find_entry(&self, name: &str, local: bool) -> Option<&SymbolTableEntry> {
+    self.resolve(name, local).map(|var| self.entry(&var))
+  }
+
+  pub fn entry(&self, var: &VarRef) -> &SymbolTableEntry {
+    &self.symbol_tables[var.table].entries[var.index]
+  }
+
+  pub fn entry_mut(&mut self, var: &VarRef) -> &mut SymbolTableEntry {
+    &mut self.symbol_tables[var.table].entries[var.index]
+  }
+
+  /// Recomputes every variable's offset from its final type. Each block scope
+  /// is placed after all of its parent's variables, tables are stored parents
+  /// first so a single pass suffices.
+  pub fn layout(&mut self) {
+    for i in 0..self.symbol_tables.len() {
+      let offset = match self.symbol_tables[i].parent {
+        Some(parent) => {
+          let parent = &self.symbol_tables[parent];
+          parent.offset + parent.size()
+        },
+        None => 0,
+      };
+      self.symbol_tables[i].layout(offset);
+    }
+  }
+}
+
+/// A `{ }` delimited list of statements with its own symbol table.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Block {
+  pub stmts: Vec<Stmt>,
+  pub table: usize,
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Stmt {
   Exit(Expr),
-  VarDecl(String, Expr),
-  VarAssign(String, Expr),
+  /// Declaration with an optional type annotation.
+  VarDecl(VarRef, Option<Type>, Expr),
+  VarAssign(VarRef, Expr),
+  /// Assignment to a place other than a variable, e.g. `a[i] = v`.
+  Assign(Expr, Expr),
+  /// `if`/`else if` branches in order, followed by the optional `else` block.
+  If(Vec<(Expr, Block)>, Option<Block>),
+  While(Expr, Block),
+  /// `match value { pattern => { ... } ... }`, running the block of the first
+  /// arm whose pattern matches.
+  Match(Expr, Vec<(Pattern, Block)>),
+  /// `return` with an optional value, functions return 0 otherwise.
+  Return(Option<Expr>),
+  /// An expression evaluated for its side effects, e.g. a call.
+  Expr(Expr),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum BinaryOp {
+  Add,
+  Sub,
+  Mul,
+  Div,
+  Mod,
+  Eq,
+  Ne,
+  Lt,
+  Le,
+  Gt,
+  Ge,
+  And,
+  Or,
+}
+
+impl BinaryOp {
+  pub fn from_symbol(sym: &Symbol) -> Option<Self> {
+    match sym {
+      Symbol::Plus => Some(BinaryOp::Add),
+      Symbol::Minus => Some(BinaryOp::Sub),
+      Symbol::Star => Some(BinaryOp::Mul),
+      Symbol::Slash => Some(BinaryOp::Div),
+      Symbol::Percent => Some(BinaryOp::Mod),
+      Symbol::EqualsEquals => Some(BinaryOp::Eq),
+      Symbol::BangEquals => Some(BinaryOp::Ne),
+      Symbol::Less => Some(BinaryOp::Lt),
+      Symbol::LessEquals => Some(BinaryOp::Le),
+      Symbol::Greater => Some(BinaryOp::Gt),
+      Symbol::GreaterEquals => Some(BinaryOp::Ge),
+      Symbol::AmpAmp => Some(BinaryOp::And),
+      Symbol::PipePipe => Some(BinaryOp::Or),
+      _ => None,
+    }
+  }
+
+  /// Binding power of the operator, higher binds tighter. All binary
+  /// operators are left associative.
+  pub fn precedence(&self) -> u8 {
+    match self {
+      BinaryOp::Or => 1,
+      BinaryOp::And => 2,
+      BinaryOp::Eq | BinaryOp::Ne => 3,
+      BinaryOp::Lt | BinaryOp::Le | BinaryOp::Gt | BinaryOp::Ge => 4,
+      BinaryOp::Add | BinaryOp::Sub => 5,
+      BinaryOp::Mul | BinaryOp::Div | BinaryOp::Mod => 6,
+    }
+  }
+}
+
+impl fmt::Display for BinaryOp {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      BinaryOp::Add => write!(f, "+"),
+      BinaryOp::Sub => write!(f, "-"),
+      BinaryOp::Mul => write!(f, "*"),
+      BinaryOp::Div => write!(f, "/"),
+      BinaryOp::Mod => write!(f, "%"),
+      BinaryOp::Eq => write!(f, "=="),
+      BinaryOp::Ne => write!(f, "!="),
+      BinaryOp::Lt => write!(f, "<"),
+      BinaryOp::Le => write!(f, "<="),
+      BinaryOp::Gt => write!(f, ">"),
+      BinaryOp::Ge => write!(f, ">="),
+      BinaryOp::And => write!(f, "&&"),
+      BinaryOp::Or => write!(f, "||"),
+    }
+  }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum UnaryOp {
+  Neg,
+  Not,
+}
+
+impl fmt::Display for UnaryOp {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      UnaryOp::Neg => write!(f, "-"),
+      UnaryOp::Not => write!(f, "!"),
+    }
+  }
+}
+
+/// Functions provided by the compiler rather than declared in the program.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Builtin {
+  /// `print(x)` writes a string, integer or bool to stdout and gives the
+  /// number of bytes written.
+  Print,
+  /// `println(x)` is `print(x)` followed by a line break, `println()` only
+  /// writes the line break.
+  Println,
+}
+
+impl Builtin {
+  pub fn from_name(name: &str) -> Option<Self> {
+    match name {
+      "print" => Some(Builtin::Print),
+      "println" => Some(Builtin::Println),
+      _ => None,
+    }
+  }
+
+  /// Whether the builtin can be called with `argc` arguments.
+  pub fn takes(&self, argc: usize) -> bool {
+    match self {
+      Builtin::Print => argc == 1,
+      Builtin::Println => argc <= 1,
+    }
+  }
+}
+
+impl fmt::Display for Builtin {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Builtin::Print => write!(f, "print"),
+      Builtin::Println => write!(f, "println"),
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expr {
+  pub kind: ExprKind,
+  pub span: Span,
+  /// Type of the value, filled in by the type checker.
+  pub ty:   Type,
+}
+
+impl Expr {
+  pub fn new(kind: ExprKind, span: Span) -> Self {
+    Self {
+      kind,
+      span,
+      ty: Type::I64,
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExprKind {
+  Literal(Literal),
+  Identifier(VarRef),
+  Binary(BinaryOp, Box<Expr>, Box<Expr>),
+  Unary(UnaryOp, Box<Expr>),
+  Call(String, Vec<Expr>),
+  Builtin(Builtin, Vec<Expr>),
+  /// `if cond { then } else { other }`, the value of the branch taken.
+  If(Box<Expr>, Box<Expr>, Box<Expr>),
+  /// `[a, b, c]`, built in the hidden variable that stores it.
+  Array(VarRef, Vec<Expr>),
+  /// `array[index]`
+  Index(Box<Expr>, Box<Expr>),
+  /// `Name { field: value, ... }` with the fields in the order written, built
+  /// in the hidden variable that stores it.
+  Struct(VarRef, Rc<Struct>, Vec<(String, Expr)>),
+  /// `value.field`
+  Field(Box<Expr>, String),
+  /// `Enum::Variant(a, b)`, the variant at the index with its values, built in
+  /// the hidden variable that stores it.
+  Variant(VarRef, Rc<Enum>, usize, Vec<Expr>),
+  /// `match value { pattern => expr, ... }`, the value of the first arm whose
+  /// pattern matches.
+  Match(Box<Expr>, Vec<(Pattern, Expr)>),
+  /// `&place`, the address of a variable, element, field or dereferenced
+  /// pointer.
+  Ref(Box<Expr>),
+  /// `*pointer`, the value the pointer points to.
+  Deref(Box<Expr>),
+  /// `value as type`, the value converted to the integer type.
+  Cast(Box<Expr>, Type),
+}
+
+impl ExprKind {
+  /// Whether the expression names a place in memory, which can be assigned to
+  /// and has an address, rather than a temporary value.
+  pub fn is_place(&self) -> bool {
+    match self {
+      ExprKind::Identifier(_) | ExprKind::Deref(_) => true,
+      ExprKind::Index(inner, _) | ExprKind::Field(inner, _) => inner.kind.is_place(),
+      _ => false,
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pattern {
+  pub kind: PatternKind,
+  pub span: Span,
 }
 
 #[derive(Debug, Clone, PartialEq)]
-pub enum Expr {
+pub enum PatternKind {
+  /// `_`, matches anything.
+  Wildcard,
+  /// An integer or bool literal, matches values equal to it.
   Literal(Literal),
-  Identifier(String),
+  /// `Enum::Variant(a, _)`, matches the variant at the index and binds its
+  /// values to the variables, or to nothing for `_`.
+  Variant(Rc<Enum>, usize, Vec<Option<VarRef>>),
 }
@@ -0,0 +1,720 @@
+use std::rc::Rc;
+
+use super::*;
+
+/// Smallest and largest value of the integer type `ty`.
+fn range(ty: &Type) -> (i128, i128) {
+  let bits = 8 * ty.size() as u32;
+  if ty.is_signed() {
+    (-(1 << (bits - 1)), (1 << (bits - 1)) - 1)
+  } else {
+    (0, (1 << bits) - 1)
+  }
+}
+
+/// Whether `expr` is built only from literals, e.g. `2 * 3`. Like a literal,
+/// it takes the type of the other operand rather than giving it its own.
+fn is_untyped(expr: &Expr) -> bool {
+  match &expr.kind {
+    ExprKind::Literal(_) => true,
+    ExprKind::Unary(_, inner) => is_untyped(inner),
+    ExprKind::Binary(
+      BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul | BinaryOp::Div | BinaryOp::Mod,
+      lhs,
+      rhs,
+    ) => is_untyped(lhs) && is_untyped(rhs),
+    _ => false,
+  }
+}
+
+/// Semantic analysis pass run on a parsed [`Program`]: infers the type of every
+/// expression and unannotated variable and reports mismatches, then lays the
+/// variables out in their frames now that their sizes are known.
+///
+/// Integer literals, and expressions built only from them, have no type of
+/// their own, they take the type expected where they're used, e.g. the other
+/// operand's or the annotated variable's, and default to `i64`.
+pub struct TypeChecker {
+  program: Program,
+  /// Return type of the function being checked.
+  ret:     Option<Type>,
+  errors:  Vec<TypeError>,
+}
+
+impl TypeChecker {
+  pub fn new(program: Program) -> Self {
+    Self {
+      program,
+      ret: None,
+      errors: Vec::new(),
+    }
+  }
+
+  /// Checks that `expr` has type `ty`.
+  fn expect(&mut self, expr: &mut Expr, ty: Type) {
+    let found = self.check_expr(expr, Some(ty.clone()));
+    if found != ty {
+      self.errors.push(TypeError::new(
+        expr.span,
+        &format!("Mismatched types: expected {}, found {}", ty, found),
+      ));
+    }
+  }
+
+  /// Checks an operand used as a truth value, which must be a bool or an
+  /// integer.
+  fn check_condition(&mut self, expr: &mut Expr) {
+    let ty = self.check_expr(expr, None);
+    if !ty.is_integer() && ty != Type::Bool {
+      self.errors.push(TypeError::new(
+        expr.span,
+        &format!("Expected bool or integer, found {}", ty),
+      ));
+    }
+  }
+
+  fn check_literal(&mut self, lit: &Literal, span: Span, hint: Option<Type>) -> Type {
+    let (val, ty) = match *lit {
+      Literal::Integer(i) => (i as i128, Type::I64),
+      Literal::Unsigned(u) => (u as i128, Type::U64),
+      Literal::String(_) => return Type::Str,
+      Literal::Bool(_) => return Type::Bool,
+    };
+    let ty = match hint {
+      Some(hint) if hint.is_integer() => hint,
+      _ => ty,
+    };
+
+    let (min, max) = range(&ty);
+    if val < min || val > max {
+      self.errors.push(
+        TypeError::new(span, &format!("Integer literal out of range for {}", ty))
+          .with_note(&format!("the range of {} is {}..={}", ty, min, max)),
+      );
+    }
+
+    ty
+  }
+
+  fn check_binary(
+    &mut self,
+    op: BinaryOp,
+    lhs: &mut Expr,
+    rhs: &mut Expr,
+    hint: Option<Type>,
+  ) -> Type {
+    if matches!(op, BinaryOp::And | BinaryOp::Or) {
+      self.check_condition(lhs);
+      self.check_condition(rhs);
+      return Type::Bool;
+    }
+
+    let arithmetic = matches!(
+      op,
+      BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul | BinaryOp::Div | BinaryOp::Mod
+    );
+    let hint = if arithmetic { hint } else { None };
+
+    // check the typed operand first so literals on the left take its type
+    let (lty, rty) = if is_untyped(lhs) && !is_untyped(rhs) {
+      let rty = self.check_expr(rhs, hint);
+      (self.check_expr(lhs, Some(rty.clone())), rty)
+    } else {
+      let lty = self.check_expr(lhs, hint);
+      (lty.clone(), self.check_expr(rhs, Some(lty)))
+    };
+
+    let span = lhs.span.to(rhs.span);
+    if let Type::Pointer(_) = lty {
+      return self.check_pointer_binary(op, &lty, &rty, span);
+    }
+    if lty != rty {
+      self.errors.push(
+        TypeError::new(
+          span,
+          &format!("Cannot apply '{}' to {} and {}", op, lty, rty),
+        )
+        .with_note("both operands must have the same type"),
+      );
+    } else if !lty.is_integer() && !(lty == Type::Bool && matches!(op, BinaryOp::Eq | BinaryOp::Ne))
+    {
+      self.errors.push(TypeError::new(
+        span,
+        &format!("Cannot apply '{}' to {}", op, lty),
+      ));
+    }
+
+    if arithmetic {
+      lty
+    } else {
+      Type::Bool
+    }
+  }
+
+  /// Checks an operation with a pointer on the left. Adding or subtracting an
+  /// integer moves the pointer by that many values of the type it points to,
+  /// and pointers of the same type can be compared or subtracted, giving the
+  /// number of values between them.
+  fn check_pointer_binary(&mut self, op: BinaryOp, lty: &Type, rty: &Type, span: Span) -> Type {
+    let Type::Pointer(elem) = lty else {
+      unreachable!("pointer operation on {}", lty)
+    };
+    let ty = match op {
+      BinaryOp::Add | BinaryOp::Sub if rty.is_integer() => lty.clone(),
+      BinaryOp::Sub if rty == lty => Type::I64,
+      BinaryOp::Eq | BinaryOp::Ne | BinaryOp::Lt | BinaryOp::Le | BinaryOp::Gt | BinaryOp::Ge
+        if rty == lty =>
+      {
+        return Type::Bool;
+      },
+      BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul | BinaryOp::Div | BinaryOp::Mod => {
+        self.errors.push(
+          TypeError::new(
+            span,
+            &format!("Cannot apply '{}' to {} and {}", op, lty, rty),
+          )
+          .with_note("an integer can be added to or subtracted from a pointer"),
+        );
+        return lty.clone();
+      },
+      _ => {
+        self.errors.push(
+          TypeError::new(
+            span,
+            &format!("Cannot apply '{}' to {} and {}", op, lty, rty),
+          )
+          .with_note("both operands must have the same type"),
+        );
+        return Type::Bool;
+      },
+    };
+
+    if elem.size() == 0 {
+      self.errors.push(
+        TypeError::new(span, &format!("Cannot apply '{}' to {}", op, lty))
+          .with_note(&format!("values of type {} take no bytes", elem)),
+      );
+    }
+    ty
+  }
+
+  /// Checks `&expr` takes the address of a place, not of a temporary value.
+  fn check_ref(&mut self, expr: &mut Expr) -> Type {
+    let ty = self.check_expr(expr, None);
+    if !expr.kind.is_place() {
+      self.errors.push(
+        TypeError::new(expr.span, "Cannot take the address of a temporary value")
+          .with_note("variables, elements, fields and dereferenced pointers have an address"),
+      );
+    }
+    Type::Pointer(Box::new(ty))
+  }
+
+  /// Checks `*expr` dereferences a pointer and gives the type it points to.
+  fn check_deref(&mut self, expr: &mut Expr, span: Span) -> Type {
+    match self.check_expr(expr, None) {
+      Type::Pointer(ty) => *ty,
+      ty => {
+        self.errors.push(TypeError::new(
+          span,
+          &format!("Cannot dereference a value of type {}", ty),
+        ));
+        Type::I64
+      },
+    }
+  }
+
+  /// Checks both branches of a conditional expression have the same type, the
+  /// type of the whole expression.
+  fn check_conditional(
+    &mut self,
+    cond: &mut Expr,
+    then: &mut Expr,
+    other: &mut Expr,
+    hint: Option<Type>,
+  ) -> Type {
+    self.check_condition(cond);
+
+    // a literal branch takes the type of the other one, as with operands
+    let (then_ty, other_ty) = if is_untyped(then) && !is_untyped(other) {
+      let other_ty = self.check_expr(other, hint);
+      (self.check_expr(then, Some(other_ty.clone())), other_ty)
+    } else {
+      let then_ty = self.check_expr(then, hint);
+      (then_ty.clone(), self.check_expr(other, Some(then_ty)))
+    };
+
+    if then_ty != other_ty {
+      self.errors.push(
+        TypeError::new(
+          other.span,
+          &format!("Mismatched types: expected {}, found {}", then_ty, other_ty),
+        )
+        .with_note("both branches of a conditional expression must have the same type"),
+      );
+    }
+
+    then_ty
+  }
+
+  /// Checks the elements of an array literal have the same type, the element
+  /// type of `hint` if it's an array, and gives the hidden variable the array
+  /// is built in its type.
+  fn check_array(
+    &mut self,
+    var: &VarRef,
+    elems: &mut [Expr],
+    span: Span,
+    hint: Option<Type>,
+  ) -> Type {
+    let hint = match hint {
+      Some(Type::Array(elem, _)) => Some(*elem),
+      _ => None,
+    };
+
+    // a typed element gives its type to the literals around it
+    let first = elems
+      .iter()
+      .position(|expr| !matches!(expr.kind, ExprKind::Literal(_)))
+      .unwrap_or(0);
+    let elem = match (elems.get_mut(first), hint) {
+      (Some(expr), hint) => self.check_expr(expr, hint),
+      (None, Some(hint)) => hint,
+      (None, None) => {
+        self.errors.push(
+          TypeError::new(span, "Cannot infer the type of an empty array")
+            .with_help("annotate the variable, e.g. 'a: [i64; 0] := []'"),
+        );
+        Type::I64
+      },
+    };
+    for (i, expr) in elems.iter_mut().enumerate() {
+      if i != first {
+        self.expect(expr, elem.clone());
+      }
+    }
+
+    let ty = Type::Array(Box::new(elem), elems.len());
+    self.program.entry_mut(var).ty = ty.clone();
+    ty
+  }
+
+  /// Checks `array[index]` and gives the type of the element. Constant indexes
+  /// are checked against the length here, others when the program runs.
+  fn check_index(&mut self, array: &mut Expr, index: &mut Expr) -> Type {
+    let ty = self.check_expr(array, None);
+    let index_ty = self.check_expr(index, None);
+    if !index_ty.is_integer() {
+      self.errors.push(TypeError::new(
+        index.span,
+        &format!("Expected an integer index, found {}", index_ty),
+      ));
+    }
+
+    let Type::Array(elem, len) = ty else {
+      self.errors.push(TypeError::new(
+        array.span,
+        &format!("Cannot index a value of type {}", ty),
+      ));
+      return Type::I64;
+    };
+
+    let out_of_bounds = match index.kind {
+      ExprKind::Literal(Literal::Integer(i)) => usize::try_from(i).map_or(true, |i| i >= len),
+      ExprKind::Literal(Literal::Unsigned(_)) => true,
+      _ => false,
+    };
+    if out_of_bounds {
+      self.errors.push(
+        TypeError::new(
+          index.span,
+          &format!("Index out of bounds for {}", Type::Array(elem.clone(), len)),
+        )
+        .with_note(&format!("the array has {} elements", len)),
+      );
+    }
+
+    *elem
+  }
+
+  /// Checks every field of the struct `def` is given once, with a value of
+  /// its type.
+  fn check_struct(&mut self, def: &Rc<Struct>, fields: &mut [(String, Expr)], span: Span) -> Type {
+    let mut given: Vec<String> = Vec::new();
+    for (name, expr) in fields.iter_mut() {
+      let Some(field) = def.field(name) else {
+        self.check_expr(expr, None);
+        self.errors.push(TypeError::new(
+          expr.span,
+          &format!("Struct {} has no field '{}'", def.name, name),
+        ));
+        continue;
+      };
+      if given.contains(name) {
+        self.errors.push(TypeError::new(
+          expr.span,
+          &format!("Field '{}' given twice", name),
+        ));
+      }
+      given.push(name.clone());
+      self.expect(expr, field.ty.clone());
+    }
+
+    for field in def.fields().iter() {
+      if !given.contains(&field.name) {
+        self.errors.push(TypeError::new(
+          span,
+          &format!("Missing field '{}' of {}", field.name, def.name),
+        ));
+      }
+    }
+
+    Type::Struct(def.clone())
+  }
+
+  /// Checks `expr.field` and gives the type of the field.
+  fn check_field(&mut self, expr: &mut Expr, name: &str, span: Span) -> Type {
+    let ty = self.check_expr(expr, None);
+    let field = match &ty {
+      Type::Struct(def) => def.field(name),
+      _ => None,
+    };
+    match field {
+      Some(field) => field.ty.clone(),
+      None => {
+        self.errors.push(TypeError::new(
+          span,
+          &format!("No field '{}' on type {}", name, ty),
+        ));
+        Type::I64
+      },
+    }
+  }
+
+  /// Checks the values of the variant at `index` have the types it holds.
+  fn check_variant(&mut self, def: &Rc<Enum>, index: usize, values: &mut [Expr]) -> Type {
+    let fields = def.variants[index].fields.clone();
+    for (expr, ty) in values.iter_mut().zip(fields) {
+      self.expect(expr, ty);
+    }
+    Type::Enum(def.clone())
+  }
+
+  /// Checks the patterns of a match on `value` have its type, reporting arms
+  /// that can never be reached because earlier ones match everything they
+  /// would, and values no arm matches.
+  fn check_patterns<'a>(&mut self, value: &mut Expr, patterns: impl Iterator<Item = &'a Pattern>) {
+    let ty = self.check_expr(value, None);
+    if !ty.is_integer() && !matches!(ty, Type::Bool | Type::Enum(_)) {
+      self.errors.push(TypeError::new(
+        value.span,
+        &format!("Cannot match on a value of type {}", ty),
+      ));
+      return;
+    }
+
+    // how many values the type has, once they're all matched only `_` is left
+    let count = match &ty {
+      Type::Enum(def) => def.variants.len() as i128,
+      Type::Bool => 2,
+      _ => {
+        let (min, max) = range(&ty);
+        max - min + 1
+      },
+    };
+    let mut wildcard = false;
+    // tags of the variants or values of the literals matched so far
+    let mut matched: Vec<i64> = Vec::new();
+    for pattern in patterns {
+      let key = match &pattern.kind {
+        PatternKind::Wildcard => None,
+        PatternKind::Literal(lit) => {
+          let found = self.check_literal(lit, pattern.span, Some(ty.clone()));
+          if found != ty {
+            self.errors.push(TypeError::new(
+              pattern.span,
+              &format!("Mismatched types: expected {}, found {}", ty, found),
+            ));
+            continue;
+          }
+          Some(match *lit {
+            Literal::Integer(i) => i,
+            Literal::Unsigned(u) => u as i64,
+            Literal::Bool(b) => b as i64,
+            Literal::String(_) => unreachable!("string pattern"),
+          })
+        },
+        PatternKind::Variant(def, index, _) => {
+          let found = Type::Enum(def.clone());
+          if found != ty {
+            self.errors.push(TypeError::new(
+              pattern.span,
+              &format!("Mismatched types: expected {}, found {}", ty, found),
+            ));
+            continue;
+          }
+          Some(*index as i64)
+        },
+      };
+
+      if wildcard || matched.len() as i128 == count || key.is_some_and(|key| matched.contains(&key))
+      {
+        self.errors.push(
+          TypeError::new(pattern.span, "Unreachable pattern")
+            .with_note("the arms above already match every value it does"),
+        );
+      }
+      match key {
+        Some(key) if !matched.contains(&key) => matched.push(key),
+        Some(_) => (),
+        None => wildcard = true,
+      }
+    }
+    if wildcard {
+      return;
+    }
+
+    let missing = match &ty {
+      Type::Enum(def) => (0..def.variants.len())
+        .filter(|i| !matched.contains(&(*i as i64)))
+        .map(|i| def.path(i))
+        .collect::<Vec<_>>(),
+      Type::Bool => [false, true]
+        .into_iter()
+        .filter(|b| !matched.contains(&(*b as i64)))
+        .map(|b| b.to_string())
+        .collect(),
+      _ => {
+        if matched.len() as i128 == count {
+          return;
+        }
+        self.errors.push(
+          TypeError::new(value.span, &format!("Non-exhaustive match on {}", ty))
+            .with_help("add a '_' arm for the values not listed"),
+        );
+        return;
+      },
+    };
+    if !missing.is_empty() {
+      self.errors.push(
+        TypeError::new(
+          value.span,
+          &format!("Non-exhaustive match, {} not covered", missing.join(", ")),
+        )
+        .with_help("add an arm for each of them or a '_' arm"),
+      );
+    }
+  }
+
+  /// Checks a match used as a value, whose arms must all have the same type,
+  /// the type of the whole expression.
+  fn check_match(
+    &mut self,
+    value: &mut Expr,
+    arms: &mut [(Pattern, Expr)],
+    hint: Option<Type>,
+  ) -> Type {
+    self.check_patterns(value, arms.iter().map(|(pattern, _)| pattern));
+
+    // a typed arm gives its type to the literal ones, as with array elements
+    let first = arms
+      .iter()
+      .position(|(_, expr)| !matches!(expr.kind, ExprKind::Literal(_)))
+      .unwrap_or(0);
+    let Some((_, expr)) = arms.get_mut(first) else {
+      return hint.unwrap_or(Type::I64);
+    };
+    let ty = self.check_expr(expr, hint);
+    for (i, (_, expr)) in arms.iter_mut().enumerate() {
+      if i == first {
+        continue;
+      }
+      let found = self.check_expr(expr, Some(ty.clone()));
+      if found != ty {
+        self.errors.push(
+          TypeError::new(
+            expr.span,
+            &format!("Mismatched types: expected {}, found {}", ty, found),
+          )
+          .with_note("every arm of a match expression must have the same type"),
+        );
+      }
+    }
+
+    ty
+  }
+
+  /// Infers the type of `expr`, using `hint` as the type of untyped literals,
+  /// and records it in the expression.
+  fn check_expr(&mut self, expr: &mut Expr, hint: Option<Type>) -> Type {
+    let ty = match &mut expr.kind {
+      ExprKind::Literal(lit) => self.check_literal(lit, expr.span, hint),
+      ExprKind::Identifier(var) => self.program.entry(var).ty.clone(),
+      ExprKind::Binary(op, lhs, rhs) => self.check_binary(*op, lhs, rhs, hint),
+      ExprKind::Unary(UnaryOp::Neg, operand) => {
+        let ty = self.check_expr(operand, hint);
+        if !ty.is_signed() {
+          self.errors.push(TypeError::new(
+            expr.span,
+            &format!("Cannot negate a value of type {}", ty),
+          ));
+        }
+        ty
+      },
+      ExprKind::Unary(UnaryOp::Not, operand) => {
+        self.check_condition(operand);
+        Type::Bool
+      },
+      ExprKind::Call(name, args) => {
+        let function = self.program.find_function(name).unwrap();
+        let params = function
+          .params
+          .iter()
+          .map(|(_, ty)| ty.clone())
+          .collect::<Vec<_>>();
+        let ret = function.ret.clone();
+
+        for (arg, ty) in args.iter_mut().zip(params) {
+          self.expect(arg, ty);
+        }
+        ret
+      },
+      ExprKind::If(cond, then, other) => self.check_conditional(cond, then, other, hint),
+      ExprKind::Array(var, elems) => self.check_array(var, elems, expr.span, hint),
+      ExprKind::Index(array, index) => self.check_index(array, index),
+      ExprKind::Struct(_, def, fields) => self.check_struct(def, fields, expr.span),
+      ExprKind::Field(inner, name) => self.check_field(inner, name, expr.span),
+      ExprKind::Variant(_, def, index, values) => self.check_variant(def, *index, values),
+      ExprKind::Match(value, arms) => self.check_match(value, arms, hint),
+      ExprKind::Ref(inner) => self.check_ref(inner),
+      ExprKind::Deref(inner) => self.check_deref(inner, expr.span),
+      ExprKind::Cast(inner, ty) => {
+        let from = self.check_expr(inner, None);
+        if !(from.is_integer() || from == Type::Bool) || !ty.is_integer() {
+          self.errors.push(TypeError::new(
+            expr.span,
+            &format!("Cannot convert {} to {}", from, ty),
+          ));
+        }
+        ty.clone()
+      },
+      ExprKind::Builtin(Builtin::Print | Builtin::Println, args) => {
+        for arg in args.iter_mut() {
+          let ty = self.check_expr(arg, None);
+          if !ty.is_scalar() || matches!(ty, Type::Pointer(_)) {
+            self.errors.push(TypeError::new(
+              arg.span,
+              &format!("Cannot print a value of type {}", ty),
+            ));
+          }
+        }
+        Type::I64
+      },
+    };
+
+    expr.ty = ty.clone();
+    ty
+  }
+
+  fn check_block(&mut self, block: &mut Block) {
+    for stmt in block.stmts.iter_mut() {
+      self.check_stmt(stmt);
+    }
+  }
+
+  fn check_stmt(&mut self, stmt: &mut Stmt) {
+    match stmt {
+      Stmt::Exit(expr) => {
+        let ty = self.check_expr(expr, None);
+        if !ty.is_integer() {
+          self.errors.push(TypeError::new(
+            expr.span,
+            &format!("'exit' expects an integer, found {}", ty),
+          ));
+        }
+      },
+      Stmt::VarDecl(var, ty, expr) => match ty {
+        Some(ty) => self.expect(expr, ty.clone()),
+        None => {
+          let ty = self.check_expr(expr, None);
+          self.program.entry_mut(var).ty = ty;
+        },
+      },
+      Stmt::VarAssign(var, expr) => {
+        let ty = self.program.entry(var).ty.clone();
+        self.expect(expr, ty);
+      },
+      Stmt::Assign(place, expr) => {
+        let ty = self.check_expr(place, None);
+        if !place.kind.is_place() {
+          self.errors.push(
+            TypeError::new(place.span, "Cannot assign to a temporary value").with_note(
+              "variables, elements, fields and dereferenced pointers can be assigned to",
+            ),
+          );
+        }
+        self.expect(expr, ty);
+      },
+      Stmt::If(branches, else_block) => {
+        for (cond, block) in branches.iter_mut() {
+          self.check_condition(cond);
+          self.check_block(block);
+        }
+        if let Some(block) = else_block {
+          self.check_block(block);
+        }
+      },
+      Stmt::While(cond, block) => {
+        self.check_condition(cond);
+        self.check_block(block);
+      },
+      Stmt::Match(value, arms) => {
+        self.check_patterns(value, arms.iter().map(|(pattern, _)| pattern));
+        for (_, block) in arms.iter_mut() {
+          self.check_block(block);
+        }
+      },
+      Stmt::Return(expr) => {
+        if let (Some(expr), Some(ret)) = (expr, self.ret.clone()) {
+          self.expect(expr, ret);
+        }
+      },
+      Stmt::Expr(expr) => {
+        self.check_expr(expr, None);
+      },
+    }
+  }
+
+  /// Checks the whole program and returns it with every type filled in and its
+  /// frames laid out, or every type error found.
+  pub fn check(&mut self) -> Result<Program, Vec<TypeError>> {
+    let mut stmts = std::mem::take(&mut self.program.stmts);
+    for stmt in stmts.iter_mut() {
+      self.check_stmt(stmt);
+    }
+    self.program.stmts = stmts;
+
+    for i in 0..self.program.functions.len() {
+      // calls only need the signature, which stays in place
+      let function = &mut self.program.functions[i];
+      let mut stmts = std::mem::take(&mut function.body.stmts);
+      self.ret = Some(function.ret.clone());
+
+      for stmt in stmts.iter_mut() {
+        self.check_stmt(stmt);
+      }
+
+      self.program.functions[i].body.stmts = stmts;
+    }
+    self.ret = None;
+
+    if !self.errors.is_empty() {
+      self.errors.sort_by_key(|err| err.span().start);
+      return Err(std::mem::take(&mut self.errors));
+    }
+
+    self.program.layout();
+
+    Ok(self.program.clone())
+  }
+}
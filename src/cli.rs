@@ -0,0 +1,263 @@
+use pecan::{Emit, OptLevel};
+
+pub const USAGE: &str = "Usage: pn <command> [options] <file>";
+
+pub const HELP: &str = "Usage: pn <command> [options] <file>
+
+Commands:
+  build            Compile to an x86_64 linux executable
+  run              Compile and run the program
+  check            Check the program for errors without compiling it
+  fmt              Format the file in place
+
+Options:
+  -o <file>        Output file name, '-' for stdout. Defaults to the file name
+                   with the extension of what is emitted
+  --emit <kind>    Stop after a stage and write its output: tokens, ast,
+                   symbols, ir, asm, obj or exe (default)
+  -O0, -O1, -O2    Optimization level: none, registers and simple cleanups
+                   (default), or propagating constants across branches too
+  --keep-temps     Keep the intermediate assembly and object files
+  --nasm           Assemble and link with nasm and ld instead of writing the
+                   executable directly
+  -i, --interpret  Run the program with the interpreter instead of compiling it
+  --check          Only check that the file is formatted (fmt)
+  -v, --verbose    Print each step and the commands run
+  -h, --help       Print this help message
+
+'pn -c <file>' and 'pn -i <file>' are short for 'pn build <file>' and
+'pn run -i <file>'.";
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Command {
+  Build,
+  Run,
+  Check,
+  Fmt,
+}
+
+impl Command {
+  fn from_name(name: &str) -> Option<Self> {
+    match name {
+      "build" => Some(Command::Build),
+      "run" => Some(Command::Run),
+      "check" => Some(Command::Check),
+      "fmt" => Some(Command::Fmt),
+      _ => None,
+    }
+  }
+}
+
+/// Command line options, in any order around the command and the file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Options {
+  pub command:    Command,
+  pub file:       String,
+  pub out:        Option<String>,
+  pub emit:       Emit,
+  pub opt_level:  OptLevel,
+  pub keep_temps: bool,
+  pub nasm:       bool,
+  pub interpret:  bool,
+  /// `fmt --check`
+  pub check:      bool,
+  pub verbose:    bool,
+  pub help:       bool,
+}
+
+impl Options {
+  /// Parses the arguments following the program name.
+  pub fn parse(args: &[String]) -> Result<Self, String> {
+    let mut command = None;
+    let mut file = None;
+    let mut out = None;
+    let mut emit = None;
+    let mut opt_level = None;
+    let mut keep_temps = false;
+    let mut nasm = false;
+    let mut interpret = false;
+    let mut check = false;
+    let mut verbose = false;
+    let mut help = false;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+      // `--emit=asm` is the same as `--emit asm`
+      let (arg, inline) = match arg.split_once('=') {
+        Some((arg, value)) if arg.starts_with("--") => (arg, Some(value.to_string())),
+        _ => (arg.as_str(), None),
+      };
+      let mut value = |name: &str| match inline.clone().or_else(|| args.next().cloned()) {
+        Some(value) => Ok(value),
+        None => Err(format!("'{}' expects a value", name)),
+      };
+
+      match arg {
+        "-h" | "--help" => help = true,
+        "-v" | "--verbose" => verbose = true,
+        "--keep-temps" => keep_temps = true,
+        "--nasm" => nasm = true,
+        "--check" => check = true,
+        "-i" | "--interpret" => {
+          interpret = true;
+          command.get_or_insert(Command::Run);
+        },
+        "-c" => {
+          command.get_or_insert(Command::Build);
+        },
+        "-o" => out = Some(value(arg)?),
+        _ if arg.starts_with("-O") => match OptLevel::from_name(&arg[2..]) {
+          Some(level) => opt_level = Some(level),
+          None => {
+            return Err(format!(
+              "Unknown optimization level '{}', expected -O0, -O1 or -O2",
+              arg
+            ))
+          },
+        },
+        "--emit" => {
+          let kind = value(arg)?;
+          match Emit::from_name(&kind) {
+            Some(kind) => emit = Some(kind),
+            None => {
+              return Err(format!(
+                "Unknown --emit kind '{}', expected tokens, ast, symbols, ir, asm, obj or exe",
+                kind
+              ))
+            },
+          }
+        },
+        _ if arg.starts_with('-') && arg.len() > 1 => {
+          return Err(format!("Unknown option '{}'", arg));
+        },
+        _ if command.is_none() && file.is_none() => match Command::from_name(arg) {
+          Some(cmd) => command = Some(cmd),
+          None => return Err(format!("Unknown command '{}'", arg)),
+        },
+        _ if file.is_none() => file = Some(arg.to_string()),
+        _ => return Err(format!("Unexpected argument '{}'", arg)),
+      }
+    }
+
+    let command = match command {
+      Some(command) => command,
+      None if help => Command::Build,
+      None => return Err("Missing command".to_string()),
+    };
+
+    let file = match file {
+      Some(file) => file,
+      None if help => String::new(),
+      None => return Err("Missing input file".to_string()),
+    };
+
+    if out.is_some() && command != Command::Build {
+      return Err("'-o' is only valid for build".to_string());
+    }
+    if emit.is_some() && command != Command::Build {
+      return Err("'--emit' is only valid for build".to_string());
+    }
+    if opt_level.is_some() && (interpret || !matches!(command, Command::Build | Command::Run)) {
+      return Err("'-O' is only valid for build and run".to_string());
+    }
+    if interpret && command != Command::Run {
+      return Err("'--interpret' is only valid for run".to_string());
+    }
+    if check && command != Command::Fmt {
+      return Err("'--check' is only valid for fmt".to_string());
+    }
+
+    Ok(Self {
+      command,
+      file,
+      out,
+      emit: emit.unwrap_or(Emit::Exe),
+      opt_level: opt_level.unwrap_or_default(),
+      keep_temps,
+      nasm,
+      interpret,
+      check,
+      verbose,
+      help,
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn parse(args: &str) -> Result<Options, String> {
+    let args = args
+      .split_whitespace()
+      .map(String::from)
+      .collect::<Vec<_>>();
+    Options::parse(&args)
+  }
+
+  #[test]
+  fn options_go_anywhere_around_the_command_and_file() {
+    let expected = parse("build --emit asm -O2 -o out.asm prog.pn").unwrap();
+
+    assert_eq!(expected.command, Command::Build);
+    assert_eq!(expected.file, "prog.pn");
+    assert_eq!(expected.out.as_deref(), Some("out.asm"));
+    assert_eq!(expected.emit, Emit::Asm);
+    assert_eq!(expected.opt_level, OptLevel::O2);
+    for args in [
+      "-O2 build prog.pn --emit=asm -o out.asm",
+      "--emit=asm -o out.asm build -O2 prog.pn",
+      "build prog.pn -o out.asm -O2 --emit asm",
+    ] {
+      assert_eq!(parse(args), Ok(expected.clone()), "{}", args);
+    }
+  }
+
+  #[test]
+  fn short_flags_imply_their_command() {
+    assert_eq!(parse("-c prog.pn"), parse("build prog.pn"));
+    assert_eq!(parse("-i prog.pn"), parse("run -i prog.pn"));
+    assert!(parse("-i prog.pn").unwrap().interpret);
+  }
+
+  #[test]
+  fn misuse_is_reported() {
+    let error = |args| parse(args).unwrap_err();
+
+    assert_eq!(error("-v"), "Missing command");
+    assert_eq!(error("build"), "Missing input file");
+    assert_eq!(error("compile prog.pn"), "Unknown command 'compile'");
+    assert_eq!(
+      error("build prog.pn other.pn"),
+      "Unexpected argument 'other.pn'"
+    );
+    assert_eq!(error("build prog.pn --fast"), "Unknown option '--fast'");
+    assert_eq!(error("build prog.pn -o"), "'-o' expects a value");
+    assert_eq!(error("build prog.pn --emit"), "'--emit' expects a value");
+    assert!(error("build prog.pn --emit=elf").starts_with("Unknown --emit kind 'elf'"));
+    assert!(error("build prog.pn -O3").starts_with("Unknown optimization level '-O3'"));
+    assert_eq!(error("run prog.pn -o out"), "'-o' is only valid for build");
+    assert_eq!(
+      error("check prog.pn --emit ir"),
+      "'--emit' is only valid for build"
+    );
+    assert_eq!(
+      error("run -i -O1 prog.pn"),
+      "'-O' is only valid for build and run"
+    );
+    assert_eq!(
+      error("build -i prog.pn"),
+      "'--interpret' is only valid for run"
+    );
+    assert_eq!(
+      error("build --check prog.pn"),
+      "'--check' is only valid for fmt"
+    );
+  }
+
+  #[test]
+  fn help_needs_no_command_or_file() {
+    assert!(parse("--help").unwrap().help);
+    assert!(parse("run -h").unwrap().help);
+  }
+}
@@ -1,94 +1,987 @@
 use super::*;
 
+/// Memory operand for a variable at `offset` below `rbp`.
+pub fn slot(offset: isize) -> String {
+  if offset < 0 {
+    format!("[rbp + {}]", -offset)
+  } else {
+    format!("[rbp - {}]", offset)
+  }
+}
+
+/// Loads the value of type `ty` at the memory operand `mem` into `rax`,
+/// extended to 64 bits.
+fn load(ty: &Type, mem: &str) -> String {
+  match ty {
+    Type::I8 => format!("  movsx rax, byte {}\n", mem),
+    Type::U8 | Type::Bool => format!("  movzx eax, byte {}\n", mem),
+    Type::I16 => format!("  movsx rax, word {}\n", mem),
+    Type::U16 => format!("  movzx eax, word {}\n", mem),
+    Type::I32 => format!("  movsxd rax, dword {}\n", mem),
+    Type::U32 => format!("  mov eax, dword {}\n", mem),
+    Type::I64 | Type::U64 | Type::Str | Type::Pointer(_) => format!("  mov rax, qword {}\n", mem),
+    Type::Array(..) | Type::Struct(_) | Type::Enum(_) => {
+      unreachable!("arrays, structs and enums are copied by address")
+    },
+  }
+}
+
+/// Stores the low bytes of `rax` as a value of type `ty` at the memory operand
+/// `mem`.
+fn store(ty: &Type, mem: &str) -> String {
+  let reg = match ty.size() {
+    1 => "al",
+    2 => "ax",
+    4 => "eax",
+    _ => "rax",
+  };
+  format!("  mov {}, {}\n", mem, reg)
+}
+
+/// Truncates `rax` to the width of `ty` and extends it back to 64 bits, see
+/// [`Type::wrap`].
+fn wrap(ty: &Type) -> &'static str {
+  match ty {
+    Type::I8 => "  movsx rax, al\n",
+    Type::U8 => "  movzx eax, al\n",
+    Type::I16 => "  movsx rax, ax\n",
+    Type::U16 => "  movzx eax, ax\n",
+    Type::I32 => "  movsxd rax, eax\n",
+    Type::U32 => "  mov eax, eax\n",
+    Type::I64
+    | Type::U64
+    | Type::Bool
+    | Type::Str
+    | Type::Array(..)
+    | Type::Struct(_)
+    | Type::Enum(_)
+    | Type::Pointer(_) => "",
+  }
+}
+
+/// Routines the generated code calls to print, included if the program prints.
+/// They take their argument in `rdi`, return the number of bytes written in
+/// `rax` and clobber the caller-saved registers like any call.
+const RUNTIME: &str = "
+_print_str:
+  ; rdi: address of the length of the string, followed by its bytes
+  mov rdx, qword [rdi]
+  lea rsi, [rdi + 8]
+  mov rdi, 1
+  mov rax, 1
+  syscall
+  ret
+
+_print_int:
+  xor esi, esi
+  cmp rdi, 0
+  jge _print_dec
+  neg rdi
+  mov esi, 1
+  jmp _print_dec
+
+_print_uint:
+  xor esi, esi
+
+_print_dec:
+  ; rdi: magnitude, rsi: 1 to write a minus sign before it
+  push rbp
+  mov rbp, rsp
+  sub rsp, 32
+  mov rax, rdi
+  mov rcx, 10
+  mov r8, rbp
+_print_dec_digit:
+  ; digits are written right to left below rbp
+  xor edx, edx
+  div rcx
+  add dl, 48
+  sub r8, 1
+  mov [r8], dl
+  cmp rax, 0
+  jne _print_dec_digit
+  cmp rsi, 0
+  je _print_dec_write
+  sub r8, 1
+  mov byte [r8], 45
+_print_dec_write:
+  mov rdx, rbp
+  sub rdx, r8
+  mov rsi, r8
+  mov rdi, 1
+  mov rax, 1
+  syscall
+  mov rsp, rbp
+  pop rbp
+  ret
+";
+
+/// Message the generated code writes to stderr for `error`, the same as the
+/// interpreter's.
+fn error_message(error: &RuntimeError) -> String {
+  format!("{}\n", error)
+}
+
+/// Routine the generated code jumps to on `error`, included if the program
+/// checks for it. It reports the error under `{label}_msg` and exits.
+fn error_runtime(label: &str, error: &RuntimeError) -> String {
+  format!(
+    "
+{label}:
+  mov rdi, 2
+  lea rsi, [rel {label}_msg]
+  mov rdx, {}
+  mov rax, 1
+  syscall
+  mov rdi, {}
+  jmp _exit
+",
+    error_message(error).len(),
+    error.code()
+  )
+}
+
+/// Whether a division of `ty` by `rhs` has to check for a zero divisor and for
+/// the overflow of the smallest `i64` by -1, which would trap otherwise.
+/// Narrower signed types are divided at 64 bits, where their quotient always
+/// fits.
+fn division_checks(ty: &Type, rhs: Value) -> (bool, bool) {
+  match rhs {
+    Value::Const(rhs) => (rhs == 0, *ty == Type::I64 && rhs == -1),
+    Value::Temp(_) => (true, *ty == Type::I64),
+  }
+}
+
+/// Label of the function `name`. Characters assemblers don't accept in labels
+/// are written as `.u`, their code point in hexadecimal and a closing `.`,
+/// identifiers never contain `.` so this doesn't clash.
+fn function_label(name: &str) -> String {
+  let mut label = "pn_".to_string();
+  for c in name.chars() {
+    if c.is_ascii_alphanumeric() || c == '_' {
+      label.push(c);
+    } else {
+      label += &format!(".u{:x}.", c as u32);
+    }
+  }
+  label
+}
+
+/// Label of the string at `index` in [`Module::strings`].
+fn string_label(index: usize) -> String {
+  format!("_str{}", index)
+}
+
+/// Generates NASM assembly from a [`Module`]. Temps are kept in registers as
+/// much as possible, by linear scan, or all in the frame without
+/// [`Generator::with_registers`]. Variables are in the frame unless
+/// [`promote_locals`] made them temps.
 pub struct Generator {
-  program: Program,
-  output:  String,
+  module:    Module,
+  output:    String,
+  registers: bool,
+  /// Number of the first label of the function being generated, block `i` is
+  /// `.L{labels + i}`.
+  labels:    usize,
+  /// Where the temps of the function being generated are.
+  alloc:     Allocation,
+  /// Number of times each temp of the function being generated is read.
+  uses:      Vec<usize>,
+}
+
+/// Condition code of `set<cc>` and `j<cc>` true when `op` holds for operands
+/// of type `ty`, if it's a comparison.
+fn condition(op: BinaryOp, ty: &Type) -> Option<&'static str> {
+  let signed = ty.is_signed();
+  match op {
+    BinaryOp::Eq => Some("e"),
+    BinaryOp::Ne => Some("ne"),
+    BinaryOp::Lt => Some(if signed { "l" } else { "b" }),
+    BinaryOp::Le => Some(if signed { "le" } else { "be" }),
+    BinaryOp::Gt => Some(if signed { "g" } else { "a" }),
+    BinaryOp::Ge => Some(if signed { "ge" } else { "ae" }),
+    _ => None,
+  }
+}
+
+/// The condition code true when `cc` is false.
+fn negate(cc: &str) -> &'static str {
+  match cc {
+    "e" => "ne",
+    "ne" => "e",
+    "l" => "ge",
+    "ge" => "l",
+    "le" => "g",
+    "g" => "le",
+    "b" => "ae",
+    "ae" => "b",
+    "be" => "a",
+    "a" => "be",
+    _ => unreachable!(),
+  }
+}
+
+/// Whether `val` fits the 32-bit immediate of most instructions.
+fn is_imm32(val: i64) -> bool {
+  i32::try_from(val).is_ok()
 }
 
 impl Generator {
-  pub fn new(program: Program) -> Self {
+  pub fn new(module: Module) -> Self {
     Self {
-      program,
+      module,
       output: String::new(),
+      registers: true,
+      labels: 0,
+      alloc: Allocation {
+        locs:  Vec::new(),
+        saved: Vec::new(),
+        frame: 0,
+      },
+      uses: Vec::new(),
+    }
+  }
+
+  /// Whether to allocate registers, otherwise every temp lives in the frame.
+  pub fn with_registers(mut self, registers: bool) -> Self {
+    self.registers = registers;
+    self
+  }
+
+  fn label(&self, block: usize) -> String {
+    format!(".L{}", self.labels + block)
+  }
+
+  fn loc(&self, temp: Temp) -> Loc {
+    self.alloc.locs[temp.0]
+  }
+
+  fn value(&self, val: Value) -> String {
+    match val {
+      Value::Temp(temp) => self.loc(temp).to_string(),
+      Value::Const(val) => val.to_string(),
+    }
+  }
+
+  /// Moves `val` into `reg`.
+  fn load_value(&self, reg: &str, val: Value) -> String {
+    let val = self.value(val);
+    if val == reg {
+      return String::new();
+    }
+    format!("  mov {}, {}\n", reg, val)
+  }
+
+  /// Moves `reg` into `dst`.
+  fn store_temp(&self, dst: Temp, reg: &str) -> String {
+    let dst = self.loc(dst).to_string();
+    if dst == reg {
+      return String::new();
+    }
+    format!("  mov {}, {}\n", dst, reg)
+  }
+
+  /// `val` as the source operand of an ALU instruction, moving it into
+  /// `scratch` first if it's an immediate that doesn't fit.
+  fn source(&self, scratch: &str, val: Value) -> (String, String) {
+    match val {
+      Value::Const(c) if !is_imm32(c) => (self.load_value(scratch, val), scratch.to_string()),
+      _ => (String::new(), self.value(val)),
+    }
+  }
+
+  /// Memory operand at the address `val`, moving it into `scratch` first
+  /// unless it's in a register.
+  fn address(&self, scratch: &str, val: Value) -> (String, String) {
+    match val {
+      Value::Temp(temp) if matches!(self.loc(temp), Loc::Reg(_)) => {
+        (String::new(), format!("[{}]", self.loc(temp)))
+      },
+      _ => (self.load_value(scratch, val), format!("[{}]", scratch)),
     }
   }
 
-  fn gen_expr(&mut self, expr: Expr) -> String {
+  fn gen_copy(&self, dst: Temp, src: Value) -> String {
+    let (dst_loc, src_str) = (self.loc(dst), self.value(src));
+    if dst_loc.to_string() == src_str {
+      return String::new();
+    }
+    // memory to memory and 64-bit immediates to memory go through rax
+    let direct = match (dst_loc, src) {
+      (Loc::Reg(_), _) => true,
+      (Loc::Slot(_), Value::Const(c)) => is_imm32(c),
+      (Loc::Slot(_), Value::Temp(src)) => matches!(self.loc(src), Loc::Reg(_)),
+    };
+    if direct {
+      format!("  mov {}, {}\n", dst_loc, src_str)
+    } else {
+      self.load_value("rax", src) + &self.store_temp(dst, "rax")
+    }
+  }
+
+  fn gen_call(&self, dst: Temp, name: &str, args: &[Value]) -> String {
     let mut code = String::new();
+    let on_stack = args.len().saturating_sub(ARG_REGISTERS.len());
+
+    // the frame is 16-byte aligned, so rsp is aligned at the call when an even
+    // number of arguments is pushed
+    let pad = on_stack % 2;
+    if pad == 1 {
+      code += "  sub rsp, 8\n";
+    }
 
-    match expr {
-      Expr::Literal(l) => match l {
-        Literal::Integer(i) => {
-          code += &format!("  mov rax, {}\n", i);
+    // the seventh argument ends up on top
+    for arg in args.iter().skip(ARG_REGISTERS.len()).rev() {
+      match arg {
+        Value::Temp(_) => code += &format!("  push {}\n", self.value(*arg)),
+        Value::Const(_) => {
+          code += &self.load_value("rax", *arg);
           code += "  push rax\n";
         },
+      }
+    }
+
+    for (arg, reg) in args.iter().zip(ARG_REGISTERS) {
+      code += &self.load_value(reg, *arg);
+    }
+
+    code += &format!("  call {}\n", function_label(name));
+
+    if pad + on_stack > 0 {
+      code += &format!("  add rsp, {}\n", 8 * (pad + on_stack));
+    }
+
+    code += &self.store_temp(dst, "rax");
+    code
+  }
+
+  /// Compares `lhs` with `rhs` setting the flags.
+  fn gen_compare(&self, lhs: Value, rhs: Value) -> String {
+    let mut code = String::new();
+    let lhs = match (lhs, rhs) {
+      (Value::Temp(temp), _) if matches!(self.loc(temp), Loc::Reg(_)) => self.value(lhs),
+      (Value::Temp(_), Value::Const(_)) => self.value(lhs),
+      _ => {
+        code += &self.load_value("rax", lhs);
+        "rax".to_string()
       },
-      Expr::Identifier(i) => {
-        let entry = self.program.find_entry(&i, false).unwrap();
-        code += &format!("  mov rax, [rbp - {}]\n", entry.offset);
-        code += "  push rax\n";
+    };
+    let (load, rhs) = self.source("rcx", rhs);
+    code += &load;
+    code += &format!("  cmp {}, {}\n", lhs, rhs);
+    code
+  }
+
+  fn gen_binary(&self, dst: Temp, op: BinaryOp, ty: &Type, lhs: Value, rhs: Value) -> String {
+    let mut code = String::new();
+
+    if let Some(cc) = condition(op, ty) {
+      code += &self.gen_compare(lhs, rhs);
+      code += &format!("  set{} al\n", cc);
+      return match self.loc(dst) {
+        Loc::Reg(reg) => code + &format!("  movzx {}, al\n", reg),
+        Loc::Slot(_) => code + "  movzx rax, al\n" + &self.store_temp(dst, "rax"),
+      };
+    }
+
+    // compute in the destination register if nothing needs rax, and it isn't
+    // overwritten before the right operand is read
+    let target = match self.loc(dst) {
+      Loc::Reg(reg)
+        if matches!(op, BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul)
+          && wrap(ty).is_empty()
+          && self.value(rhs) != reg =>
+      {
+        reg
       },
+      _ => "rax",
+    };
+    code += &self.load_value(target, lhs);
+
+    match op {
+      BinaryOp::Add | BinaryOp::Sub => {
+        let (load, rhs) = self.source("rcx", rhs);
+        code += &load;
+        let mnemonic = if op == BinaryOp::Add { "add" } else { "sub" };
+        code += &format!("  {} {}, {}\n", mnemonic, target, rhs);
+      },
+      BinaryOp::Mul => {
+        let rhs = match rhs {
+          Value::Temp(_) => self.value(rhs),
+          Value::Const(_) => {
+            code += &self.load_value("rcx", rhs);
+            "rcx".to_string()
+          },
+        };
+        code += &format!("  imul {}, {}\n", target, rhs);
+      },
+      BinaryOp::Div | BinaryOp::Mod => {
+        code += &self.load_value("rcx", rhs);
+        let (zero, overflow) = division_checks(ty, rhs);
+        if zero {
+          code += "  test rcx, rcx\n";
+          code += "  je _division_by_zero\n";
+        }
+        if overflow {
+          // rax ^ i64::MIN and !rcx are both 0 only for i64::MIN / -1, `not`
+          // leaves the flags of `or` alone
+          code += &format!("  mov rdx, {}\n", i64::MIN);
+          code += "  xor rdx, rax\n";
+          code += "  not rcx\n";
+          code += "  or rdx, rcx\n";
+          code += "  not rcx\n";
+          code += "  je _division_overflow\n";
+        }
+        if ty.is_signed() {
+          code += "  cqo\n";
+          code += "  idiv rcx\n";
+        } else {
+          code += "  xor edx, edx\n";
+          code += "  div rcx\n";
+        }
+        if op == BinaryOp::Mod {
+          code += "  mov rax, rdx\n";
+        }
+      },
+      _ => unreachable!("comparisons are handled above, && and || lowered to branches"),
     }
 
+    code += wrap(ty);
+    code += &self.store_temp(dst, target);
     code
   }
 
-  fn gen_stmt(&mut self, stmt: Stmt) -> String {
+  fn gen_inst(&self, function: &IrFunction, inst: &Inst) -> String {
     let mut code = String::new();
 
-    match stmt {
-      Stmt::Exit(expr) => {
-        code += "  ; exit\n";
-        code += &self.gen_expr(expr);
-        code += "  pop rdi\n";
-        code += "  jmp _exit\n";
-        code += "  ; end exit\n";
+    match inst {
+      Inst::Copy(dst, src) => {
+        code += &self.gen_copy(*dst, *src);
+      },
+      Inst::Binary(dst, op, ty, lhs, rhs) => {
+        code += &self.gen_binary(*dst, *op, ty, *lhs, *rhs);
+      },
+      Inst::Unary(dst, op, ty, src) => {
+        code += &self.load_value("rax", *src);
+        match op {
+          UnaryOp::Neg => {
+            code += "  neg rax\n";
+            code += wrap(ty);
+          },
+          UnaryOp::Not => {
+            code += "  cmp rax, 0\n";
+            code += "  sete al\n";
+            code += "  movzx rax, al\n";
+          },
+        }
+        code += &self.store_temp(*dst, "rax");
+      },
+      Inst::Cast(dst, ty, src) => {
+        code += &self.load_value("rax", *src);
+        code += wrap(ty);
+        code += &self.store_temp(*dst, "rax");
+      },
+      Inst::Load(dst, local) => {
+        let local = &function.locals[*local];
+        code += &load(&local.ty, &slot(local.offset));
+        code += &self.store_temp(*dst, "rax");
+      },
+      Inst::Store(local, src) => {
+        let local = &function.locals[*local];
+        code += &self.load_value("rax", *src);
+        code += &store(&local.ty, &slot(local.offset));
+      },
+      Inst::Call(dst, name, args) => {
+        code += &self.gen_call(*dst, name, args);
       },
-      Stmt::VarDecl(name, expr) => {
-        code += "  ; var declaration\n";
-        code += &self.gen_expr(expr);
-        code += "  pop rax\n";
-        let entry = self.program.find_entry(&name, false).unwrap();
-        code += &format!("  mov [rbp - {}], rax\n", entry.offset);
-        code += "  ; end var declaration\n";
+      Inst::Str(dst, i) => {
+        let reg = match self.loc(*dst) {
+          Loc::Reg(reg) => reg,
+          Loc::Slot(_) => "rax",
+        };
+        code += &format!("  lea {}, [rel {}]\n", reg, string_label(*i));
+        code += &self.store_temp(*dst, reg);
       },
-      Stmt::VarAssign(name, expr) => {
-        code += "  ; var assignment\n";
-        code += &self.gen_expr(expr);
-        code += "  pop rax\n";
-        let entry = self.program.find_entry(&name, false).unwrap();
-        code += &format!("  mov [rbp - {}], rax\n", entry.offset);
-        code += "  ; end var assignment\n";
+      Inst::Print(dst, ty, src) => {
+        let routine = match ty {
+          Type::Str => "_print_str",
+          _ if ty.is_signed() => "_print_int",
+          Type::Bool => unreachable!("bools are printed as strings"),
+          _ => "_print_uint",
+        };
+        code += &self.load_value("rdi", *src);
+        code += &format!("  call {}\n", routine);
+        code += &self.store_temp(*dst, "rax");
+      },
+      Inst::Addr(dst, local) => {
+        let reg = match self.loc(*dst) {
+          Loc::Reg(reg) => reg,
+          Loc::Slot(_) => "rax",
+        };
+        let local = &function.locals[*local];
+        code += &format!("  lea {}, {}\n", reg, slot(local.offset));
+        code += &self.store_temp(*dst, reg);
+      },
+      Inst::Read(dst, ty, addr) => {
+        let (load_addr, mem) = self.address("rax", *addr);
+        code += &load_addr;
+        code += &load(ty, &mem);
+        code += &self.store_temp(*dst, "rax");
+      },
+      Inst::Write(ty, addr, src) => {
+        let (load_addr, mem) = self.address("rcx", *addr);
+        code += &load_addr;
+        code += &self.load_value("rax", *src);
+        code += &store(ty, &mem);
+      },
+      Inst::Move(dst, src, size) => {
+        // rdi and rsi are only used to pass arguments, rcx is scratch
+        code += &self.load_value("rdi", *dst);
+        code += &self.load_value("rsi", *src);
+        code += &format!("  mov rcx, {}\n", size);
+        code += "  rep movsb\n";
+      },
+      Inst::Check(index, len) => {
+        code += &self.gen_compare(*index, Value::Const(*len as i64));
+        code += "  jae _out_of_bounds\n";
+      },
+      Inst::Param(dst, i) => match ARG_REGISTERS.get(*i) {
+        Some(reg) => code += &self.store_temp(*dst, reg),
+        None => {
+          // pushed by the caller above the return address
+          let arg = slot(-(16 + 8 * (i - ARG_REGISTERS.len()) as isize));
+          code += &format!("  mov rax, qword {}\n", arg);
+          code += &self.store_temp(*dst, "rax");
+        },
+      },
+    }
+
+    code
+  }
+
+  /// Ends block `i`, leaving out jumps to the block that follows it. A branch
+  /// on a comparison only the branch reads jumps on the comparison's flags.
+  fn gen_term(&self, i: usize, term: &Terminator, compare: Option<&Inst>) -> String {
+    let mut code = String::new();
+    let jump = |target: usize| {
+      if target == i + 1 {
+        String::new()
+      } else {
+        format!("  jmp {}\n", self.label(target))
+      }
+    };
+
+    match term {
+      Terminator::Jump(target) => code += &jump(*target),
+      Terminator::Branch(Value::Const(c), then, other) => {
+        code += &jump(if *c != 0 { *then } else { *other });
+      },
+      Terminator::Branch(cond, then, other) => {
+        let cc = match compare {
+          Some(Inst::Binary(_, op, ty, lhs, rhs)) => {
+            code += &self.gen_compare(*lhs, *rhs);
+            condition(*op, ty).unwrap()
+          },
+          _ => {
+            code += &format!("  cmp {}, 0\n", self.value(*cond));
+            "ne"
+          },
+        };
+        if *then == i + 1 {
+          code += &format!("  j{} {}\n", negate(cc), self.label(*other));
+        } else {
+          code += &format!("  j{} {}\n", cc, self.label(*then));
+          code += &jump(*other);
+        }
+      },
+      Terminator::Return(val) => {
+        code += &self.load_value("rax", *val);
+        for (i, reg) in self.alloc.saved.iter().enumerate() {
+          code += &format!("  mov {}, qword {}\n", reg, slot(self.alloc.save_slot(i)));
+        }
+        code += "  mov rsp, rbp\n";
+        code += "  pop rbp\n";
+        code += "  ret\n";
+      },
+      Terminator::Exit(val) => {
+        code += &self.load_value("rdi", *val);
+        code += "  jmp _exit\n";
       },
     }
 
     code
   }
 
+  fn gen_blocks(&mut self, function: &IrFunction) -> String {
+    let mut code = String::new();
+
+    for (i, block) in function.blocks.iter().enumerate() {
+      code += &format!("{}:\n", self.label(i));
+
+      let mut insts = &block.insts[..];
+      let compare = match (insts.last(), &block.term) {
+        (Some(inst @ Inst::Binary(dst, op, ty, ..)), Terminator::Branch(Value::Temp(cond), ..))
+          if dst == cond && self.uses[dst.0] == 1 && condition(*op, ty).is_some() =>
+        {
+          insts = &insts[..insts.len() - 1];
+          Some(inst)
+        },
+        _ => None,
+      };
+
+      for inst in insts.iter() {
+        code += &self.gen_inst(function, inst);
+      }
+      code += &self.gen_term(i, &block.term, compare);
+    }
+
+    self.labels += function.blocks.len();
+    code
+  }
+
+  /// Decides where the temps of `function` go.
+  fn allocate(&mut self, function: &IrFunction) {
+    self.alloc = if self.registers {
+      allocate(function)
+    } else {
+      Allocation::spill_all(function)
+    };
+
+    self.uses = vec![0; function.temps];
+    for block in function.blocks.iter() {
+      for inst in block.insts.iter() {
+        for temp in inst.uses() {
+          self.uses[temp.0] += 1;
+        }
+      }
+      for temp in block.term.uses() {
+        self.uses[temp.0] += 1;
+      }
+    }
+  }
+
+  fn gen_function(&mut self, function: &IrFunction) -> String {
+    let mut code = String::new();
+    self.allocate(function);
+    let frame = self.alloc.frame_size(true);
+
+    let label = function_label(&function.name);
+    code += &format!("global {}\n{}:\n", label, label);
+    code += "  push rbp\n";
+    code += "  mov rbp, rsp\n";
+    if frame > 0 {
+      code += &format!("  sub rsp, {}\n", frame);
+    }
+    for (i, reg) in self.alloc.saved.iter().enumerate() {
+      code += &format!("  mov qword {}, {}\n", slot(self.alloc.save_slot(i)), reg);
+    }
+
+    code += &self.gen_blocks(function);
+    code
+  }
+
   pub fn generate(&mut self) -> String {
     self.output.clear();
-    self.program.curr_table = 0;
+    self.labels = 0;
+
+    let module = self.module.clone();
+    self.allocate(&module.main);
 
     self.output += "; generated by pecan\n\n";
     self.output += "global _start\n";
     self.output += "section .text\n";
     self.output += "_start:\n";
     self.output += "  mov rbp, rsp\n";
+    // rsp is 16-byte aligned on entry, the program exits instead of returning
+    // so nothing needs to be saved
+    let frame = self.alloc.frame_size(false);
+    if frame > 0 {
+      self.output += &format!("  sub rsp, {}\n", frame);
+    }
     self.output += "; program start\n\n";
 
-    let stmts = self.program.stmts.clone();
-    for stmt in stmts {
-      let code = self.gen_stmt(stmt.clone());
-      self.output += &code;
-    }
+    let code = self.gen_blocks(&module.main);
+    self.output += &code;
 
     self.output += "\n; program end\n";
-    self.output += "  mov rdi, 0\n";
     self.output += "_exit:\n";
     self.output += "  mov rsp, rbp\n";
     self.output += "  mov rax, 60\n";
     self.output += "  syscall\n";
 
+    for function in module.functions.iter() {
+      self.output += "\n";
+      let code = self.gen_function(function);
+      self.output += &code;
+    }
+
+    let uses = |pred: fn(&Inst) -> bool| {
+      std::iter::once(&module.main)
+        .chain(module.functions.iter())
+        .flat_map(|function| function.blocks.iter())
+        .any(|block| block.insts.iter().any(pred))
+    };
+    if uses(|inst| matches!(inst, Inst::Print(..))) {
+      self.output += RUNTIME;
+    }
+    let errors = [
+      (
+        "_out_of_bounds",
+        RuntimeError::out_of_bounds(),
+        uses(|inst| matches!(inst, Inst::Check(..))),
+      ),
+      (
+        "_division_by_zero",
+        RuntimeError::division_by_zero(),
+        uses(|inst| {
+          matches!(inst, Inst::Binary(_, BinaryOp::Div | BinaryOp::Mod, ty, _, rhs)
+            if division_checks(ty, *rhs).0)
+        }),
+      ),
+      (
+        "_division_overflow",
+        RuntimeError::division_overflow(),
+        uses(|inst| {
+          matches!(inst, Inst::Binary(_, BinaryOp::Div | BinaryOp::Mod, ty, _, rhs)
+            if division_checks(ty, *rhs).1)
+        }),
+      ),
+    ];
+    let errors = errors
+      .iter()
+      .filter(|(.., checked)| *checked)
+      .collect::<Vec<_>>();
+    for (label, error, _) in errors.iter() {
+      self.output += &error_runtime(label, error);
+    }
+
+    if !module.strings.is_empty() || !errors.is_empty() {
+      self.output += "\nsection .rodata\n";
+    }
+    for (label, error, _) in errors.iter() {
+      let bytes = error_message(error)
+        .bytes()
+        .map(|b| b.to_string())
+        .collect::<Vec<_>>()
+        .join(", ");
+      self.output += &format!("{}_msg:\n", label);
+      self.output += &format!("  db {}\n", bytes);
+    }
+    for (i, string) in module.strings.iter().enumerate() {
+      self.output += &format!("{}:\n", string_label(i));
+      self.output += &format!("  dq {}\n", string.len());
+      if !string.is_empty() {
+        let bytes = string
+          .bytes()
+          .map(|b| b.to_string())
+          .collect::<Vec<_>>()
+          .join(", ");
+        let literal = Literal::String(string.clone());
+        self.output += &format!("  db {} ; {}\n", bytes, literal);
+      }
+    }
+
     self.output.clone()
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn generate(input: &str) -> (Program, Module, String) {
+    let program = check(input, "test.pn").unwrap();
+    let module = Lowerer::new(program.clone()).lower();
+    let asm = Generator::new(module.clone())
+      .with_registers(false)
+      .generate();
+    (program, module, asm)
+  }
+
+  /// Displacements of every `[rbp - n]` operand in `asm`.
+  fn slots(asm: &str) -> Vec<usize> {
+    asm
+      .split("[rbp - ")
+      .skip(1)
+      .map(|rest| rest[..rest.find(']').unwrap()].parse().unwrap())
+      .collect()
+  }
+
+  #[test]
+  fn variables_and_temps_do_not_alias() {
+    let (program, module, asm) = generate(
+      "a := 1
+b := 2
+c := a + b * (a - b)
+if c < 0 {
+  d := a + b + c
+  c = d
+}
+exit(a + b + c)
+",
+    );
+
+    let frame = Allocation::spill_all(&module.main).frame_size(false);
+    assert_eq!(frame % 16, 0);
+    assert!(asm.contains(&format!("  mov rbp, rsp\n  sub rsp, {}\n", frame)));
+
+    // every variable has its own 8-byte slot between rbp and the temps, so
+    // nothing computed afterwards can overwrite it
+    let mut offsets = program
+      .symbol_tables
+      .iter()
+      .flat_map(|table| table.entries.iter().map(|entry| entry.offset))
+      .collect::<Vec<_>>();
+    offsets.sort();
+    assert_eq!(offsets, vec![8, 16, 24, 32]);
+    assert_eq!(module.main.frame, 32);
+
+    for slot in slots(&asm) {
+      assert!(slot >= 8 && slot <= frame, "[rbp - {}] outside frame", slot);
+    }
+  }
+
+  #[test]
+  fn variables_take_the_size_of_their_type() {
+    let (program, _, asm) = generate(
+      "a: u8 := 1
+b: i32 := 2
+c := a
+d: i16 := -4
+exit(b)
+",
+    );
+
+    // each variable is aligned to its size, c is inferred as u8
+    let offsets = program.symbol_tables[0]
+      .entries
+      .iter()
+      .map(|entry| entry.offset)
+      .collect::<Vec<_>>();
+    assert_eq!(offsets, vec![1, 8, 9, 12]);
+    assert_eq!(program.frame_size(0), 16);
+
+    assert!(asm.contains("  mov [rbp - 1], al\n"));
+    assert!(asm.contains("  mov [rbp - 8], eax\n"));
+    assert!(asm.contains("  movzx eax, byte [rbp - 1]\n"));
+    assert!(asm.contains("  mov [rbp - 12], ax\n"));
+  }
+
+  #[test]
+  fn arrays_are_aligned_like_their_elements() {
+    let (program, _, asm) = generate(
+      "a: u8 := 1
+b: [i32; 3] := [1, 2, 3]
+c := b[a]
+exit(c)
+",
+    );
+
+    let offsets = program.symbol_tables[0]
+      .entries
+      .iter()
+      .map(|entry| (entry.name.as_str(), entry.offset))
+      .collect::<Vec<_>>();
+    assert_eq!(offsets, vec![("a", 1), ("[]", 16), ("b", 28), ("c", 32)]);
+
+    assert!(asm.contains(
+      "  lea rax, [rbp - 28]
+"
+    ));
+    assert!(asm.contains(
+      "  rep movsb
+"
+    ));
+    assert!(asm.contains(
+      "  jae _out_of_bounds
+"
+    ));
+    assert!(asm.contains(
+      "_out_of_bounds:
+"
+    ));
+  }
+
+  #[test]
+  fn divisions_are_checked_unless_the_divisor_is_safe() {
+    let (_, _, asm) = generate(
+      "a := 7
+b: u8 := 2
+c := a / 2 + a % -1
+d := b / b
+e := a / a
+exit(c + d as i64 + e)
+",
+    );
+
+    assert_eq!(asm.matches("  je _division_by_zero\n").count(), 2);
+    assert_eq!(asm.matches("  je _division_overflow\n").count(), 2);
+    assert!(asm.contains("_division_by_zero:\n") && asm.contains("_division_overflow:\n"));
+    assert!(!asm.contains("_out_of_bounds"));
+  }
+
+  #[test]
+  fn function_frames_are_reserved_below_saved_rbp() {
+    let (program, module, asm) = generate(
+      "fn f(x, y) {
+  z := x * y
+  return z + x
+}
+exit(f(2, 3))
+",
+    );
+
+    let function = program.find_function("f").unwrap();
+    assert_eq!(program.frame_size(function.body.table), 32);
+
+    let frame = Allocation::spill_all(&module.functions[0]).frame_size(true);
+    assert!(asm.contains(&format!(
+      "pn_f:\n  push rbp\n  mov rbp, rsp\n  sub rsp, {}\n",
+      frame
+    )));
+
+    for slot in slots(&asm) {
+      assert!(slot >= 8 && slot <= frame, "[rbp - {}] outside frame", slot);
+    }
+  }
+
+  #[test]
+  fn calls_follow_the_system_v_convention() {
+    let (_, _, asm) = generate(
+      "fn f(a, b, c, d, e, g, h) {
+  return a + h
+}
+exit(f(1, 2, 3, 4, 5, 6, 7))
+",
+    );
+
+    // the seventh argument is on the stack, kept 16-byte aligned at the call
+    assert!(asm.contains(
+      "  sub rsp, 8
+  mov rax, 7
+  push rax
+  mov rdi, 1
+  mov rsi, 2
+  mov rdx, 3
+  mov rcx, 4
+  mov r8, 5
+  mov r9, 6
+  call pn_f
+  add rsp, 16
+"
+    ));
+    assert!(asm.contains("pn_f:\n  push rbp\n  mov rbp, rsp\n"));
+    assert!(asm.contains("qword [rbp + 16]"));
+    assert!(asm.contains("  mov rsp, rbp\n  pop rbp\n  ret\n"));
+  }
+
+  #[test]
+  fn escaped_function_labels_are_distinct() {
+    assert_eq!(function_label("é1"), "pn_.ue9.1");
+    assert_eq!(function_label("ຑ"), "pn_.ue91.");
+
+    let (_, _, asm) =
+      generate("fn é1() {\n  return 1\n}\nfn ຑ() {\n  return 2\n}\nexit(é1() + ຑ())\n");
+    assert!(Assembler::new(&asm).assemble().is_ok());
+  }
+}
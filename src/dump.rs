@@ -0,0 +1,415 @@
+//! Human readable dumps of each compiler stage for `--emit`. The formats are
+//! kept stable so dumps can be diffed between versions.
+
+use std::fmt::Write;
+
+use super::*;
+
+/// One token per line with its location, kind and text.
+///
+/// ```text
+/// 1:1     Identifier  x
+/// 1:3     Symbol      :=
+/// 1:6     Literal     1
+/// 1:7     Newline     <NEWLINE>
+/// ```
+pub fn dump_tokens(tokens: &[Token], source_map: &SourceMap) -> String {
+  let mut out = String::new();
+  for token in tokens.iter() {
+    let (line, col) = source_map.location(token.start());
+    writeln!(
+      out,
+      "{:<8}{:<12}{}",
+      format!("{}:{}", line, col),
+      token.type_to_string(),
+      token
+    )
+    .unwrap();
+  }
+  out
+}
+
+/// A pattern as written, with its bindings.
+fn pattern(pattern: &Pattern) -> String {
+  match &pattern.kind {
+    PatternKind::Wildcard => "_".to_string(),
+    PatternKind::Literal(lit) => lit.to_string(),
+    PatternKind::Variant(def, index, bindings) if bindings.is_empty() => def.path(*index),
+    PatternKind::Variant(def, index, bindings) => {
+      let bindings = bindings
+        .iter()
+        .map(|var| var.as_ref().map_or("_", |var| &var.name))
+        .collect::<Vec<_>>()
+        .join(", ");
+      format!("{}({})", def.path(*index), bindings)
+    },
+  }
+}
+
+fn dump_expr(out: &mut String, expr: &Expr, depth: usize) {
+  let indent = "  ".repeat(depth);
+  match &expr.kind {
+    ExprKind::Literal(lit) => writeln!(out, "{}lit {}: {}", indent, lit, expr.ty).unwrap(),
+    ExprKind::Identifier(var) => writeln!(out, "{}var {}: {}", indent, var.name, expr.ty).unwrap(),
+    ExprKind::Binary(op, lhs, rhs) => {
+      writeln!(out, "{}binary {}: {}", indent, op, expr.ty).unwrap();
+      dump_expr(out, lhs, depth + 1);
+      dump_expr(out, rhs, depth + 1);
+    },
+    ExprKind::Unary(op, operand) => {
+      writeln!(out, "{}unary {}: {}", indent, op, expr.ty).unwrap();
+      dump_expr(out, operand, depth + 1);
+    },
+    ExprKind::Call(name, args) => {
+      writeln!(out, "{}call {}: {}", indent, name, expr.ty).unwrap();
+      for arg in args.iter() {
+        dump_expr(out, arg, depth + 1);
+      }
+    },
+    ExprKind::Builtin(builtin, args) => {
+      writeln!(out, "{}builtin {}: {}", indent, builtin, expr.ty).unwrap();
+      for arg in args.iter() {
+        dump_expr(out, arg, depth + 1);
+      }
+    },
+    ExprKind::If(cond, then, other) => {
+      writeln!(out, "{}if: {}", indent, expr.ty).unwrap();
+      for expr in [cond, then, other] {
+        dump_expr(out, expr, depth + 1);
+      }
+    },
+    ExprKind::Array(_, elems) => {
+      writeln!(out, "{}array: {}", indent, expr.ty).unwrap();
+      for elem in elems.iter() {
+        dump_expr(out, elem, depth + 1);
+      }
+    },
+    ExprKind::Index(array, index) => {
+      writeln!(out, "{}index: {}", indent, expr.ty).unwrap();
+      dump_expr(out, array, depth + 1);
+      dump_expr(out, index, depth + 1);
+    },
+    ExprKind::Struct(_, _, fields) => {
+      writeln!(out, "{}struct: {}", indent, expr.ty).unwrap();
+      for (name, value) in fields.iter() {
+        writeln!(out, "{}  {}", indent, name).unwrap();
+        dump_expr(out, value, depth + 2);
+      }
+    },
+    ExprKind::Field(inner, name) => {
+      writeln!(out, "{}field {}: {}", indent, name, expr.ty).unwrap();
+      dump_expr(out, inner, depth + 1);
+    },
+    ExprKind::Ref(inner) => {
+      writeln!(out, "{}ref: {}", indent, expr.ty).unwrap();
+      dump_expr(out, inner, depth + 1);
+    },
+    ExprKind::Deref(inner) => {
+      writeln!(out, "{}deref: {}", indent, expr.ty).unwrap();
+      dump_expr(out, inner, depth + 1);
+    },
+    ExprKind::Cast(inner, _) => {
+      writeln!(out, "{}cast: {}", indent, expr.ty).unwrap();
+      dump_expr(out, inner, depth + 1);
+    },
+    ExprKind::Variant(_, def, index, values) => {
+      writeln!(out, "{}variant {}: {}", indent, def.path(*index), expr.ty).unwrap();
+      for value in values.iter() {
+        dump_expr(out, value, depth + 1);
+      }
+    },
+    ExprKind::Match(value, arms) => {
+      writeln!(out, "{}match: {}", indent, expr.ty).unwrap();
+      dump_expr(out, value, depth + 1);
+      for (pat, arm) in arms.iter() {
+        writeln!(out, "{}  arm {}", indent, pattern(pat)).unwrap();
+        dump_expr(out, arm, depth + 2);
+      }
+    },
+  }
+}
+
+fn dump_block(out: &mut String, program: &Program, block: &Block, depth: usize) {
+  for stmt in block.stmts.iter() {
+    dump_stmt(out, program, stmt, depth);
+  }
+}
+
+fn dump_stmt(out: &mut String, program: &Program, stmt: &Stmt, depth: usize) {
+  let indent = "  ".repeat(depth);
+  match stmt {
+    Stmt::Exit(expr) => {
+      writeln!(out, "{}exit", indent).unwrap();
+      dump_expr(out, expr, depth + 1);
+    },
+    Stmt::VarDecl(var, _, expr) => {
+      let ty = &program.entry(var).ty;
+      writeln!(out, "{}decl {}: {}", indent, var.name, ty).unwrap();
+      dump_expr(out, expr, depth + 1);
+    },
+    Stmt::VarAssign(var, expr) => {
+      writeln!(out, "{}assign {}", indent, var.name).unwrap();
+      dump_expr(out, expr, depth + 1);
+    },
+    Stmt::Assign(place, expr) => {
+      writeln!(out, "{}assign", indent).unwrap();
+      dump_expr(out, place, depth + 1);
+      dump_expr(out, expr, depth + 1);
+    },
+    Stmt::If(branches, else_block) => {
+      writeln!(out, "{}if", indent).unwrap();
+      for (cond, block) in branches.iter() {
+        writeln!(out, "{}  cond", indent).unwrap();
+        dump_expr(out, cond, depth + 2);
+        writeln!(out, "{}  then", indent).unwrap();
+        dump_block(out, program, block, depth + 2);
+      }
+      if let Some(block) = else_block {
+        writeln!(out, "{}  else", indent).unwrap();
+        dump_block(out, program, block, depth + 2);
+      }
+    },
+    Stmt::While(cond, block) => {
+      writeln!(out, "{}while", indent).unwrap();
+      writeln!(out, "{}  cond", indent).unwrap();
+      dump_expr(out, cond, depth + 2);
+      writeln!(out, "{}  do", indent).unwrap();
+      dump_block(out, program, block, depth + 2);
+    },
+    Stmt::Match(value, arms) => {
+      writeln!(out, "{}match", indent).unwrap();
+      dump_expr(out, value, depth + 1);
+      for (pat, block) in arms.iter() {
+        writeln!(out, "{}  arm {}", indent, pattern(pat)).unwrap();
+        dump_block(out, program, block, depth + 2);
+      }
+    },
+    Stmt::Return(expr) => {
+      writeln!(out, "{}return", indent).unwrap();
+      if let Some(expr) = expr {
+        dump_expr(out, expr, depth + 1);
+      }
+    },
+    Stmt::Expr(expr) => {
+      writeln!(out, "{}expr", indent).unwrap();
+      dump_expr(out, expr, depth + 1);
+    },
+  }
+}
+
+/// The checked syntax tree as an indented outline, with the type of every
+/// expression. Structs and enums come first with the offset of each field or
+/// value, then functions.
+///
+/// ```text
+/// decl x: u8
+///   binary +: u8
+///     lit 1: u8
+///     lit 2: u8
+/// ```
+pub fn dump_ast(program: &Program) -> String {
+  let mut out = String::new();
+
+  for def in program.structs.iter() {
+    writeln!(
+      out,
+      "struct {}, size {}, align {}",
+      def.name,
+      def.size(),
+      def.align()
+    )
+    .unwrap();
+    for field in def.fields().iter() {
+      writeln!(out, "  {}: {} at {}", field.name, field.ty, field.offset).unwrap();
+    }
+  }
+
+  for def in program.enums.iter() {
+    writeln!(
+      out,
+      "enum {}, size {}, align {}",
+      def.name, def.size, def.align
+    )
+    .unwrap();
+    for variant in def.variants.iter() {
+      let fields = variant
+        .fields
+        .iter()
+        .zip(variant.offsets.iter())
+        .map(|(ty, offset)| format!("{} at {}", ty, offset))
+        .collect::<Vec<_>>();
+      if fields.is_empty() {
+        writeln!(out, "  {}", variant.name).unwrap();
+      } else {
+        writeln!(out, "  {}({})", variant.name, fields.join(", ")).unwrap();
+      }
+    }
+  }
+
+  for function in program.functions.iter() {
+    let params = function
+      .params
+      .iter()
+      .map(|(name, ty)| format!("{}: {}", name, ty))
+      .collect::<Vec<_>>()
+      .join(", ");
+    writeln!(out, "fn {}({}) -> {}", function.name, params, function.ret).unwrap();
+    dump_block(&mut out, program, &function.body, 1);
+  }
+
+  for stmt in program.stmts.iter() {
+    dump_stmt(&mut out, program, stmt, 0);
+  }
+
+  out
+}
+
+/// Every symbol table with its scope, placement in the frame and variables.
+///
+/// ```text
+/// table 0: global, offset 0, size 9
+///   x: u8 at [rbp - 1]
+///   y: i64 at [rbp - 16]
+/// ```
+pub fn dump_symbols(program: &Program) -> String {
+  let mut out = String::new();
+
+  for (i, table) in program.symbol_tables.iter().enumerate() {
+    let scope = match table.ty {
+      ScopeType::Global => "global".to_string(),
+      ScopeType::Block => format!("block in table {}", table.parent.unwrap()),
+      ScopeType::Function => match program.functions.iter().find(|f| f.body.table == i) {
+        Some(function) => format!("function {}", function.name),
+        None => "function".to_string(),
+      },
+    };
+    writeln!(
+      out,
+      "table {}: {}, offset {}, size {}",
+      i,
+      scope,
+      table.offset,
+      table.size()
+    )
+    .unwrap();
+
+    for entry in table.entries.iter() {
+      writeln!(
+        out,
+        "  {}: {} at {}",
+        entry.name,
+        entry.ty,
+        slot(entry.offset)
+      )
+      .unwrap();
+    }
+  }
+
+  out
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn dumps_tokens_with_locations() {
+    let input = "x := 0xff\nexit(x)";
+    let tokens = Lexer::new(input).lex().unwrap();
+    let dump = dump_tokens(&tokens, &SourceMap::new("test.pn", input));
+
+    assert_eq!(
+      dump,
+      "1:1     Identifier  x
+1:3     Symbol      :=
+1:6     Literal     255
+1:10    Newline     <NEWLINE>
+2:1     Keyword     exit
+2:5     Symbol      (
+2:6     Identifier  x
+2:7     Symbol      )
+2:8     EOF         <EOF>
+"
+    );
+  }
+
+  #[test]
+  fn dumps_typed_ast_and_symbols() {
+    let program = check(
+      "fn inc(a: u8) -> u8 {
+  return a + 1
+}
+x := inc(2)
+if x > 2 {
+  y := -1
+}
+",
+      "test.pn",
+    )
+    .unwrap();
+
+    assert_eq!(
+      dump_ast(&program),
+      "fn inc(a: u8) -> u8
+  return
+    binary +: u8
+      var a: u8
+      lit 1: u8
+decl x: u8
+  call inc: u8
+    lit 2: u8
+if
+  cond
+    binary >: bool
+      var x: u8
+      lit 2: u8
+  then
+    decl y: i64
+      lit -1: i64
+"
+    );
+
+    assert_eq!(
+      dump_symbols(&program),
+      "table 0: global, offset 0, size 1
+  x: u8 at [rbp - 1]
+table 1: function inc, offset 0, size 1
+  a: u8 at [rbp - 1]
+table 2: block in table 0, offset 1, size 15
+  y: i64 at [rbp - 16]
+"
+    );
+  }
+
+  #[test]
+  fn dumps_struct_layout_and_fields() {
+    let program = check(
+      "struct Mixed { a: u8, b: i64, c: u16 }
+m := Mixed { a: 1, b: 2, c: 3 }
+exit(m.c)
+",
+      "test.pn",
+    )
+    .unwrap();
+
+    assert_eq!(
+      dump_ast(&program),
+      "struct Mixed, size 24, align 8
+  a: u8 at 0
+  b: i64 at 8
+  c: u16 at 16
+decl m: Mixed
+  struct: Mixed
+    a
+      lit 1: u8
+    b
+      lit 2: i64
+    c
+      lit 3: u16
+exit
+  field c: u16
+    var m: Mixed
+"
+    );
+  }
+}
@@ -0,0 +1,343 @@
+use super::*;
+
+/// Address the executable is loaded at, the usual base for static x86_64
+/// executables.
+const BASE: u64 = 0x400000;
+const EHDR_SIZE: u64 = 64;
+const PHDR_SIZE: u64 = 56;
+
+/// Builds a static ELF64 executable for x86_64 linux running `image`, see
+/// `Object::image`, starting at `entry` bytes into it.
+///
+/// The file is mapped as a single readable and executable segment: the ELF
+/// and program headers followed by `image`. There are no section headers or
+/// symbols, like an executable linked with `ld -s`.
+pub fn write_executable(image: &[u8], entry: usize) -> Vec<u8> {
+  let headers = EHDR_SIZE + PHDR_SIZE;
+  let size = headers + image.len() as u64;
+  let mut out = Vec::with_capacity(size as usize);
+
+  // ELF header
+  out.extend(b"\x7fELF");
+  out.push(2); // 64-bit
+  out.push(1); // little endian
+  out.push(1); // ELF version
+  out.push(0); // System V ABI
+  out.extend([0; 8]);
+  out.extend(2u16.to_le_bytes()); // ET_EXEC
+  out.extend(62u16.to_le_bytes()); // EM_X86_64
+  out.extend(1u32.to_le_bytes());
+  out.extend((BASE + headers + entry as u64).to_le_bytes());
+  out.extend(EHDR_SIZE.to_le_bytes()); // program headers
+  out.extend(0u64.to_le_bytes()); // section headers
+  out.extend(0u32.to_le_bytes()); // flags
+  out.extend((EHDR_SIZE as u16).to_le_bytes());
+  out.extend((PHDR_SIZE as u16).to_le_bytes());
+  out.extend(1u16.to_le_bytes()); // program header count
+  out.extend(64u16.to_le_bytes()); // section header size
+  out.extend(0u16.to_le_bytes()); // section header count
+  out.extend(0u16.to_le_bytes()); // section name table index
+
+  // program header
+  out.extend(1u32.to_le_bytes()); // PT_LOAD
+  out.extend(5u32.to_le_bytes()); // PF_R | PF_X
+  out.extend(0u64.to_le_bytes()); // file offset
+  out.extend(BASE.to_le_bytes()); // virtual address
+  out.extend(BASE.to_le_bytes()); // physical address
+  out.extend(size.to_le_bytes()); // size in the file
+  out.extend(size.to_le_bytes()); // size in memory
+  out.extend(0x1000u64.to_le_bytes()); // alignment
+
+  out.extend(image);
+  out
+}
+
+/// Appends a section header.
+#[allow(clippy::too_many_arguments)]
+fn section_header(
+  out: &mut Vec<u8>,
+  name: u32,
+  ty: u32,
+  flags: u64,
+  offset: u64,
+  size: u64,
+  link: u32,
+  info: u32,
+  align: u64,
+  entsize: u64,
+) {
+  out.extend(name.to_le_bytes());
+  out.extend(ty.to_le_bytes());
+  out.extend(flags.to_le_bytes());
+  out.extend(0u64.to_le_bytes()); // address
+  out.extend(offset.to_le_bytes());
+  out.extend(size.to_le_bytes());
+  out.extend(link.to_le_bytes());
+  out.extend(info.to_le_bytes());
+  out.extend(align.to_le_bytes());
+  out.extend(entsize.to_le_bytes());
+}
+
+/// Builds an ELF64 relocatable object file holding the `.text` and `.rodata`
+/// of `object`, like `nasm -f elf64` would, so it can be inspected with
+/// `objdump` or linked with `ld`.
+///
+/// Jumps and calls are already resolved, references from `.text` to `.rodata`
+/// get `R_X86_64_PC32` relocations against the `.rodata` section symbol since
+/// the linker may place the sections apart. Labels not starting with `.`
+/// become symbols. `_start` and the `pn_` functions are global so the object
+/// can be linked with C code calling them, the runtime helpers stay local.
+pub fn write_object(object: &Object) -> Vec<u8> {
+  let mut labels = object
+    .labels
+    .iter()
+    .filter(|(name, _)| !name.starts_with('.'))
+    .map(|(name, place)| (name.as_str(), *place))
+    .collect::<Vec<_>>();
+  let is_global = |name: &str| name == "_start" || name.starts_with("pn_");
+  // locals first as the symbol table requires, in address order
+  labels.sort_by_key(|(name, place)| (is_global(name), *place, *name));
+
+  let mut strtab = vec![0];
+  let mut symtab = vec![0; 24];
+  // the .rodata section symbol, which relocations refer to
+  symtab.extend(0u32.to_le_bytes());
+  symtab.push(0x03); // local, STT_SECTION
+  symtab.push(0);
+  symtab.extend(2u16.to_le_bytes());
+  symtab.extend([0; 16]);
+  for (name, (section, offset)) in labels.iter() {
+    let index: u16 = match section {
+      Section::Text => 1,
+      Section::Rodata => 2,
+    };
+    symtab.extend((strtab.len() as u32).to_le_bytes());
+    symtab.push(if is_global(name) { 0x10 } else { 0x00 }); // binding, no type
+    symtab.push(0); // default visibility
+    symtab.extend(index.to_le_bytes());
+    symtab.extend((*offset as u64).to_le_bytes());
+    symtab.extend(0u64.to_le_bytes()); // size
+    strtab.extend(name.as_bytes());
+    strtab.push(0);
+  }
+  let first_global = 2 + labels.iter().filter(|(name, _)| !is_global(name)).count();
+
+  let mut rela = Vec::new();
+  for (pos, label) in object.relocations.iter() {
+    let (_, offset) = object.labels[label];
+    rela.extend((*pos as u64).to_le_bytes());
+    rela.extend((1u64 << 32 | 2).to_le_bytes()); // symbol 1, R_X86_64_PC32
+                                                 // the displacement is relative to its own end
+    rela.extend((offset as i64 - 4).to_le_bytes());
+  }
+
+  let mut shstrtab = vec![0];
+  let mut names = Vec::new();
+  for name in [
+    ".text",
+    ".rodata",
+    ".rela.text",
+    ".symtab",
+    ".strtab",
+    ".shstrtab",
+  ] {
+    names.push(shstrtab.len() as u32);
+    shstrtab.extend(name.as_bytes());
+    shstrtab.push(0);
+  }
+
+  // file layout: header, .text, .rodata, .rela.text, .symtab, .strtab,
+  // .shstrtab, section headers
+  let text_offset = EHDR_SIZE;
+  let rodata_file_offset = (text_offset + object.text.len() as u64).next_multiple_of(8);
+  let rela_offset = (rodata_file_offset + object.rodata.len() as u64).next_multiple_of(8);
+  let symtab_offset = rela_offset + rela.len() as u64;
+  let strtab_offset = symtab_offset + symtab.len() as u64;
+  let shstrtab_offset = strtab_offset + strtab.len() as u64;
+  let shoff = (shstrtab_offset + shstrtab.len() as u64).next_multiple_of(8);
+
+  let mut out = Vec::new();
+
+  // ELF header
+  out.extend(b"\x7fELF");
+  out.push(2); // 64-bit
+  out.push(1); // little endian
+  out.push(1); // ELF version
+  out.push(0); // System V ABI
+  out.extend([0; 8]);
+  out.extend(1u16.to_le_bytes()); // ET_REL
+  out.extend(62u16.to_le_bytes()); // EM_X86_64
+  out.extend(1u32.to_le_bytes());
+  out.extend(0u64.to_le_bytes()); // entry
+  out.extend(0u64.to_le_bytes()); // program headers
+  out.extend(shoff.to_le_bytes()); // section headers
+  out.extend(0u32.to_le_bytes()); // flags
+  out.extend((EHDR_SIZE as u16).to_le_bytes());
+  out.extend(0u16.to_le_bytes()); // program header size
+  out.extend(0u16.to_le_bytes()); // program header count
+  out.extend(64u16.to_le_bytes()); // section header size
+  out.extend(7u16.to_le_bytes()); // section header count
+  out.extend(6u16.to_le_bytes()); // section name table index
+
+  out.extend(&object.text);
+  out.resize(rodata_file_offset as usize, 0);
+  out.extend(&object.rodata);
+  out.resize(rela_offset as usize, 0);
+  out.extend(&rela);
+  out.extend(&symtab);
+  out.extend(&strtab);
+  out.extend(&shstrtab);
+  out.resize(shoff as usize, 0);
+
+  section_header(&mut out, 0, 0, 0, 0, 0, 0, 0, 0, 0);
+  // .text: SHT_PROGBITS, SHF_ALLOC | SHF_EXECINSTR
+  section_header(
+    &mut out,
+    names[0],
+    1,
+    6,
+    text_offset,
+    object.text.len() as u64,
+    0,
+    0,
+    16,
+    0,
+  );
+  // .rodata: SHT_PROGBITS, SHF_ALLOC
+  section_header(
+    &mut out,
+    names[1],
+    1,
+    2,
+    rodata_file_offset,
+    object.rodata.len() as u64,
+    0,
+    0,
+    8,
+    0,
+  );
+  // .rela.text: SHT_RELA, SHF_INFO_LINK, with symbols from .symtab applying
+  // to .text
+  section_header(
+    &mut out,
+    names[2],
+    4,
+    0x40,
+    rela_offset,
+    rela.len() as u64,
+    4,
+    1,
+    8,
+    24,
+  );
+  // .symtab: SHT_SYMTAB linked to .strtab
+  section_header(
+    &mut out,
+    names[3],
+    2,
+    0,
+    symtab_offset,
+    symtab.len() as u64,
+    5,
+    first_global as u32,
+    8,
+    24,
+  );
+  // .strtab and .shstrtab: SHT_STRTAB
+  section_header(
+    &mut out,
+    names[4],
+    3,
+    0,
+    strtab_offset,
+    strtab.len() as u64,
+    0,
+    0,
+    1,
+    0,
+  );
+  section_header(
+    &mut out,
+    names[5],
+    3,
+    0,
+    shstrtab_offset,
+    shstrtab.len() as u64,
+    0,
+    0,
+    1,
+    0,
+  );
+
+  out
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn u16_at(data: &[u8], pos: usize) -> usize {
+    u16::from_le_bytes(data[pos..pos + 2].try_into().unwrap()) as usize
+  }
+
+  fn u32_at(data: &[u8], pos: usize) -> usize {
+    u32::from_le_bytes(data[pos..pos + 4].try_into().unwrap()) as usize
+  }
+
+  fn u64_at(data: &[u8], pos: usize) -> usize {
+    u64::from_le_bytes(data[pos..pos + 8].try_into().unwrap()) as usize
+  }
+
+  /// Names of the symbols in the object file `data`, with whether they are
+  /// global.
+  fn symbols(data: &[u8]) -> Vec<(String, bool)> {
+    let sections = (0..u16_at(data, 0x3c))
+      .map(|i| u64_at(data, 0x28) + i * 64)
+      .collect::<Vec<_>>();
+    let symtab = *sections
+      .iter()
+      .find(|&&sh| u32_at(data, sh + 4) == 2)
+      .unwrap();
+    let strtab = u64_at(data, sections[u32_at(data, symtab + 40)] + 24);
+
+    (u64_at(data, symtab + 24)..)
+      .step_by(24)
+      .take(u64_at(data, symtab + 32) / 24)
+      .skip(2)
+      .map(|sym| {
+        let name = &data[strtab + u32_at(data, sym)..];
+        let name = &name[..name.iter().position(|&b| b == 0).unwrap()];
+        (
+          String::from_utf8(name.to_vec()).unwrap(),
+          data[sym + 4] >> 4 == 1,
+        )
+      })
+      .collect()
+  }
+
+  #[test]
+  fn functions_are_global_symbols() {
+    let object = Assembler::new(
+      "global _start
+_start:
+  call pn_f
+  call _helper
+.L0:
+  ret
+global pn_f
+pn_f:
+  ret
+_helper:
+  ret
+",
+    )
+    .assemble()
+    .unwrap();
+
+    assert_eq!(symbols(&write_object(&object)), [
+      ("_helper".to_string(), false),
+      ("_start".to_string(), true),
+      ("pn_f".to_string(), true),
+    ]);
+  }
+}
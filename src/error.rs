@@ -1,60 +1,290 @@
-pub trait Error {
-  fn new(pos: usize, msg: &str) -> Self;
-  fn pos(&self) -> usize;
+use super::*;
+
+pub trait Error: Sized {
+  fn new(span: Span, msg: &str) -> Self;
+  fn span(&self) -> Span;
   fn msg(&self) -> String;
-  fn to_string(&self) -> String;
+  fn notes(&self) -> &[Note];
+  fn push_note(&mut self, note: Note);
+  /// What produced the error, e.g. "Parser error".
+  fn kind(&self) -> &'static str;
+
+  fn with_note(mut self, msg: &str) -> Self {
+    self.push_note(Note::Note(msg.to_string()));
+    self
+  }
+
+  fn with_help(mut self, msg: &str) -> Self {
+    self.push_note(Note::Help(msg.to_string()));
+    self
+  }
+
+  fn render(&self, map: &SourceMap) -> String {
+    map.render(self.kind(), self.span(), &self.msg(), self.notes())
+  }
 }
 
 #[derive(Debug)]
 pub struct LexerError {
-  pos: usize,
-  msg: String,
+  span:  Span,
+  msg:   String,
+  notes: Vec<Note>,
 }
 
 impl Error for LexerError {
-  fn new(pos: usize, msg: &str) -> Self {
+  fn new(span: Span, msg: &str) -> Self {
     Self {
-      pos,
+      span,
       msg: msg.to_string(),
+      notes: Vec::new(),
     }
   }
 
-  fn pos(&self) -> usize {
-    self.pos
+  fn span(&self) -> Span {
+    self.span
   }
 
   fn msg(&self) -> String {
     self.msg.clone()
   }
 
-  fn to_string(&self) -> String {
-    format!("Lexer error at position {}: {}", self.pos, self.msg)
+  fn notes(&self) -> &[Note] {
+    &self.notes
+  }
+
+  fn push_note(&mut self, note: Note) {
+    self.notes.push(note);
+  }
+
+  fn kind(&self) -> &'static str {
+    "Lexer error"
   }
 }
 
 #[derive(Debug)]
 pub struct ParserError {
-  pos: usize,
-  msg: String,
+  span:  Span,
+  msg:   String,
+  notes: Vec<Note>,
 }
 
 impl Error for ParserError {
-  fn new(pos: usize, msg: &str) -> Self {
+  fn new(span: Span, msg: &str) -> Self {
+    Self {
+      span,
+      msg: msg.to_string(),
+      notes: Vec::new(),
+    }
+  }
+
+  fn span(&self) -> Span {
+    self.span
+  }
+
+  fn msg(&self) -> String {
+    self.msg.clone()
+  }
+
+  fn notes(&self) -> &[Note] {
+    &self.notes
+  }
+
+  fn push_note(&mut self, note: Note) {
+    self.notes.push(note);
+  }
+
+  fn kind(&self) -> &'static str {
+    "Parser error"
+  }
+}
+
+#[derive(Debug)]
+pub struct TypeError {
+  span:  Span,
+  msg:   String,
+  notes: Vec<Note>,
+}
+
+impl Error for TypeError {
+  fn new(span: Span, msg: &str) -> Self {
     Self {
-      pos,
+      span,
       msg: msg.to_string(),
+      notes: Vec::new(),
     }
   }
 
-  fn pos(&self) -> usize {
-    self.pos
+  fn span(&self) -> Span {
+    self.span
   }
 
   fn msg(&self) -> String {
     self.msg.clone()
   }
 
-  fn to_string(&self) -> String {
-    format!("Parser error at position {}: {}", self.pos, self.msg)
+  fn notes(&self) -> &[Note] {
+    &self.notes
+  }
+
+  fn push_note(&mut self, note: Note) {
+    self.notes.push(note);
+  }
+
+  fn kind(&self) -> &'static str {
+    "Type error"
+  }
+}
+
+#[derive(Debug)]
+pub struct RuntimeError {
+  msg:  String,
+  code: u8,
+}
+
+impl RuntimeError {
+  pub fn new(msg: &str) -> Self {
+    Self {
+      msg:  msg.to_string(),
+      code: 1,
+    }
+  }
+
+  /// Indexing an array out of bounds, which exits with 101 like a Rust panic.
+  /// The generated code reports it the same way.
+  pub fn out_of_bounds() -> Self {
+    Self::new("Index out of bounds").with_code(101)
+  }
+
+  /// Dividing or taking the remainder by zero. The generated code reports it
+  /// the same way.
+  pub fn division_by_zero() -> Self {
+    Self::new("Division by zero")
+  }
+
+  /// Dividing the smallest `i64` by -1, whose quotient doesn't fit. The
+  /// generated code reports it the same way.
+  pub fn division_overflow() -> Self {
+    Self::new("Division overflow")
+  }
+
+  /// Sets the exit code of the process, 1 by default.
+  pub fn with_code(mut self, code: u8) -> Self {
+    self.code = code;
+    self
+  }
+
+  pub fn msg(&self) -> String {
+    self.msg.clone()
+  }
+
+  pub fn code(&self) -> u8 {
+    self.code
+  }
+}
+
+impl std::fmt::Display for RuntimeError {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    write!(f, "Runtime error: {}", self.msg)
+  }
+}
+
+#[derive(Debug)]
+pub struct AssemblerError {
+  line: usize,
+  msg:  String,
+}
+
+impl AssemblerError {
+  pub fn new(line: usize, msg: &str) -> Self {
+    Self {
+      line,
+      msg: msg.to_string(),
+    }
+  }
+
+  pub fn msg(&self) -> String {
+    self.msg.clone()
+  }
+}
+
+impl std::fmt::Display for AssemblerError {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    write!(f, "Assembler error: line {}: {}", self.line, self.msg)
+  }
+}
+
+/// An error reported by [`compile`], already rendered against the source.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+  /// What produced the error, e.g. "Parser error".
+  pub kind:     &'static str,
+  pub msg:      String,
+  /// Where in the source the error is, if it comes from it.
+  pub span:     Option<Span>,
+  pub notes:    Vec<Note>,
+  pub rendered: String,
+}
+
+impl Diagnostic {
+  pub fn from_error<E: Error>(err: &E, map: &SourceMap) -> Self {
+    Self {
+      kind:     err.kind(),
+      msg:      err.msg(),
+      span:     Some(err.span()),
+      notes:    err.notes().to_vec(),
+      rendered: err.render(map),
+    }
+  }
+}
+
+impl From<AssemblerError> for Diagnostic {
+  fn from(err: AssemblerError) -> Self {
+    Self {
+      kind:     "Assembler error",
+      msg:      err.msg(),
+      span:     None,
+      notes:    Vec::new(),
+      rendered: err.to_string(),
+    }
+  }
+}
+
+/// Every error of the stage that failed.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Diagnostics {
+  pub errors: Vec<Diagnostic>,
+}
+
+impl Diagnostics {
+  pub fn from_errors<E: Error>(errs: &[E], map: &SourceMap) -> Self {
+    Self {
+      errors: errs
+        .iter()
+        .map(|err| Diagnostic::from_error(err, map))
+        .collect(),
+    }
+  }
+}
+
+impl From<AssemblerError> for Diagnostics {
+  fn from(err: AssemblerError) -> Self {
+    Self {
+      errors: vec![err.into()],
+    }
+  }
+}
+
+/// Renders every error followed by a summary line.
+impl std::fmt::Display for Diagnostics {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    for err in self.errors.iter() {
+      write!(f, "{}\n\n", err.rendered)?;
+    }
+    write!(
+      f,
+      "aborting due to {} error{}",
+      self.errors.len(),
+      if self.errors.len() == 1 { "" } else { "s" }
+    )
   }
 }
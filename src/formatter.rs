@@ -0,0 +1,339 @@
+use std::collections::HashSet;
+
+use super::*;
+
+fn is_symbol(token: &Token, sym: Symbol) -> bool {
+  matches!(token, Token::Symbol { val, .. } if *val == sym)
+}
+
+fn is_line_comment(token: &Token) -> bool {
+  matches!(token, Token::Comment { val, .. } if val.starts_with("//"))
+}
+
+/// Reformats source code from its tokens: one statement per line, blocks
+/// indented by two spaces, single spaces around binary operators and at most
+/// one blank line in a row. Identifiers, literals and comments are copied from
+/// the source, so `0xff` stays `0xff`. Comments stay at the end of their line
+/// or on their own line. Array literals and conditional expressions are kept on
+/// one line, struct and enum declarations, struct literals and matches too
+/// unless they're written over several lines, then they get one field, variant
+/// or arm per line.
+pub struct Formatter {
+  source: String,
+  tokens: Vec<Token>,
+  output: String,
+  depth:  usize,
+}
+
+impl Formatter {
+  pub fn new(source: &str, tokens: Vec<Token>) -> Self {
+    Self {
+      source: source.to_string(),
+      tokens,
+      output: String::new(),
+      depth: 0,
+    }
+  }
+
+  fn line_break(&mut self, blank: bool) {
+    self.output.push('\n');
+    if blank {
+      self.output.push('\n');
+    }
+    self.output += &"  ".repeat(self.depth);
+  }
+
+  /// Whether a space goes between `prev` and `curr` on the same line.
+  /// `unary` tells if `prev` is a unary operator.
+  fn space(prev: &Token, curr: &Token, unary: bool) -> bool {
+    if matches!(prev, Token::Comment { .. }) || matches!(curr, Token::Comment { .. }) {
+      return true;
+    }
+    if unary
+      || is_symbol(prev, Symbol::LeftParen)
+      || is_symbol(prev, Symbol::LeftBracket)
+      || is_symbol(prev, Symbol::Dot)
+      || is_symbol(curr, Symbol::Dot)
+      || is_symbol(prev, Symbol::ColonColon)
+      || is_symbol(curr, Symbol::ColonColon)
+    {
+      return false;
+    }
+    if is_symbol(curr, Symbol::RightParen)
+      || is_symbol(curr, Symbol::RightBracket)
+      || is_symbol(curr, Symbol::Comma)
+      || is_symbol(curr, Symbol::Colon)
+      || is_symbol(curr, Symbol::Semicolon)
+    {
+      return false;
+    }
+    if is_symbol(curr, Symbol::LeftBracket) {
+      // indexing, but not `:= [...]` or `: [...]`
+      return !matches!(
+        prev,
+        Token::Identifier { .. }
+          | Token::Symbol {
+            val: Symbol::RightParen | Symbol::RightBracket,
+            ..
+          }
+      );
+    }
+    if is_symbol(curr, Symbol::LeftParen) {
+      // calls and exit(...), but not `if (...)` or `a + (...)`
+      return !matches!(
+        prev,
+        Token::Identifier { .. }
+          | Token::Keyword {
+            val: Keyword::Exit,
+            ..
+          }
+      );
+    }
+    true
+  }
+
+  pub fn format(&mut self) -> String {
+    self.output.clear();
+    self.depth = 0;
+
+    let tokens = std::mem::take(&mut self.tokens);
+    // a struct's name followed by `{` starts a literal of it, or its
+    // declaration like an enum's
+    let types = tokens
+      .windows(2)
+      .filter_map(|pair| match pair {
+        [Token::Keyword {
+          val: Keyword::Struct | Keyword::Enum,
+          ..
+        }, Token::Identifier { val, .. }] => Some(val),
+        _ => None,
+      })
+      .collect::<HashSet<_>>();
+    // last token written, and the last one that isn't a comment
+    let mut last: Option<&Token> = None;
+    let mut prev: Option<&Token> = None;
+    let mut unary = false;
+    let mut parens = 0usize;
+    let mut newlines = 0;
+    // whether each open brace is a conditional expression's or a struct's,
+    // kept on one line, and whether the next one will be
+    let mut braces: Vec<bool> = vec![];
+    let mut value_if = false;
+    let mut closed_inline = false;
+    // whether the next `{` opens the arms of a match
+    let mut arms = false;
+
+    for (i, token) in tokens.iter().enumerate() {
+      match token {
+        Token::Newline { .. } => {
+          // expressions may span lines inside parentheses, brackets or a
+          // conditional expression's braces, join them
+          if parens == 0 && braces.last() != Some(&true) {
+            newlines += 1;
+          }
+          continue;
+        },
+        Token::EOF { .. } => break,
+        // the elements or fields are joined on one line, a trailing comma
+        // isn't needed
+        _ if is_symbol(token, Symbol::Comma)
+          && tokens[i + 1..]
+            .iter()
+            .find(|token| !matches!(token, Token::Newline { .. }))
+            .is_some_and(|token| {
+              is_symbol(token, Symbol::RightBracket)
+                || is_symbol(token, Symbol::RightBrace) && braces.last() == Some(&true)
+            }) =>
+        {
+          continue;
+        },
+        _ => (),
+      }
+
+      let inline = braces.last() == Some(&true);
+      if is_symbol(token, Symbol::RightBrace) && !braces.pop().unwrap_or(false) {
+        self.depth = self.depth.saturating_sub(1);
+      }
+
+      if let Some(last) = last {
+        let is_else = matches!(token, Token::Keyword {
+          val: Keyword::Else,
+          ..
+        });
+        let is_comment = matches!(token, Token::Comment { .. });
+
+        if is_comment && newlines == 0 {
+          // a comment after code stays on its line
+          self.output.push(' ');
+        } else if is_symbol(last, Symbol::RightBrace) && is_else {
+          self.output.push(' ');
+        } else if !inline
+          && (is_symbol(last, Symbol::LeftBrace) || is_symbol(token, Symbol::RightBrace))
+        {
+          // no blank lines at the start or end of a block
+          self.line_break(false);
+        } else if parens > 0 && is_line_comment(last) {
+          // a line comment ends the line even inside parentheses, what follows
+          // is indented as a continuation
+          self.depth += 1;
+          self.line_break(false);
+          self.depth -= 1;
+        } else if newlines > 0
+          || is_line_comment(last)
+          || is_symbol(last, Symbol::RightBrace) && !matches!(token, Token::Symbol { .. })
+        {
+          // an operator after a conditional expression's `}` continues it
+          self.line_break(newlines > 1);
+        } else if Self::space(last, token, unary) {
+          self.output.push(' ');
+        }
+      }
+
+      self.output += &self.source[token.start()..token.end()];
+      last = Some(token);
+      let line_start = newlines > 0;
+      newlines = 0;
+      if let Token::Comment { .. } = token {
+        continue;
+      }
+
+      match token {
+        Token::Symbol {
+          val: Symbol::LeftBrace,
+          ..
+        } => {
+          let inline = value_if
+            || (arms || matches!(prev, Some(Token::Identifier { val, .. }) if types.contains(val)))
+              && !matches!(tokens.get(i + 1), Some(Token::Newline { .. }));
+          braces.push(inline);
+          if !inline {
+            self.depth += 1;
+          }
+          value_if = false;
+          arms = false;
+        },
+        Token::Symbol {
+          val: Symbol::RightBrace,
+          ..
+        } => closed_inline = inline,
+        // an `if` is a statement at the start of a line or block, or after the
+        // `else` of one, and a value anywhere else
+        Token::Keyword {
+          val: Keyword::If, ..
+        } => {
+          value_if = match prev {
+            None => false,
+            _ if line_start => false,
+            Some(Token::Keyword {
+              val: Keyword::Else, ..
+            }) => closed_inline,
+            Some(Token::Symbol {
+              val: Symbol::LeftBrace,
+              ..
+            }) => inline,
+            Some(Token::Symbol {
+              val: Symbol::RightBrace,
+              ..
+            }) => closed_inline,
+            _ => true,
+          }
+        },
+        Token::Keyword {
+          val: Keyword::Else, ..
+        } => value_if = closed_inline,
+        Token::Keyword {
+          val: Keyword::Match,
+          ..
+        } => arms = true,
+        Token::Symbol {
+          val: Symbol::LeftParen | Symbol::LeftBracket,
+          ..
+        } => parens += 1,
+        Token::Symbol {
+          val: Symbol::RightParen | Symbol::RightBracket,
+          ..
+        } => parens = parens.saturating_sub(1),
+        _ => (),
+      }
+
+      // '-' and '*' are unary unless they follow an operand on the same line
+      unary = match token {
+        Token::Symbol {
+          val: Symbol::Bang | Symbol::Amp,
+          ..
+        } => true,
+        Token::Symbol {
+          val: Symbol::Minus | Symbol::Star,
+          ..
+        } => {
+          line_start
+            || !matches!(
+              prev,
+              Some(
+                Token::Identifier { .. }
+                  | Token::Literal { .. }
+                  | Token::Symbol {
+                    val: Symbol::RightParen | Symbol::RightBracket | Symbol::RightBrace,
+                    ..
+                  }
+              )
+            )
+        },
+        _ => false,
+      };
+
+      prev = Some(token);
+    }
+
+    if !self.output.is_empty() {
+      self.output.push('\n');
+    }
+
+    self.tokens = tokens;
+    self.output.clone()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn comments_are_skipped_and_kept_by_the_formatter() {
+    let source = "/* header /* nested */ */
+x := 4 / 2 // half
+fn f(a, // first
+b) {
+// inside
+return a+b
+}
+exit(f(x,1))
+";
+    assert!(compile(source, &CompileOptions::default()).is_ok());
+
+    assert_eq!(
+      format(source, "test.pn").unwrap(),
+      "/* header /* nested */ */
+x := 4 / 2 // half
+fn f(a, // first
+  b) {
+  // inside
+  return a + b
+}
+exit(f(x, 1))
+"
+    );
+  }
+
+  #[test]
+  fn sources_with_syntax_errors_are_not_formatted() {
+    let diagnostics = format("x := (1 + 2\ny := 3\nexit(y)\n", "test.pn").unwrap_err();
+
+    assert_eq!(diagnostics.errors[0].kind, "Parser error");
+    // type errors don't stop it
+    assert_eq!(
+      format("x: bool := 1\nexit( x )\n", "test.pn").unwrap(),
+      "x: bool := 1\nexit(x)\n"
+    );
+  }
+}
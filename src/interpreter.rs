@@ -0,0 +1,732 @@
+use std::{io::Write, ops::Range, rc::Rc};
+
+use super::*;
+
+/// Calls deeper than this abort instead of overflowing the interpreter's own
+/// stack.
+const MAX_CALL_DEPTH: usize = 10_000;
+
+/// Stack each call takes in the interpreter, through `call`, `exec_block`,
+/// `exec_stmt` and `eval_expr`, with room to spare: about 0.6 KiB for
+/// `return n + f(n - 1)` and 2 KiB for a call nested in a `while`, `if` and
+/// `match`, in release builds. Debug builds take up to twelve times as much.
+const STACK_PER_CALL: usize = if cfg!(debug_assertions) {
+  32 * 1024
+} else {
+  4 * 1024
+};
+
+/// Stack [`Interpreter::interpret`] needs to reach `MAX_CALL_DEPTH` calls, more
+/// than the main thread has, so it's run on a thread with this much.
+pub const INTERPRETER_STACK_SIZE: usize = MAX_CALL_DEPTH * STACK_PER_CALL;
+
+/// Bytes of addresses each frame gets, see [`Interpreter::address`].
+const FRAME_SIZE: i64 = 1 << 32;
+
+/// What the interpreter should do after executing a statement.
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Flow {
+  Next,
+  Return(i64),
+}
+
+/// Reasons evaluation stops early, unwinding through any expressions and
+/// calls in progress. Errors are boxed to keep the results of evaluation small.
+#[derive(Debug)]
+enum Halt {
+  Exit(i64),
+  Error(Box<RuntimeError>),
+}
+
+impl From<RuntimeError> for Halt {
+  fn from(err: RuntimeError) -> Self {
+    Halt::Error(Box::new(err))
+  }
+}
+
+/// Values of the variables of one call, by their symbol table offset.
+struct Frame {
+  /// Offsets of the bytes of the variables, negative for parameters passed on
+  /// the stack.
+  offsets: Range<isize>,
+  slots:   Vec<i64>,
+}
+
+impl Frame {
+  fn new(offsets: Range<isize>) -> Self {
+    let len = (offsets.end - offsets.start) as usize;
+    Self {
+      offsets,
+      slots: vec![0; len],
+    }
+  }
+
+  /// Value of the slot at `offset`, 0 if nothing was written there.
+  fn get(&self, offset: isize) -> i64 {
+    if self.offsets.contains(&offset) {
+      self.slots[(offset - self.offsets.start) as usize]
+    } else {
+      0
+    }
+  }
+
+  /// Writes `val` to the slot at `offset`, dropped if it's outside the frame.
+  fn set(&mut self, offset: isize, val: i64) {
+    if self.offsets.contains(&offset) {
+      self.slots[(offset - self.offsets.start) as usize] = val;
+    }
+  }
+}
+
+/// Tree-walking interpreter over a parsed [`Program`].
+///
+/// Variables live in slots of their frame indexed by their symbol table
+/// offset, the same layout `Generator` uses for `[rbp - offset]`. Values are
+/// kept truncated to their type and extended to 64 bits like in the registers
+/// of the generated code, so arithmetic wraps at the width of the type. A
+/// `str` is the index of the string in `strings`, a pointer, array, struct or
+/// enum the address of its first byte, with its elements, fields or tag and
+/// values at increasing addresses.
+pub struct Interpreter {
+  /// Shared with the calls in progress, which borrow their function from it.
+  program: Rc<Program>,
+  /// Offsets of the frame of each function, by the table of its body, and of
+  /// the top level at 0.
+  layouts: Vec<Range<isize>>,
+  frames:  Vec<Frame>,
+  strings: Vec<String>,
+}
+
+impl Interpreter {
+  pub fn new(program: Program) -> Self {
+    let mut layouts = vec![0..0; program.symbol_tables.len()];
+    for (i, table) in program.symbol_tables.iter().enumerate() {
+      let layout = &mut layouts[program.frame_root(i)];
+      for entry in table.entries.iter() {
+        // `[rbp - offset]` is the first byte, the others are at lower offsets
+        let first = entry.offset - entry.ty.size() as isize + 1;
+        *layout = layout.start.min(first)..layout.end.max(entry.offset + 1);
+      }
+    }
+
+    Self {
+      program: Rc::new(program),
+      layouts,
+      frames: Vec::new(),
+      strings: Vec::new(),
+    }
+  }
+
+  fn intern(&mut self, string: &str) -> i64 {
+    let index = match self.strings.iter().position(|s| s == string) {
+      Some(index) => index,
+      None => {
+        self.strings.push(string.to_string());
+        self.strings.len() - 1
+      },
+    };
+    index as i64
+  }
+
+  /// Writes `val` of type `ty` to stdout like the generated code, and returns
+  /// the number of bytes written.
+  fn print(&self, ty: &Type, val: i64) -> Result<i64, Halt> {
+    let text = match ty {
+      Type::Str => self.strings[val as usize].clone(),
+      Type::Bool => (val != 0).to_string(),
+      _ if ty.is_signed() => val.to_string(),
+      _ => (val as u64).to_string(),
+    };
+    match std::io::stdout().write_all(text.as_bytes()) {
+      Ok(()) => Ok(text.len() as i64),
+      Err(err) => Err(RuntimeError::new(&format!("Failed to write to stdout: {}", err)).into()),
+    }
+  }
+
+  /// Address of the slot at `offset` in the frame at `index`. Each frame gets
+  /// its own [`FRAME_SIZE`] addresses, and like `[rbp - offset]` they decrease
+  /// as offsets grow, so pointers into any frame can be compared and moved
+  /// like in the generated code.
+  fn address(index: usize, offset: isize) -> i64 {
+    (index as i64 + 1) * FRAME_SIZE - offset as i64
+  }
+
+  /// Frame and offset of the slot at `addr`, if it's in a frame.
+  fn slot(&self, addr: i64) -> Option<(usize, isize)> {
+    let index = addr.wrapping_add(FRAME_SIZE / 2).div_euclid(FRAME_SIZE) - 1;
+    if index < 0 || index as usize >= self.frames.len() {
+      return None;
+    }
+    Some((
+      index as usize,
+      Self::address(index as usize, 0).wrapping_sub(addr) as isize,
+    ))
+  }
+
+  /// Value of type `ty` at `addr`, arrays and structs are left where they
+  /// are. Addresses outside every frame read 0.
+  fn read(&self, ty: &Type, addr: i64) -> i64 {
+    if !ty.is_scalar() {
+      return addr;
+    }
+    match self.slot(addr) {
+      Some((index, offset)) => self.frames[index].get(offset),
+      None => 0,
+    }
+  }
+
+  /// Stores `val` of type `ty` at `addr`, copying every element of arrays,
+  /// field of structs and the tag and values of enums. Writes through
+  /// dangling pointers are dropped.
+  fn write(&mut self, ty: &Type, addr: i64, val: i64) {
+    match ty {
+      Type::Array(elem, len) => {
+        let size = elem.size() as i64;
+        for i in 0..*len as i64 {
+          let val = self.read(elem, val + i * size);
+          self.write(elem, addr + i * size, val);
+        }
+      },
+      Type::Struct(def) => {
+        for field in def.fields().iter() {
+          let field_offset = field.offset as i64;
+          let val = self.read(&field.ty, val + field_offset);
+          self.write(&field.ty, addr + field_offset, val);
+        }
+      },
+      Type::Enum(def) => {
+        let tag = self.read(&Enum::TAG, val);
+        self.write(&Enum::TAG, addr, tag);
+        let variant = &def.variants[tag as usize];
+        for (ty, field_offset) in variant.fields.iter().zip(&variant.offsets) {
+          let field_offset = *field_offset as i64;
+          let val = self.read(ty, val + field_offset);
+          self.write(ty, addr + field_offset, val);
+        }
+      },
+      _ => {
+        if let Some((index, offset)) = self.slot(addr) {
+          self.frames[index].set(offset, ty.wrap(val));
+        }
+      },
+    }
+  }
+
+  /// Address of `var` in the current frame.
+  fn local(&self, var: &VarRef) -> i64 {
+    let offset = self.program.entry(var).offset;
+    Self::address(self.frames.len() - 1, offset)
+  }
+
+  /// Value of `var`, read from the current frame directly if it's a scalar.
+  fn load(&self, var: &VarRef) -> i64 {
+    let entry = self.program.entry(var);
+    if entry.ty.is_scalar() {
+      self.frames.last().unwrap().get(entry.offset)
+    } else {
+      self.local(var)
+    }
+  }
+
+  /// Stores `val` in `var`, written to the current frame directly if it's a
+  /// scalar.
+  fn store(&mut self, var: &VarRef, val: i64) {
+    let entry = self.program.entry(var);
+    if entry.ty.is_scalar() {
+      let val = entry.ty.wrap(val);
+      self.frames.last_mut().unwrap().set(entry.offset, val);
+    } else {
+      let program = self.program.clone();
+      self.write(&program.entry(var).ty, self.local(var), val);
+    }
+  }
+
+  /// Address of the element of `array` at `index`.
+  fn element(&mut self, array: &Expr, index: &Expr) -> Result<i64, Halt> {
+    let Type::Array(elem, len) = &array.ty else {
+      unreachable!("indexing {}", array.ty)
+    };
+    let addr = self.eval_expr(array)?;
+    // negative indexes are huge as unsigned, like in the generated code
+    let index = self.eval_expr(index)?;
+    if index as u64 >= *len as u64 {
+      return Err(RuntimeError::out_of_bounds().into());
+    }
+    Ok(addr + index * elem.size() as i64)
+  }
+
+  /// Address of `field` of the struct `expr`.
+  fn field(&mut self, expr: &Expr, name: &str) -> Result<i64, Halt> {
+    let Type::Struct(def) = &expr.ty else {
+      unreachable!("field of {}", expr.ty)
+    };
+    let field = def.field(name).unwrap();
+    Ok(self.eval_expr(expr)? + field.offset as i64)
+  }
+
+  /// Address of the place `expr` names.
+  fn place(&mut self, expr: &Expr) -> Result<i64, Halt> {
+    match &expr.kind {
+      ExprKind::Identifier(var) => Ok(self.local(var)),
+      ExprKind::Index(array, index) => self.element(array, index),
+      ExprKind::Field(inner, name) => self.field(inner, name),
+      ExprKind::Deref(inner) => self.eval_expr(inner),
+      _ => unreachable!("assignment to {:?}", expr.kind),
+    }
+  }
+
+  /// Evaluates the value of a match and gives the index of the first arm whose
+  /// pattern matches it, after binding the variables of the pattern.
+  fn match_arm<'a>(
+    &mut self,
+    value: &Expr,
+    patterns: impl Iterator<Item = &'a Pattern>,
+  ) -> Result<usize, Halt> {
+    let val = self.eval_expr(value)?;
+    let key = match value.ty {
+      Type::Enum(_) => self.read(&Enum::TAG, val),
+      _ => val,
+    };
+
+    for (i, pattern) in patterns.enumerate() {
+      match &pattern.kind {
+        PatternKind::Wildcard => return Ok(i),
+        PatternKind::Literal(lit) => {
+          let expected = match *lit {
+            Literal::Integer(i) => i,
+            Literal::Unsigned(u) => u as i64,
+            Literal::Bool(b) => b as i64,
+            Literal::String(_) => unreachable!("string pattern"),
+          };
+          if key == expected {
+            return Ok(i);
+          }
+        },
+        PatternKind::Variant(def, index, bindings) if key == *index as i64 => {
+          let variant = &def.variants[*index];
+          for (var, (ty, offset)) in bindings
+            .iter()
+            .zip(variant.fields.iter().zip(&variant.offsets))
+          {
+            if let Some(var) = var {
+              let val = self.read(ty, val + *offset as i64);
+              self.store(var, val);
+            }
+          }
+          return Ok(i);
+        },
+        PatternKind::Variant(..) => (),
+      }
+    }
+    unreachable!("no arm of the match matches {}", key)
+  }
+
+  fn call(&mut self, name: &str, args: &[Expr]) -> Result<i64, Halt> {
+    if self.frames.len() > MAX_CALL_DEPTH {
+      return Err(RuntimeError::new("Stack overflow").into());
+    }
+
+    let mut vals = Vec::new();
+    for arg in args.iter() {
+      vals.push(self.eval_expr(arg)?);
+    }
+
+    let program = self.program.clone();
+    let function = program.find_function(name).unwrap();
+
+    let layout = self.layouts[function.body.table].clone();
+    self.frames.push(Frame::new(layout));
+    for (i, ((name, _), val)) in function.params.iter().zip(vals).enumerate() {
+      let var = VarRef {
+        name:  name.clone(),
+        table: function.body.table,
+        index: i,
+      };
+      self.store(&var, val);
+    }
+
+    let flow = self.exec_block(&function.body);
+
+    self.frames.pop();
+
+    match flow? {
+      Flow::Return(val) => Ok(val),
+      Flow::Next => Ok(0),
+    }
+  }
+
+  /// `lhs op rhs` with a pointer on the left, scaled like in the generated
+  /// code.
+  fn eval_pointer_binary(&mut self, op: BinaryOp, lhs: &Expr, rhs: &Expr) -> Result<i64, Halt> {
+    let Type::Pointer(elem) = &lhs.ty else {
+      unreachable!("pointer operation on {}", lhs.ty)
+    };
+    let size = elem.size() as i64;
+    let integer = rhs.ty.is_integer();
+    let lhs = self.eval_expr(lhs)?;
+    let rhs = self.eval_expr(rhs)?;
+
+    Ok(match op {
+      BinaryOp::Add => lhs.wrapping_add(rhs.wrapping_mul(size)),
+      BinaryOp::Sub if integer => lhs.wrapping_sub(rhs.wrapping_mul(size)),
+      BinaryOp::Sub => lhs.wrapping_sub(rhs) / size,
+      BinaryOp::Eq => (lhs == rhs) as i64,
+      BinaryOp::Ne => (lhs != rhs) as i64,
+      BinaryOp::Lt => ((lhs as u64) < (rhs as u64)) as i64,
+      BinaryOp::Le => ((lhs as u64) <= (rhs as u64)) as i64,
+      BinaryOp::Gt => ((lhs as u64) > (rhs as u64)) as i64,
+      BinaryOp::Ge => ((lhs as u64) >= (rhs as u64)) as i64,
+      _ => unreachable!("'{}' on {}", op, elem),
+    })
+  }
+
+  fn eval_binary(&mut self, op: BinaryOp, lhs: &Expr, rhs: &Expr) -> Result<i64, Halt> {
+    if let Type::Pointer(_) = lhs.ty {
+      return self.eval_pointer_binary(op, lhs, rhs);
+    }
+    let signed = lhs.ty.is_signed();
+    let lhs = self.eval_operand(lhs)?;
+
+    match op {
+      BinaryOp::And if lhs == 0 => return Ok(0),
+      BinaryOp::Or if lhs != 0 => return Ok(1),
+      _ => (),
+    }
+
+    let rhs = self.eval_operand(rhs)?;
+
+    Ok(match op {
+      BinaryOp::Add => lhs.wrapping_add(rhs),
+      BinaryOp::Sub => lhs.wrapping_sub(rhs),
+      BinaryOp::Mul => lhs.wrapping_mul(rhs),
+      BinaryOp::Div | BinaryOp::Mod => {
+        if rhs == 0 {
+          return Err(RuntimeError::division_by_zero().into());
+        }
+        let res = match (op, signed) {
+          (BinaryOp::Div, true) => lhs.checked_div(rhs),
+          (BinaryOp::Div, false) => Some(((lhs as u64) / (rhs as u64)) as i64),
+          (_, true) => lhs.checked_rem(rhs),
+          (_, false) => Some(((lhs as u64) % (rhs as u64)) as i64),
+        };
+        match res {
+          Some(res) => res,
+          None => return Err(RuntimeError::division_overflow().into()),
+        }
+      },
+      BinaryOp::Eq => (lhs == rhs) as i64,
+      BinaryOp::Ne => (lhs != rhs) as i64,
+      BinaryOp::Lt if signed => (lhs < rhs) as i64,
+      BinaryOp::Le if signed => (lhs <= rhs) as i64,
+      BinaryOp::Gt if signed => (lhs > rhs) as i64,
+      BinaryOp::Ge if signed => (lhs >= rhs) as i64,
+      BinaryOp::Lt => ((lhs as u64) < (rhs as u64)) as i64,
+      BinaryOp::Le => ((lhs as u64) <= (rhs as u64)) as i64,
+      BinaryOp::Gt => ((lhs as u64) > (rhs as u64)) as i64,
+      BinaryOp::Ge => ((lhs as u64) >= (rhs as u64)) as i64,
+      BinaryOp::And | BinaryOp::Or => (rhs != 0) as i64,
+    })
+  }
+
+  /// [`Self::eval_expr`] with variables and integer literals, the most common
+  /// operands, evaluated without another call.
+  #[inline(always)]
+  fn eval_operand(&mut self, expr: &Expr) -> Result<i64, Halt> {
+    match &expr.kind {
+      ExprKind::Literal(Literal::Integer(i)) => Ok(expr.ty.wrap(*i)),
+      ExprKind::Identifier(var) => Ok(self.load(var)),
+      _ => self.eval_expr(expr),
+    }
+  }
+
+  fn eval_expr(&mut self, expr: &Expr) -> Result<i64, Halt> {
+    let val = match &expr.kind {
+      ExprKind::Literal(l) => match l {
+        Literal::Integer(i) => *i,
+        Literal::Unsigned(u) => *u as i64,
+        Literal::String(s) => self.intern(s),
+        Literal::Bool(b) => *b as i64,
+      },
+      ExprKind::Identifier(var) => self.load(var),
+      ExprKind::Binary(op, lhs, rhs) => self.eval_binary(*op, lhs, rhs)?,
+      ExprKind::Unary(op, operand) => {
+        let val = self.eval_expr(operand)?;
+        match op {
+          UnaryOp::Neg => val.wrapping_neg(),
+          UnaryOp::Not => (val == 0) as i64,
+        }
+      },
+      ExprKind::Call(name, args) => self.call(name, args)?,
+      ExprKind::Array(var, elems) => {
+        let addr = self.local(var);
+        let Type::Array(elem, _) = &expr.ty else {
+          unreachable!("array literal of type {}", expr.ty)
+        };
+        let size = elem.size() as i64;
+        for (i, expr) in elems.iter().enumerate() {
+          let val = self.eval_expr(expr)?;
+          self.write(elem, addr + i as i64 * size, val);
+        }
+        addr
+      },
+      ExprKind::Struct(var, def, fields) => {
+        let addr = self.local(var);
+        for (name, expr) in fields.iter() {
+          let val = self.eval_expr(expr)?;
+          let field = def.field(name).unwrap();
+          self.write(&field.ty, addr + field.offset as i64, val);
+        }
+        addr
+      },
+      ExprKind::Variant(var, def, index, values) => {
+        let addr = self.local(var);
+        let variant = &def.variants[*index];
+        for (expr, (ty, field_offset)) in values
+          .iter()
+          .zip(variant.fields.iter().zip(&variant.offsets))
+        {
+          let val = self.eval_expr(expr)?;
+          self.write(ty, addr + *field_offset as i64, val);
+        }
+        self.write(&Enum::TAG, addr, *index as i64);
+        addr
+      },
+      ExprKind::Match(value, arms) => {
+        let arm = self.match_arm(value, arms.iter().map(|(pattern, _)| pattern))?;
+        self.eval_expr(&arms[arm].1)?
+      },
+      ExprKind::Ref(inner) => self.place(inner)?,
+      ExprKind::Cast(inner, ty) => ty.wrap(self.eval_expr(inner)?),
+      ExprKind::Index(..) | ExprKind::Field(..) | ExprKind::Deref(_) => {
+        let addr = self.place(expr)?;
+        self.read(&expr.ty, addr)
+      },
+      ExprKind::If(cond, then, other) => match self.eval_expr(cond)? {
+        0 => self.eval_expr(other)?,
+        _ => self.eval_expr(then)?,
+      },
+      ExprKind::Builtin(builtin, args) => {
+        let mut written = 0;
+        if let Some(arg) = args.first() {
+          let val = self.eval_expr(arg)?;
+          written += self.print(&arg.ty, val)?;
+        }
+        if *builtin == Builtin::Println {
+          let newline = self.intern("\n");
+          written += self.print(&Type::Str, newline)?;
+        }
+        written
+      },
+    };
+
+    Ok(expr.ty.wrap(val))
+  }
+
+  fn exec_block(&mut self, block: &Block) -> Result<Flow, Halt> {
+    for stmt in block.stmts.iter() {
+      let flow = self.exec_stmt(stmt)?;
+      if flow != Flow::Next {
+        return Ok(flow);
+      }
+    }
+    Ok(Flow::Next)
+  }
+
+  fn exec_stmt(&mut self, stmt: &Stmt) -> Result<Flow, Halt> {
+    match stmt {
+      Stmt::Exit(expr) => Err(Halt::Exit(self.eval_expr(expr)?)),
+      Stmt::VarDecl(var, _, expr) | Stmt::VarAssign(var, expr) => {
+        let val = self.eval_expr(expr)?;
+        self.store(var, val);
+        Ok(Flow::Next)
+      },
+      Stmt::Assign(place, expr) => {
+        let addr = self.place(place)?;
+        let val = self.eval_expr(expr)?;
+        self.write(&place.ty, addr, val);
+        Ok(Flow::Next)
+      },
+      Stmt::If(branches, else_block) => {
+        for (cond, block) in branches.iter() {
+          if self.eval_expr(cond)? != 0 {
+            return self.exec_block(block);
+          }
+        }
+        match else_block {
+          Some(block) => self.exec_block(block),
+          None => Ok(Flow::Next),
+        }
+      },
+      Stmt::Match(value, arms) => {
+        let arm = self.match_arm(value, arms.iter().map(|(pattern, _)| pattern))?;
+        self.exec_block(&arms[arm].1)
+      },
+      Stmt::While(cond, block) => {
+        while self.eval_expr(cond)? != 0 {
+          let flow = self.exec_block(block)?;
+          if flow != Flow::Next {
+            return Ok(flow);
+          }
+        }
+        Ok(Flow::Next)
+      },
+      Stmt::Return(expr) => {
+        let val = match expr {
+          Some(expr) => self.eval_expr(expr)?,
+          None => 0,
+        };
+        Ok(Flow::Return(val))
+      },
+      Stmt::Expr(expr) => {
+        self.eval_expr(expr)?;
+        Ok(Flow::Next)
+      },
+    }
+  }
+
+  /// Runs the program and returns the value passed to `exit`, or 0 if the
+  /// program falls off the end.
+  pub fn interpret(&mut self) -> Result<i64, RuntimeError> {
+    self.frames = vec![Frame::new(self.layouts[0].clone())];
+
+    let program = self.program.clone();
+    let mut res = Ok(0);
+    for stmt in program.stmts.iter() {
+      match self.exec_stmt(stmt) {
+        Ok(_) => (),
+        Err(Halt::Exit(code)) => {
+          res = Ok(code);
+          break;
+        },
+        Err(Halt::Error(err)) => {
+          res = Err(*err);
+          break;
+        },
+      }
+    }
+
+    // the process exits right after, print what's buffered
+    std::io::stdout().flush().ok();
+    res
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn interpret(input: &str) -> Result<i64, RuntimeError> {
+    Interpreter::new(check(input, "test.pn").unwrap()).interpret()
+  }
+
+  #[test]
+  fn exits_with_the_value_passed_to_exit() {
+    assert_eq!(
+      interpret("x := 40\ny := x\nx = 2\nexit(x + y)\n").unwrap(),
+      42
+    );
+    assert_eq!(interpret("exit(7)\nexit(8)\n").unwrap(), 7);
+    // falling off the end
+    assert_eq!(interpret("x := 5\n").unwrap(), 0);
+  }
+
+  #[test]
+  fn runtime_errors_stop_the_program() {
+    let err = interpret("x := 0\nexit(5 / x)\n").unwrap_err();
+    assert_eq!(err.msg(), "Division by zero");
+    assert_eq!(err.code(), 1);
+
+    let err = interpret("a := [1, 2]\ni := 2\nexit(a[i])\n").unwrap_err();
+    assert_eq!(err.code(), 101);
+  }
+
+  /// 1 if `cond` holds after `setup`, 0 otherwise.
+  fn holds(setup: &str, cond: &str) -> i64 {
+    interpret(&format!(
+      "{}\nif {} {{\n  exit(1)\n}}\nexit(0)\n",
+      setup, cond
+    ))
+    .unwrap()
+  }
+
+  #[test]
+  fn operators_follow_precedence_and_associativity() {
+    assert_eq!(interpret("exit(2 + 3 * 4 - 10 / 2 % 3)\n").unwrap(), 12);
+    assert_eq!(interpret("exit(20 - 5 - 3)\n").unwrap(), 12);
+    assert_eq!(interpret("exit(64 / 4 / 2)\n").unwrap(), 8);
+    assert_eq!(interpret("x := 3\nexit(-x * -(x - 5))\n").unwrap(), -6);
+    assert_eq!(holds("", "1 + 2 < 4 == 2 > 1"), 1);
+    assert_eq!(holds("", "1 || 0 && 0"), 1);
+    assert_eq!(holds("", "!0 && !!5"), 1);
+  }
+
+  #[test]
+  fn logical_operators_short_circuit() {
+    // the right operand would divide by zero
+    assert_eq!(holds("x := 0", "x != 0 && 10 / x > 1"), 0);
+    assert_eq!(holds("x := 0", "x == 0 || 10 / x > 1"), 1);
+  }
+
+  #[test]
+  fn takes_the_first_branch_whose_condition_holds() {
+    let classify = |x: i64| {
+      interpret(&format!(
+        "x := {}
+if x < 0 {{
+  exit(1)
+}} else if x == 0 {{
+  exit(2)
+}} else if x < 10 {{
+  exit(3)
+}} else {{
+  exit(4)
+}}
+",
+        x
+      ))
+      .unwrap()
+    };
+    assert_eq!([-5, 0, 7, 10].map(classify), [1, 2, 3, 4]);
+  }
+
+  #[test]
+  fn blocks_shadow_variables_until_they_end() {
+    let code = interpret(
+      "x := 1
+i := 0
+while i < 3 {
+  x := 100
+  x = x + i
+  i = i + 1
+}
+if x == 1 {
+  x := 50
+}
+exit(x + i)
+",
+    );
+    assert_eq!(code.unwrap(), 4);
+  }
+
+  #[test]
+  fn functions_get_their_own_frames() {
+    let code = interpret(
+      "fn fib(n) {
+  if n < 2 {
+    return n
+  }
+  return fib(n - 1) + fib(n - 2)
+}
+fn nothing() {
+  x := 5
+}
+fn last(a, b, c, d, e, f, g) {
+  return g - a
+}
+n := 10
+exit(fib(n) + nothing() + last(1, 2, 3, 4, 5, 6, 7))
+",
+    );
+    assert_eq!(code.unwrap(), 55 + 6);
+  }
+}
@@ -0,0 +1,1291 @@
+use std::{
+  collections::{HashMap, HashSet},
+  fmt,
+};
+
+use super::*;
+
+/// A virtual register. Temps hold values extended to 64 bits, like `rax` in
+/// the generated code, and unlike SSA values they may be assigned more than
+/// once, e.g. by both sides of a `&&`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Temp(pub usize);
+
+impl fmt::Display for Temp {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "%{}", self.0)
+  }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Value {
+  Temp(Temp),
+  Const(i64),
+}
+
+impl fmt::Display for Value {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Value::Temp(temp) => write!(f, "{}", temp),
+      Value::Const(val) => write!(f, "{}", val),
+    }
+  }
+}
+
+/// A variable of the function, stored in the frame where the symbol table
+/// placed it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Local {
+  pub name:   String,
+  pub ty:     Type,
+  /// See [`SymbolTableEntry::offset`].
+  pub offset: isize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Inst {
+  /// `dst = src`
+  Copy(Temp, Value),
+  /// `dst = lhs op rhs` with operands of the given type. Arithmetic wraps to
+  /// the type and comparisons give 0 or 1. Never `&&` or `||`, which are
+  /// lowered to branches.
+  Binary(Temp, BinaryOp, Type, Value, Value),
+  /// `dst = op src` with an operand of the given type.
+  Unary(Temp, UnaryOp, Type, Value),
+  /// `dst = src as ty`, the value wrapped to the type, see [`Type::wrap`].
+  Cast(Temp, Type, Value),
+  /// `dst = local`
+  Load(Temp, usize),
+  /// `local = src`, truncated to the type of the local.
+  Store(usize, Value),
+  /// `dst = name(args)`
+  Call(Temp, String, Vec<Value>),
+  /// `dst = ` the argument at the index, only at the start of the entry block
+  /// where the argument registers still hold them.
+  Param(Temp, usize),
+  /// `dst = ` the address of the string at the index in [`Module::strings`].
+  Str(Temp, usize),
+  /// Writes `src`, a `str` or an integer of the given type, to stdout.
+  /// `dst = ` the number of bytes written.
+  Print(Temp, Type, Value),
+  /// `dst = ` the address of the local, which then stays in the frame.
+  Addr(Temp, usize),
+  /// `dst = ` the value of the given type at the address.
+  Read(Temp, Type, Value),
+  /// Stores the second value at the address, truncated to the type.
+  Write(Type, Value, Value),
+  /// Copies the given number of bytes from the second address to the first.
+  Move(Value, Value, usize),
+  /// Exits with an error unless the index, as unsigned, is below the length.
+  Check(Value, usize),
+}
+
+impl Inst {
+  /// Temp the instruction writes.
+  pub fn dst(&self) -> Option<Temp> {
+    match self {
+      Inst::Copy(dst, _)
+      | Inst::Binary(dst, ..)
+      | Inst::Unary(dst, ..)
+      | Inst::Cast(dst, ..)
+      | Inst::Load(dst, _)
+      | Inst::Call(dst, ..)
+      | Inst::Param(dst, _)
+      | Inst::Str(dst, _)
+      | Inst::Print(dst, ..)
+      | Inst::Addr(dst, _)
+      | Inst::Read(dst, ..) => Some(*dst),
+      Inst::Store(..) | Inst::Write(..) | Inst::Move(..) | Inst::Check(..) => None,
+    }
+  }
+
+  pub fn dst_mut(&mut self) -> Option<&mut Temp> {
+    match self {
+      Inst::Copy(dst, _)
+      | Inst::Binary(dst, ..)
+      | Inst::Unary(dst, ..)
+      | Inst::Cast(dst, ..)
+      | Inst::Load(dst, _)
+      | Inst::Call(dst, ..)
+      | Inst::Param(dst, _)
+      | Inst::Str(dst, _)
+      | Inst::Print(dst, ..)
+      | Inst::Addr(dst, _)
+      | Inst::Read(dst, ..) => Some(dst),
+      Inst::Store(..) | Inst::Write(..) | Inst::Move(..) | Inst::Check(..) => None,
+    }
+  }
+
+  /// Values the instruction reads.
+  pub fn values(&self) -> Vec<Value> {
+    match self {
+      Inst::Copy(_, src)
+      | Inst::Unary(_, _, _, src)
+      | Inst::Cast(_, _, src)
+      | Inst::Store(_, src)
+      | Inst::Print(_, _, src)
+      | Inst::Read(_, _, src)
+      | Inst::Check(src, _) => vec![*src],
+      Inst::Binary(_, _, _, lhs, rhs) | Inst::Write(_, lhs, rhs) | Inst::Move(lhs, rhs, _) => {
+        vec![*lhs, *rhs]
+      },
+      Inst::Call(_, _, args) => args.clone(),
+      Inst::Load(..) | Inst::Param(..) | Inst::Str(..) | Inst::Addr(..) => Vec::new(),
+    }
+  }
+
+  /// Temps the instruction reads.
+  pub fn uses(&self) -> Vec<Temp> {
+    temps(self.values())
+  }
+
+  pub fn values_mut(&mut self) -> Vec<&mut Value> {
+    match self {
+      Inst::Copy(_, src)
+      | Inst::Unary(_, _, _, src)
+      | Inst::Cast(_, _, src)
+      | Inst::Store(_, src)
+      | Inst::Print(_, _, src)
+      | Inst::Read(_, _, src)
+      | Inst::Check(src, _) => vec![src],
+      Inst::Binary(_, _, _, lhs, rhs) | Inst::Write(_, lhs, rhs) | Inst::Move(lhs, rhs, _) => {
+        vec![lhs, rhs]
+      },
+      Inst::Call(_, _, args) => args.iter_mut().collect(),
+      Inst::Load(..) | Inst::Param(..) | Inst::Str(..) | Inst::Addr(..) => Vec::new(),
+    }
+  }
+
+  /// Whether the instruction calls a function or the runtime, which has side
+  /// effects and clobbers the caller-saved registers.
+  pub fn is_call(&self) -> bool {
+    matches!(self, Inst::Call(..) | Inst::Print(..))
+  }
+}
+
+/// How control leaves a basic block.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Terminator {
+  Jump(usize),
+  /// Goes to the first block if the operand is non-zero, the second otherwise.
+  Branch(Value, usize, usize),
+  Return(Value),
+  /// Exits the process with the operand as its code.
+  Exit(Value),
+}
+
+impl Terminator {
+  /// Blocks control may continue to.
+  pub fn successors(&self) -> Vec<usize> {
+    match self {
+      Terminator::Jump(target) => vec![*target],
+      Terminator::Branch(_, then, other) => vec![*then, *other],
+      Terminator::Return(_) | Terminator::Exit(_) => Vec::new(),
+    }
+  }
+
+  pub fn value(&self) -> Option<Value> {
+    match self {
+      Terminator::Jump(_) => None,
+      Terminator::Branch(val, ..) | Terminator::Return(val) | Terminator::Exit(val) => Some(*val),
+    }
+  }
+
+  pub fn uses(&self) -> Vec<Temp> {
+    temps(self.value().into_iter().collect())
+  }
+
+  pub fn value_mut(&mut self) -> Option<&mut Value> {
+    match self {
+      Terminator::Jump(_) => None,
+      Terminator::Branch(val, ..) | Terminator::Return(val) | Terminator::Exit(val) => Some(val),
+    }
+  }
+}
+
+fn temps(vals: Vec<Value>) -> Vec<Temp> {
+  vals
+    .into_iter()
+    .filter_map(|val| match val {
+      Value::Temp(temp) => Some(temp),
+      Value::Const(_) => None,
+    })
+    .collect()
+}
+
+/// Straight-line instructions ending in a single jump, branch, return or exit.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BasicBlock {
+  pub insts: Vec<Inst>,
+  pub term:  Terminator,
+}
+
+/// A function, or the top level of the program, as basic blocks starting with
+/// the entry block `0`.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct IrFunction {
+  pub name:   String,
+  /// Every variable of the frame. Parameters come first, in order, and are
+  /// stored by the entry block.
+  pub locals: Vec<Local>,
+  pub params: usize,
+  /// Bytes the frame reserves for the locals.
+  pub frame:  usize,
+  /// Number of temps used, they're numbered from 0.
+  pub temps:  usize,
+  pub blocks: Vec<BasicBlock>,
+}
+
+/// A lowered [`Program`]: the top level statements as `main` and the declared
+/// functions.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Module {
+  pub main:      IrFunction,
+  pub functions: Vec<IrFunction>,
+  /// Contents of the string literals, each stored once.
+  pub strings:   Vec<String>,
+}
+
+impl Module {
+  /// `main` and every declared function.
+  pub fn functions_mut(&mut self) -> impl Iterator<Item = &mut IrFunction> {
+    std::iter::once(&mut self.main).chain(self.functions.iter_mut())
+  }
+}
+
+fn mnemonic(op: BinaryOp) -> &'static str {
+  match op {
+    BinaryOp::Add => "add",
+    BinaryOp::Sub => "sub",
+    BinaryOp::Mul => "mul",
+    BinaryOp::Div => "div",
+    BinaryOp::Mod => "mod",
+    BinaryOp::Eq => "eq",
+    BinaryOp::Ne => "ne",
+    BinaryOp::Lt => "lt",
+    BinaryOp::Le => "le",
+    BinaryOp::Gt => "gt",
+    BinaryOp::Ge => "ge",
+    BinaryOp::And => "and",
+    BinaryOp::Or => "or",
+  }
+}
+
+impl IrFunction {
+  /// Temps live on entry to each block.
+  pub fn live_in(&self) -> Vec<HashSet<Temp>> {
+    let mut uses = Vec::new();
+    let mut defs = Vec::new();
+    for block in self.blocks.iter() {
+      let mut used = HashSet::new();
+      let mut defined = HashSet::new();
+      for inst in block.insts.iter() {
+        used.extend(inst.uses().into_iter().filter(|t| !defined.contains(t)));
+        defined.extend(inst.dst());
+      }
+      used.extend(
+        block
+          .term
+          .uses()
+          .into_iter()
+          .filter(|t| !defined.contains(t)),
+      );
+      uses.push(used);
+      defs.push(defined);
+    }
+
+    let mut live = vec![HashSet::new(); self.blocks.len()];
+    let mut changed = true;
+    while changed {
+      changed = false;
+      for (i, block) in self.blocks.iter().enumerate().rev() {
+        let mut set = uses[i].clone();
+        for succ in block.term.successors() {
+          set.extend(live[succ].iter().filter(|t| !defs[i].contains(t)));
+        }
+        if set.len() != live[i].len() {
+          live[i] = set;
+          changed = true;
+        }
+      }
+    }
+
+    live
+  }
+
+  /// Blocks that can jump to each block.
+  pub fn predecessors(&self) -> Vec<Vec<usize>> {
+    let mut preds = vec![Vec::new(); self.blocks.len()];
+    for (i, block) in self.blocks.iter().enumerate() {
+      for succ in block.term.successors() {
+        preds[succ].push(i);
+      }
+    }
+    preds
+  }
+
+  fn fmt_inst(&self, f: &mut fmt::Formatter, inst: &Inst) -> fmt::Result {
+    let local = |i: usize| format!("{}.{}", self.locals[i].name, i);
+    match inst {
+      Inst::Copy(dst, src) => write!(f, "{} = {}", dst, src),
+      Inst::Binary(dst, op, ty, lhs, rhs) => {
+        write!(f, "{} = {} {} {}, {}", dst, mnemonic(*op), ty, lhs, rhs)
+      },
+      Inst::Unary(dst, UnaryOp::Neg, ty, src) => write!(f, "{} = neg {} {}", dst, ty, src),
+      Inst::Unary(dst, UnaryOp::Not, ty, src) => write!(f, "{} = not {} {}", dst, ty, src),
+      Inst::Cast(dst, ty, src) => write!(f, "{} = cast {} {}", dst, ty, src),
+      Inst::Load(dst, i) => write!(f, "{} = load {}", dst, local(*i)),
+      Inst::Store(i, src) => write!(f, "store {}, {}", local(*i), src),
+      Inst::Call(dst, name, args) => {
+        let args = args
+          .iter()
+          .map(|arg| arg.to_string())
+          .collect::<Vec<_>>()
+          .join(", ");
+        write!(f, "{} = call {}({})", dst, name, args)
+      },
+      Inst::Param(dst, i) => write!(f, "{} = param {}", dst, i),
+      Inst::Str(dst, i) => write!(f, "{} = str {}", dst, i),
+      Inst::Print(dst, ty, src) => write!(f, "{} = print {} {}", dst, ty, src),
+      Inst::Addr(dst, i) => write!(f, "{} = addr {}", dst, local(*i)),
+      Inst::Read(dst, ty, addr) => write!(f, "{} = read {} {}", dst, ty, addr),
+      Inst::Write(ty, addr, src) => write!(f, "write {} {}, {}", ty, addr, src),
+      Inst::Move(dst, src, size) => write!(f, "move {}, {}, {}", dst, src, size),
+      Inst::Check(index, len) => write!(f, "check {}, {}", index, len),
+    }
+  }
+}
+
+impl fmt::Display for IrFunction {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let params = self.locals[..self.params]
+      .iter()
+      .enumerate()
+      .map(|(i, local)| format!("{}.{}: {}", local.name, i, local.ty))
+      .collect::<Vec<_>>()
+      .join(", ");
+    writeln!(f, "fn {}({}):", self.name, params)?;
+
+    for (i, block) in self.blocks.iter().enumerate() {
+      writeln!(f, "b{}:", i)?;
+      for inst in block.insts.iter() {
+        write!(f, "  ")?;
+        self.fmt_inst(f, inst)?;
+        writeln!(f)?;
+      }
+      match &block.term {
+        Terminator::Jump(target) => writeln!(f, "  jump b{}", target)?,
+        Terminator::Branch(cond, then, other) => {
+          writeln!(f, "  branch {}, b{}, b{}", cond, then, other)?
+        },
+        Terminator::Return(val) => writeln!(f, "  return {}", val)?,
+        Terminator::Exit(val) => writeln!(f, "  exit {}", val)?,
+      }
+    }
+
+    Ok(())
+  }
+}
+
+/// The strings, then the functions separated by blank lines, `main` first.
+///
+/// ```text
+/// str 0 = "hi\n"
+///
+/// fn main():
+/// b0:
+///   %0 = load x.0
+///   %1 = add u8 %0, 1
+///   store x.0, %1
+///   exit 0
+/// ```
+impl fmt::Display for Module {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    for (i, string) in self.strings.iter().enumerate() {
+      writeln!(f, "str {} = {}", i, Literal::String(string.clone()))?;
+    }
+    if !self.strings.is_empty() {
+      writeln!(f)?;
+    }
+    write!(f, "{}", self.main)?;
+    for function in self.functions.iter() {
+      write!(f, "\n{}", function)?;
+    }
+    Ok(())
+  }
+}
+
+/// Lowers a checked [`Program`] to a [`Module`]. Variables are resolved to
+/// the locals of their frame, expressions are flattened into instructions on
+/// temps and control flow becomes jumps between basic blocks.
+pub struct Lowerer {
+  program:  Program,
+  function: IrFunction,
+  /// Local of each variable of the current frame, by table and index.
+  locals:   HashMap<(usize, usize), usize>,
+  /// Blocks are created before they're filled so jumps can target them.
+  blocks:   Vec<Option<BasicBlock>>,
+  /// Blocks in the order they were started, which is the order they're laid
+  /// out in.
+  order:    Vec<usize>,
+  /// Instructions of the block being filled.
+  insts:    Vec<Inst>,
+  strings:  Vec<String>,
+}
+
+impl Lowerer {
+  pub fn new(program: Program) -> Self {
+    Self {
+      program,
+      function: IrFunction::default(),
+      locals: HashMap::new(),
+      blocks: Vec::new(),
+      order: Vec::new(),
+      insts: Vec::new(),
+      strings: Vec::new(),
+    }
+  }
+
+  fn new_temp(&mut self) -> Temp {
+    self.function.temps += 1;
+    Temp(self.function.temps - 1)
+  }
+
+  fn new_block(&mut self) -> usize {
+    self.blocks.push(None);
+    self.blocks.len() - 1
+  }
+
+  /// Makes `block` the one instructions are added to.
+  fn start(&mut self, block: usize) {
+    self.order.push(block);
+  }
+
+  /// Ends the current block with `term`. A new block must be started before
+  /// adding more instructions.
+  fn terminate(&mut self, term: Terminator) {
+    let block = *self.order.last().unwrap();
+    self.blocks[block] = Some(BasicBlock {
+      insts: std::mem::take(&mut self.insts),
+      term,
+    });
+  }
+
+  /// Ends the current block with a return or exit, and continues in a new
+  /// block that nothing jumps to, where any statements after it go.
+  fn terminate_unreachable(&mut self, term: Terminator) {
+    self.terminate(term);
+    let block = self.new_block();
+    self.start(block);
+  }
+
+  fn emit(&mut self, inst: Inst) {
+    self.insts.push(inst);
+  }
+
+  fn local(&self, var: &VarRef) -> usize {
+    self.locals[&(var.table, var.index)]
+  }
+
+  /// `dst = val != 0`, a copy if `val` is already a bool.
+  fn emit_bool(&mut self, dst: Temp, ty: &Type, val: Value) {
+    match ty {
+      Type::Bool => self.emit(Inst::Copy(dst, val)),
+      _ => self.emit(Inst::Binary(
+        dst,
+        BinaryOp::Ne,
+        ty.clone(),
+        val,
+        Value::Const(0),
+      )),
+    }
+  }
+
+  fn lower_logical(&mut self, op: BinaryOp, lhs: &Expr, rhs: &Expr) -> Value {
+    // the right operand is only evaluated if the left one doesn't already
+    // decide the result, both sides write the result
+    let val = self.lower_expr(lhs);
+    let dst = self.new_temp();
+    self.emit_bool(dst, &lhs.ty, val);
+
+    let right = self.new_block();
+    let end = self.new_block();
+    self.terminate(match op {
+      BinaryOp::And => Terminator::Branch(Value::Temp(dst), right, end),
+      _ => Terminator::Branch(Value::Temp(dst), end, right),
+    });
+
+    self.start(right);
+    let val = self.lower_expr(rhs);
+    self.emit_bool(dst, &rhs.ty, val);
+    self.terminate(Terminator::Jump(end));
+
+    self.start(end);
+    Value::Temp(dst)
+  }
+
+  fn lower_conditional(&mut self, cond: &Expr, then: &Expr, other: &Expr) -> Value {
+    let val = self.lower_expr(cond);
+    let dst = self.new_temp();
+
+    let then_block = self.new_block();
+    let other_block = self.new_block();
+    let end = self.new_block();
+    self.terminate(Terminator::Branch(val, then_block, other_block));
+
+    // like `&&` and `||`, both branches write the result
+    for (block, expr) in [(then_block, then), (other_block, other)] {
+      self.start(block);
+      let val = self.lower_expr(expr);
+      self.emit(Inst::Copy(dst, val));
+      self.terminate(Terminator::Jump(end));
+    }
+
+    self.start(end);
+    Value::Temp(dst)
+  }
+
+  /// Address of `string`, stored once in the module.
+  fn lower_string(&mut self, string: &str) -> Value {
+    let index = match self.strings.iter().position(|s| s == string) {
+      Some(index) => index,
+      None => {
+        self.strings.push(string.to_string());
+        self.strings.len() - 1
+      },
+    };
+    let dst = self.new_temp();
+    self.emit(Inst::Str(dst, index));
+    Value::Temp(dst)
+  }
+
+  /// Address of the element of `array` at `index`, exiting if it's out of
+  /// bounds.
+  fn lower_element(&mut self, array: &Expr, index: &Expr) -> Value {
+    let Type::Array(elem, len) = &array.ty else {
+      unreachable!("indexing {}", array.ty)
+    };
+    let base = self.lower_expr(array);
+    let index = self.lower_expr(index);
+    self.emit(Inst::Check(index, *len));
+
+    let offset = self.new_temp();
+    let size = Value::Const(elem.size() as i64);
+    self.emit(Inst::Binary(offset, BinaryOp::Mul, Type::I64, index, size));
+    let addr = self.new_temp();
+    self.emit(Inst::Binary(
+      addr,
+      BinaryOp::Add,
+      Type::I64,
+      base,
+      Value::Temp(offset),
+    ));
+    Value::Temp(addr)
+  }
+
+  /// `lhs op rhs` with a pointer on the left. Integers are scaled by the size
+  /// of the values it points to, and so is the difference of two pointers.
+  fn lower_pointer_binary(&mut self, op: BinaryOp, lhs: &Expr, rhs: &Expr) -> Value {
+    let Type::Pointer(elem) = &lhs.ty else {
+      unreachable!("pointer operation on {}", lhs.ty)
+    };
+    let size = Value::Const(elem.size() as i64);
+    let lval = self.lower_expr(lhs);
+    let rval = self.lower_expr(rhs);
+    let dst = self.new_temp();
+
+    if rhs.ty.is_integer() {
+      let offset = self.new_temp();
+      self.emit(Inst::Binary(offset, BinaryOp::Mul, Type::I64, rval, size));
+      self.emit(Inst::Binary(dst, op, Type::I64, lval, Value::Temp(offset)));
+    } else if op == BinaryOp::Sub {
+      let bytes = self.new_temp();
+      self.emit(Inst::Binary(bytes, op, Type::I64, lval, rval));
+      self.emit(Inst::Binary(
+        dst,
+        BinaryOp::Div,
+        Type::I64,
+        Value::Temp(bytes),
+        size,
+      ));
+    } else {
+      // addresses compare as unsigned
+      self.emit(Inst::Binary(dst, op, Type::U64, lval, rval));
+    }
+    Value::Temp(dst)
+  }
+
+  /// `base + offset`, `base` itself if the offset is 0.
+  fn lower_offset(&mut self, base: Value, offset: usize) -> Value {
+    if offset == 0 {
+      return base;
+    }
+    let addr = self.new_temp();
+    let offset = Value::Const(offset as i64);
+    self.emit(Inst::Binary(addr, BinaryOp::Add, Type::I64, base, offset));
+    Value::Temp(addr)
+  }
+
+  /// Address of `field` of the struct `expr`.
+  fn lower_field(&mut self, expr: &Expr, name: &str) -> Value {
+    let Type::Struct(def) = &expr.ty else {
+      unreachable!("field of {}", expr.ty)
+    };
+    let offset = def.field(name).unwrap().offset;
+    let base = self.lower_expr(expr);
+    self.lower_offset(base, offset)
+  }
+
+  /// Address of the place `expr` names.
+  fn lower_place(&mut self, expr: &Expr) -> Value {
+    match &expr.kind {
+      ExprKind::Identifier(var) => {
+        let dst = self.new_temp();
+        self.emit(Inst::Addr(dst, self.local(var)));
+        Value::Temp(dst)
+      },
+      ExprKind::Index(array, index) => self.lower_element(array, index),
+      ExprKind::Field(inner, name) => self.lower_field(inner, name),
+      ExprKind::Deref(inner) => self.lower_expr(inner),
+      _ => unreachable!("assignment to {:?}", expr.kind),
+    }
+  }
+
+  /// Stores `val` of type `ty` at `addr`, arrays and structs are copied from
+  /// the address they're given by.
+  fn lower_write(&mut self, ty: &Type, addr: Value, val: Value) {
+    match ty {
+      _ if !ty.is_scalar() => self.emit(Inst::Move(addr, val, ty.size())),
+      _ => self.emit(Inst::Write(ty.clone(), addr, val)),
+    }
+  }
+
+  /// Builds the elements of an array literal in the local `var` and gives its
+  /// address.
+  fn lower_array(&mut self, var: &VarRef, elems: &[Expr], ty: &Type) -> Value {
+    let Type::Array(elem, _) = ty else {
+      unreachable!("array literal of type {}", ty)
+    };
+    let base = self.new_temp();
+    self.emit(Inst::Addr(base, self.local(var)));
+
+    for (i, expr) in elems.iter().enumerate() {
+      let val = self.lower_expr(expr);
+      let addr = self.lower_offset(Value::Temp(base), i * elem.size());
+      self.lower_write(elem, addr, val);
+    }
+
+    Value::Temp(base)
+  }
+
+  /// Builds the fields of a struct literal in the local `var`, in the order
+  /// they're written, and gives its address.
+  fn lower_struct(&mut self, var: &VarRef, def: &Struct, fields: &[(String, Expr)]) -> Value {
+    let base = self.new_temp();
+    self.emit(Inst::Addr(base, self.local(var)));
+
+    for (name, expr) in fields.iter() {
+      let val = self.lower_expr(expr);
+      let field = def.field(name).unwrap();
+      let addr = self.lower_offset(Value::Temp(base), field.offset);
+      self.lower_write(&field.ty, addr, val);
+    }
+
+    Value::Temp(base)
+  }
+
+  /// Builds the variant at `index` in the local `var`, its tag and then its
+  /// values, and gives its address.
+  fn lower_variant(&mut self, var: &VarRef, def: &Enum, index: usize, values: &[Expr]) -> Value {
+    let base = self.new_temp();
+    self.emit(Inst::Addr(base, self.local(var)));
+    let tag = Value::Const(index as i64);
+    self.emit(Inst::Write(Enum::TAG, Value::Temp(base), tag));
+
+    let variant = &def.variants[index];
+    for (expr, (ty, offset)) in values
+      .iter()
+      .zip(variant.fields.iter().zip(&variant.offsets))
+    {
+      let val = self.lower_expr(expr);
+      let addr = self.lower_offset(Value::Temp(base), *offset);
+      self.lower_write(ty, addr, val);
+    }
+
+    Value::Temp(base)
+  }
+
+  /// Lowers a match on `value` to a chain of comparisons of its value, or of
+  /// the tag of an enum, with each pattern. The arm whose pattern matches binds
+  /// its variables and is lowered by `lower_arm`. The checker made sure some
+  /// arm matches, so the last one is taken without comparing.
+  fn lower_match<T>(
+    &mut self,
+    value: &Expr,
+    arms: &[(Pattern, T)],
+    mut lower_arm: impl FnMut(&mut Self, &T),
+  ) {
+    let val = self.lower_expr(value);
+    let (key, ty) = match &value.ty {
+      Type::Enum(_) => {
+        let tag = self.new_temp();
+        self.emit(Inst::Read(tag, Enum::TAG, val));
+        (Value::Temp(tag), Enum::TAG)
+      },
+      ty => (val, ty.clone()),
+    };
+
+    let end = self.new_block();
+    for (i, (pattern, arm)) in arms.iter().enumerate() {
+      let expected = match &pattern.kind {
+        _ if i == arms.len() - 1 => None,
+        PatternKind::Wildcard => None,
+        PatternKind::Literal(Literal::Integer(i)) => Some(*i),
+        PatternKind::Literal(Literal::Unsigned(u)) => Some(*u as i64),
+        PatternKind::Literal(Literal::Bool(b)) => Some(*b as i64),
+        PatternKind::Literal(Literal::String(_)) => unreachable!("string pattern"),
+        PatternKind::Variant(_, index, _) => Some(*index as i64),
+      };
+
+      let next = expected.map(|expected| {
+        let cond = self.new_temp();
+        let expected = Value::Const(expected);
+        self.emit(Inst::Binary(cond, BinaryOp::Eq, ty.clone(), key, expected));
+        let body = self.new_block();
+        let next = self.new_block();
+        self.terminate(Terminator::Branch(Value::Temp(cond), body, next));
+        self.start(body);
+        next
+      });
+
+      if let PatternKind::Variant(def, index, bindings) = &pattern.kind {
+        let variant = &def.variants[*index];
+        for (var, (ty, offset)) in bindings
+          .iter()
+          .zip(variant.fields.iter().zip(&variant.offsets))
+        {
+          let Some(var) = var else { continue };
+          let addr = self.lower_offset(val, *offset);
+          let local = self.local(var);
+          if ty.is_scalar() {
+            let dst = self.new_temp();
+            self.emit(Inst::Read(dst, ty.clone(), addr));
+            self.emit(Inst::Store(local, Value::Temp(dst)));
+          } else {
+            let dst = self.new_temp();
+            self.emit(Inst::Addr(dst, local));
+            self.emit(Inst::Move(Value::Temp(dst), addr, ty.size()));
+          }
+        }
+      }
+      lower_arm(self, arm);
+      self.terminate(Terminator::Jump(end));
+
+      match next {
+        Some(next) => self.start(next),
+        None => break,
+      }
+    }
+
+    self.start(end);
+  }
+
+  /// Prints `expr`, a bool as `true` or `false`, giving the bytes written.
+  fn lower_print(&mut self, expr: &Expr) -> Value {
+    let val = self.lower_expr(expr);
+    let dst = self.new_temp();
+    if expr.ty != Type::Bool {
+      self.emit(Inst::Print(dst, expr.ty.clone(), val));
+      return Value::Temp(dst);
+    }
+
+    let then = self.new_block();
+    let other = self.new_block();
+    let end = self.new_block();
+    self.terminate(Terminator::Branch(val, then, other));
+    for (block, text) in [(then, "true"), (other, "false")] {
+      self.start(block);
+      let string = self.lower_string(text);
+      self.emit(Inst::Print(dst, Type::Str, string));
+      self.terminate(Terminator::Jump(end));
+    }
+    self.start(end);
+    Value::Temp(dst)
+  }
+
+  fn lower_expr(&mut self, expr: &Expr) -> Value {
+    match &expr.kind {
+      ExprKind::Literal(Literal::Integer(i)) => Value::Const(*i),
+      ExprKind::Literal(Literal::Unsigned(u)) => Value::Const(*u as i64),
+      ExprKind::Literal(Literal::String(s)) => self.lower_string(s),
+      ExprKind::Literal(Literal::Bool(b)) => Value::Const(*b as i64),
+      // arrays are handled by address
+      ExprKind::Identifier(_) if !expr.ty.is_scalar() => self.lower_place(expr),
+      ExprKind::Identifier(var) => {
+        let dst = self.new_temp();
+        self.emit(Inst::Load(dst, self.local(var)));
+        Value::Temp(dst)
+      },
+      ExprKind::Array(var, elems) => self.lower_array(var, elems, &expr.ty),
+      ExprKind::Struct(var, def, fields) => self.lower_struct(var, def, fields),
+      ExprKind::Variant(var, def, index, values) => self.lower_variant(var, def, *index, values),
+      ExprKind::Match(value, arms) => {
+        // like a conditional expression, every arm writes the result
+        let dst = self.new_temp();
+        self.lower_match(value, arms, |lowerer, expr| {
+          let val = lowerer.lower_expr(expr);
+          lowerer.emit(Inst::Copy(dst, val));
+        });
+        Value::Temp(dst)
+      },
+      ExprKind::Ref(inner) => self.lower_place(inner),
+      ExprKind::Index(..) | ExprKind::Field(..) | ExprKind::Deref(_) => {
+        let addr = self.lower_place(expr);
+        if !expr.ty.is_scalar() {
+          return addr;
+        }
+        let dst = self.new_temp();
+        self.emit(Inst::Read(dst, expr.ty.clone(), addr));
+        Value::Temp(dst)
+      },
+      ExprKind::Binary(op @ (BinaryOp::And | BinaryOp::Or), lhs, rhs) => {
+        self.lower_logical(*op, lhs, rhs)
+      },
+      ExprKind::Binary(op, lhs, rhs) if matches!(lhs.ty, Type::Pointer(_)) => {
+        self.lower_pointer_binary(*op, lhs, rhs)
+      },
+      ExprKind::Binary(op, lhs, rhs) => {
+        let lval = self.lower_expr(lhs);
+        let rval = self.lower_expr(rhs);
+        let dst = self.new_temp();
+        self.emit(Inst::Binary(dst, *op, lhs.ty.clone(), lval, rval));
+        Value::Temp(dst)
+      },
+      ExprKind::Unary(op, operand) => {
+        let val = self.lower_expr(operand);
+        let dst = self.new_temp();
+        self.emit(Inst::Unary(dst, *op, operand.ty.clone(), val));
+        Value::Temp(dst)
+      },
+      ExprKind::Cast(inner, ty) => {
+        let val = self.lower_expr(inner);
+        let dst = self.new_temp();
+        self.emit(Inst::Cast(dst, ty.clone(), val));
+        Value::Temp(dst)
+      },
+      ExprKind::If(cond, then, other) => self.lower_conditional(cond, then, other),
+      ExprKind::Call(name, args) => {
+        let args = args.iter().map(|arg| self.lower_expr(arg)).collect();
+        let dst = self.new_temp();
+        self.emit(Inst::Call(dst, name.clone(), args));
+        Value::Temp(dst)
+      },
+      ExprKind::Builtin(builtin, args) => {
+        let mut written = args.first().map(|arg| self.lower_print(arg));
+        if *builtin == Builtin::Println {
+          let newline = self.lower_string("\n");
+          let dst = self.new_temp();
+          self.emit(Inst::Print(dst, Type::Str, newline));
+          written = Some(match written {
+            Some(val) => {
+              let sum = self.new_temp();
+              self.emit(Inst::Binary(
+                sum,
+                BinaryOp::Add,
+                Type::I64,
+                val,
+                Value::Temp(dst),
+              ));
+              Value::Temp(sum)
+            },
+            None => Value::Temp(dst),
+          });
+        }
+        written.unwrap()
+      },
+    }
+  }
+
+  fn lower_block(&mut self, block: &Block) {
+    for stmt in block.stmts.iter() {
+      self.lower_stmt(stmt);
+    }
+  }
+
+  fn lower_stmt(&mut self, stmt: &Stmt) {
+    match stmt {
+      Stmt::Exit(expr) => {
+        let val = self.lower_expr(expr);
+        self.terminate_unreachable(Terminator::Exit(val));
+      },
+      Stmt::VarDecl(var, _, expr) | Stmt::VarAssign(var, expr) if !expr.ty.is_scalar() => {
+        let val = self.lower_expr(expr);
+        let dst = self.new_temp();
+        self.emit(Inst::Addr(dst, self.local(var)));
+        self.emit(Inst::Move(Value::Temp(dst), val, expr.ty.size()));
+      },
+      Stmt::VarDecl(var, _, expr) | Stmt::VarAssign(var, expr) => {
+        let val = self.lower_expr(expr);
+        self.emit(Inst::Store(self.local(var), val));
+      },
+      Stmt::Assign(place, expr) => {
+        let addr = self.lower_place(place);
+        let val = self.lower_expr(expr);
+        self.lower_write(&place.ty, addr, val);
+      },
+      Stmt::If(branches, else_block) => {
+        let end = self.new_block();
+        for (cond, block) in branches.iter() {
+          let cond = self.lower_expr(cond);
+          let then = self.new_block();
+          let next = self.new_block();
+          self.terminate(Terminator::Branch(cond, then, next));
+
+          self.start(then);
+          self.lower_block(block);
+          self.terminate(Terminator::Jump(end));
+          self.start(next);
+        }
+        if let Some(block) = else_block {
+          self.lower_block(block);
+        }
+        self.terminate(Terminator::Jump(end));
+        self.start(end);
+      },
+      Stmt::While(cond, block) => {
+        let test = self.new_block();
+        let body = self.new_block();
+        let end = self.new_block();
+        self.terminate(Terminator::Jump(test));
+
+        self.start(test);
+        let cond = self.lower_expr(cond);
+        self.terminate(Terminator::Branch(cond, body, end));
+
+        self.start(body);
+        self.lower_block(block);
+        self.terminate(Terminator::Jump(test));
+        self.start(end);
+      },
+      Stmt::Match(value, arms) => {
+        self.lower_match(value, arms, |lowerer, block| lowerer.lower_block(block));
+      },
+      Stmt::Return(expr) => {
+        let val = match expr {
+          Some(expr) => self.lower_expr(expr),
+          None => Value::Const(0),
+        };
+        self.terminate_unreachable(Terminator::Return(val));
+      },
+      Stmt::Expr(expr) => {
+        self.lower_expr(expr);
+      },
+    }
+  }
+
+  /// Lowers the statements of the frame rooted at the table `root`, ending
+  /// with `term` if control reaches the end.
+  fn lower_function(
+    &mut self,
+    name: &str,
+    params: usize,
+    root: usize,
+    stmts: &[Stmt],
+    term: Terminator,
+  ) -> IrFunction {
+    self.locals.clear();
+    let mut locals = Vec::new();
+    for (table, symbols) in self.program.symbol_tables.iter().enumerate() {
+      if self.program.frame_root(table) != root {
+        continue;
+      }
+      for (index, entry) in symbols.entries.iter().enumerate() {
+        self.locals.insert((table, index), locals.len());
+        locals.push(Local {
+          name:   entry.name.clone(),
+          ty:     entry.ty.clone(),
+          offset: entry.offset,
+        });
+      }
+    }
+
+    self.function = IrFunction {
+      name: name.to_string(),
+      locals,
+      params,
+      frame: self.program.frame_size(root),
+      temps: 0,
+      blocks: Vec::new(),
+    };
+
+    let entry = self.new_block();
+    self.start(entry);
+    for i in 0..params {
+      let dst = self.new_temp();
+      self.emit(Inst::Param(dst, i));
+      self.emit(Inst::Store(i, Value::Temp(dst)));
+    }
+    for stmt in stmts.iter() {
+      self.lower_stmt(stmt);
+    }
+    self.terminate(term);
+
+    // lay the blocks out in the order they were started, so a block usually
+    // falls through to the next one
+    let mut ids = vec![0; self.blocks.len()];
+    for (i, block) in self.order.iter().enumerate() {
+      ids[*block] = i;
+    }
+    let mut blocks = std::mem::take(&mut self.blocks);
+    for block in std::mem::take(&mut self.order) {
+      let mut block = blocks[block].take().unwrap();
+      block.term = match block.term {
+        Terminator::Jump(target) => Terminator::Jump(ids[target]),
+        Terminator::Branch(cond, then, other) => Terminator::Branch(cond, ids[then], ids[other]),
+        term => term,
+      };
+      self.function.blocks.push(block);
+    }
+
+    std::mem::take(&mut self.function)
+  }
+
+  pub fn lower(&mut self) -> Module {
+    let stmts = self.program.stmts.clone();
+    let main = self.lower_function("main", 0, 0, &stmts, Terminator::Exit(Value::Const(0)));
+
+    let mut functions = Vec::new();
+    for function in self.program.functions.clone() {
+      functions.push(self.lower_function(
+        &function.name,
+        function.params.len(),
+        function.body.table,
+        &function.body.stmts,
+        Terminator::Return(Value::Const(0)),
+      ));
+    }
+
+    Module {
+      main,
+      functions,
+      strings: std::mem::take(&mut self.strings),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn lower(input: &str) -> Module {
+    let program = check(input, "test.pn").unwrap();
+    Lowerer::new(program).lower()
+  }
+
+  #[test]
+  fn lowers_control_flow_to_blocks() {
+    let module = lower(
+      "fn f(a: u8) -> u8 {
+  while a > 0 && a != 5 {
+    a = a - 1
+  }
+  return a
+}
+x := f(9)
+if x == 5 {
+  exit(1)
+}
+",
+    );
+
+    assert_eq!(
+      module.to_string(),
+      "fn main():
+b0:
+  %0 = call f(9)
+  store x.0, %0
+  %1 = load x.0
+  %2 = eq u8 %1, 5
+  branch %2, b1, b3
+b1:
+  exit 1
+b2:
+  jump b4
+b3:
+  jump b4
+b4:
+  exit 0
+
+fn f(a.0: u8):
+b0:
+  %0 = param 0
+  store a.0, %0
+  jump b1
+b1:
+  %1 = load a.0
+  %2 = gt u8 %1, 0
+  %3 = %2
+  branch %3, b2, b3
+b2:
+  %4 = load a.0
+  %5 = ne u8 %4, 5
+  %3 = %5
+  jump b3
+b3:
+  branch %3, b4, b5
+b4:
+  %6 = load a.0
+  %7 = sub u8 %6, 1
+  store a.0, %7
+  jump b1
+b5:
+  %8 = load a.0
+  return %8
+b6:
+  return 0
+"
+    );
+  }
+
+  #[test]
+  fn shadowed_variables_get_their_own_locals() {
+    let module = lower(
+      "x := 1
+if x > 0 {
+  x := 2
+  exit(x)
+}
+",
+    );
+
+    let locals = &module.main.locals;
+    assert_eq!(locals.len(), 2);
+    assert_eq!(locals[0].name, locals[1].name);
+    assert_ne!(locals[0].offset, locals[1].offset);
+    assert_eq!(module.main.blocks[1].insts, vec![
+      Inst::Store(1, Value::Const(2)),
+      Inst::Load(Temp(2), 1)
+    ]);
+  }
+
+  #[test]
+  fn conditional_expressions_write_the_result_in_both_branches() {
+    let module = lower(
+      "x: i8 := -3
+done := false
+y := if x < 0 && !done { -x } else { x }
+exit(y)
+",
+    );
+
+    assert_eq!(
+      module.to_string(),
+      "fn main():
+b0:
+  store x.0, -3
+  store done.1, 0
+  %0 = load x.0
+  %1 = lt i8 %0, 0
+  %2 = %1
+  branch %2, b1, b2
+b1:
+  %3 = load done.1
+  %4 = not bool %3
+  %2 = %4
+  jump b2
+b2:
+  branch %2, b3, b4
+b3:
+  %6 = load x.0
+  %7 = neg i8 %6
+  %5 = %7
+  jump b5
+b4:
+  %8 = load x.0
+  %5 = %8
+  jump b5
+b5:
+  store y.2, %5
+  %9 = load y.2
+  exit %9
+b6:
+  exit 0
+"
+    );
+  }
+
+  #[test]
+  fn matches_compare_the_tag_and_take_the_last_arm_otherwise() {
+    let module = lower(
+      "enum E { A(u8), B, C }
+e := E::C
+x := match e {
+  E::A(v) => v,
+  E::B => 1,
+  _ => 2,
+}
+exit(x)
+",
+    );
+
+    assert_eq!(
+      module.to_string(),
+      "fn main():
+b0:
+  %0 = addr E::C.0
+  write u8 %0, 2
+  %1 = addr e.1
+  move %1, %0, 2
+  %3 = addr e.1
+  %4 = read u8 %3
+  %5 = eq u8 %4, 0
+  branch %5, b1, b2
+b1:
+  %6 = add i64 %3, 1
+  %7 = read u8 %6
+  store v.3, %7
+  %8 = load v.3
+  %2 = %8
+  jump b5
+b2:
+  %9 = eq u8 %4, 1
+  branch %9, b3, b4
+b3:
+  %2 = 1
+  jump b5
+b4:
+  %2 = 2
+  jump b5
+b5:
+  store x.2, %2
+  %10 = load x.2
+  exit %10
+b6:
+  exit 0
+"
+    );
+  }
+
+  #[test]
+  fn prints_strings_once_and_bools_as_words() {
+    let module = lower(
+      "s := \"hi\"
+print(s)
+println(1 > 2)
+",
+    );
+
+    assert_eq!(module.strings, vec!["hi", "true", "false", "\n"]);
+    let prints = module
+      .main
+      .blocks
+      .iter()
+      .flat_map(|block| block.insts.iter())
+      .filter_map(|inst| match inst {
+        Inst::Print(_, ty, _) => Some(ty.clone()),
+        _ => None,
+      })
+      .collect::<Vec<_>>();
+    assert_eq!(prints, vec![Type::Str; 4]);
+  }
+}
@@ -2,167 +2,456 @@ use super::*;
 
 #[derive(Debug)]
 pub struct Lexer {
-  input:  Box<str>,
-  pos:    usize,
-  tokens: Vec<Token>,
+  input:    Box<str>,
+  pos:      usize,
+  tokens:   Vec<Token>,
+  errors:   Vec<LexerError>,
+  /// Whether comments are kept as [`Token::Comment`], otherwise they're
+  /// skipped like whitespace.
+  comments: bool,
 }
 
 impl Lexer {
   pub fn new(input: &str) -> Self {
     Self {
-      input:  input.into(),
-      pos:    0,
-      tokens: Vec::new(),
+      input:    input.into(),
+      pos:      0,
+      tokens:   Vec::new(),
+      errors:   Vec::new(),
+      comments: false,
     }
   }
 
-  #[allow(dead_code)]
-  fn pos(&self) -> usize {
-    self.pos
+  /// Whether to keep comments as tokens, for the formatter.
+  pub fn with_comments(mut self, comments: bool) -> Self {
+    self.comments = comments;
+    self
   }
 
-  #[allow(dead_code)]
+  /// Character at the current byte offset.
   fn curr(&self) -> Option<char> {
-    self.input.chars().nth(self.pos)
+    self.input[self.pos..].chars().next()
   }
 
-  #[allow(dead_code)]
+  /// Moves past the current character.
   fn next(&mut self) -> Option<char> {
-    self.pos += 1;
+    if let Some(c) = self.curr() {
+      self.pos += c.len_utf8();
+    }
     self.curr()
   }
 
-  #[allow(dead_code)]
   fn next_n(&mut self, n: usize) -> Option<char> {
-    self.pos += n;
+    for _ in 0..n {
+      self.next();
+    }
     self.curr()
   }
 
-  #[allow(dead_code)]
   fn peek(&self) -> Option<char> {
-    self.input.chars().nth(self.pos + 1)
+    self.peek_n(1)
   }
 
-  #[allow(dead_code)]
+  /// Character `n` characters after the current one.
   fn peek_n(&self, n: usize) -> Option<char> {
-    self.input.chars().nth(self.pos + n)
+    self.input[self.pos..].chars().nth(n)
+  }
+
+  /// Span of the current character.
+  fn char_span(&self) -> Span {
+    let len = self.curr().map_or(0, |c| c.len_utf8());
+    Span::new(self.pos, self.pos + len)
+  }
+
+  fn push_symbol(&mut self, val: Symbol, len: usize) {
+    let start = self.pos;
+
+    self.next_n(len);
+
+    self.tokens.push(Token::Symbol {
+      val,
+      start,
+      end: self.pos,
+    });
   }
 
-  pub fn lex(&mut self) -> Result<Vec<Token>, LexerError> {
+  /// Lexes an integer literal: decimal, or hexadecimal, binary or octal with a
+  /// `0x`, `0b` or `0o` prefix, with optional `_` separators between digits.
+  fn lex_number(&mut self) {
+    let start = self.pos;
+
+    let (radix, name) = match (self.curr(), self.peek()) {
+      (Some('0'), Some('x')) => (16, "hexadecimal"),
+      (Some('0'), Some('b')) => (2, "binary"),
+      (Some('0'), Some('o')) => (8, "octal"),
+      _ => (10, "decimal"),
+    };
+    if radix != 10 {
+      self.next_n(2);
+    }
+
+    let mut digits = String::new();
+    let mut invalid = None;
+
     while let Some(c) = self.curr() {
-      if c.is_ascii_whitespace() {
-        if c == '\n' {
-          self.tokens.push(Token::Newline {
-            start: self.pos,
-            end:   self.pos + 1,
-          });
-        }
+      if c == '_' {
+        self.next();
+      } else if c.is_digit(radix) {
+        digits.push(c);
         self.next();
       } else if c.is_ascii_digit() {
-        let mut num = String::new();
-        let start = self.pos;
+        // e.g. '2' in a binary literal, keep going to report the whole literal
+        invalid.get_or_insert((self.pos, c));
+        digits.push(c);
+        self.next();
+      } else {
+        break;
+      }
+    }
 
-        while let Some(c) = self.curr() {
-          if c.is_ascii_digit() {
-            num.push(c);
-            self.next();
-          } else {
-            break;
-          }
+    if let Some(c) = self.curr() {
+      if c.is_alphabetic() {
+        let suffix = self.pos;
+        while matches!(self.curr(), Some(c) if c.is_alphanumeric() || c == '_') {
+          self.next();
         }
 
-        if let Some(c) = self.curr() {
-          if c.is_ascii_alphabetic() || c == '_' {
-            return Err(LexerError::new(
-              self.pos,
-              "Expected whitespace or symbol after number",
-            ));
-          }
-        }
+        self.errors.push(
+          LexerError::new(
+            Span::new(suffix, self.pos),
+            "Expected whitespace or symbol after number",
+          )
+          .with_note("identifiers can't start with a digit"),
+        );
+      }
+    }
 
-        self.tokens.push(Token::Literal {
-          val: Literal::Integer(num.parse::<i32>().unwrap()),
-          start,
-          end: self.pos,
-        });
-      } else if c.is_ascii_alphabetic() || c == '_' {
-        let mut word = String::new();
-        let start = self.pos;
+    let span = Span::new(start, self.pos);
 
-        while let Some(c) = self.curr() {
-          if c.is_ascii_alphanumeric() || c == '_' {
-            word.push(c);
-            self.next();
-          } else {
-            break;
-          }
-        }
+    let val = if let Some((pos, c)) = invalid {
+      self.errors.push(LexerError::new(
+        Span::new(pos, pos + 1),
+        &format!("Invalid digit '{}' in {} literal", c, name),
+      ));
+      0
+    } else if digits.is_empty() {
+      self.errors.push(LexerError::new(
+        span,
+        &format!("Expected digits in {} literal", name),
+      ));
+      0
+    } else {
+      match u64::from_str_radix(&digits, radix) {
+        Ok(val) => val,
+        Err(_) => {
+          self.errors.push(
+            LexerError::new(span, "Integer literal out of range")
+              .with_note(&format!("the largest integer is {}", u64::MAX)),
+          );
+          0
+        },
+      }
+    };
 
-        match word.as_str() {
-          "exit" => {
-            self.tokens.push(Token::Keyword {
-              val: Keyword::Exit,
-              start,
-              end: self.pos,
-            });
+    let val = match i64::try_from(val) {
+      Ok(val) => Literal::Integer(val),
+      Err(_) => Literal::Unsigned(val),
+    };
+
+    self.tokens.push(Token::Literal {
+      val,
+      start,
+      end: self.pos,
+    });
+  }
+
+  /// Lexes a `//` comment up to the end of the line, or a `/* */` comment,
+  /// which may span lines and nest. Either acts like whitespace.
+  fn lex_comment(&mut self) {
+    let start = self.pos;
+    let mut val = String::new();
+
+    if self.peek() == Some('/') {
+      while let Some(c) = self.curr() {
+        if c == '\n' {
+          break;
+        }
+        val.push(c);
+        self.next();
+      }
+    } else {
+      let mut depth = 0;
+      loop {
+        match (self.curr(), self.peek()) {
+          (Some('/'), Some('*')) => {
+            depth += 1;
+            val += "/*";
+            self.next_n(2);
           },
-          _ => {
-            self.tokens.push(Token::Identifier {
-              val: word,
-              start,
-              end: self.pos,
-            });
+          (Some('*'), Some('/')) => {
+            depth -= 1;
+            val += "*/";
+            self.next_n(2);
+            if depth == 0 {
+              break;
+            }
+          },
+          (Some(c), _) => {
+            val.push(c);
+            self.next();
+          },
+          (None, _) => {
+            self.errors.push(
+              LexerError::new(Span::new(start, start + 2), "Unterminated block comment")
+                .with_note("block comments nest, each '/*' needs its own '*/'"),
+            );
+            break;
           },
         }
-      } else if c == '(' {
-        let start = self.pos;
+      }
+    }
 
-        self.next();
+    if self.comments {
+      self.tokens.push(Token::Comment {
+        val,
+        start,
+        end: self.pos,
+      });
+    }
+  }
 
-        self.tokens.push(Token::Symbol {
-          val: Symbol::LeftParen,
-          start,
-          end: self.pos,
-        });
-      } else if c == ')' {
-        let start = self.pos;
+  /// Lexes a `"` delimited string literal on a single line. The escapes are
+  /// `\n`, `\t`, `\r`, `\0`, `\\`, `\"` and `\x` followed by two hexadecimal
+  /// digits up to `7f`.
+  fn lex_string(&mut self) {
+    let start = self.pos;
+    self.next();
 
-        self.next();
+    let mut val = String::new();
+    loop {
+      match self.curr() {
+        None | Some('\n') => {
+          self.errors.push(
+            LexerError::new(Span::new(start, self.pos), "Unterminated string literal")
+              .with_help("add a closing '\"', use '\\n' for a line break"),
+          );
+          break;
+        },
+        Some('"') => {
+          self.next();
+          break;
+        },
+        Some('\\') => {
+          let escape = self.pos;
+          let c = match self.next() {
+            Some('n') => Some('\n'),
+            Some('t') => Some('\t'),
+            Some('r') => Some('\r'),
+            Some('0') => Some('\0'),
+            Some('\\') => Some('\\'),
+            Some('"') => Some('"'),
+            Some('x') => {
+              let digits = [self.peek(), self.peek_n(2)];
+              let code = match digits {
+                [Some(hi), Some(lo)] => u8::from_str_radix(&format!("{}{}", hi, lo), 16).ok(),
+                _ => None,
+              };
+              match code {
+                Some(code) if code.is_ascii() => {
+                  self.next_n(2);
+                  Some(code as char)
+                },
+                _ => {
+                  self.errors.push(
+                    LexerError::new(Span::new(escape, self.pos + 1), "Invalid '\\x' escape")
+                      .with_note("'\\x' takes two hexadecimal digits up to 7f"),
+                  );
+                  None
+                },
+              }
+            },
+            // the line break or end of input ends the string on the next turn
+            Some('\n') | None => continue,
+            Some(c) => {
+              self.errors.push(
+                LexerError::new(
+                  Span::new(escape, self.pos + c.len_utf8()),
+                  &format!("Unknown escape sequence '\\{}'", c),
+                )
+                .with_note("the escapes are \\n, \\t, \\r, \\0, \\\\, \\\" and \\x"),
+              );
+              None
+            },
+          };
+          val.extend(c);
+          self.next();
+        },
+        Some(c) => {
+          val.push(c);
+          self.next();
+        },
+      }
+    }
 
-        self.tokens.push(Token::Symbol {
-          val: Symbol::RightParen,
-          start,
-          end: self.pos,
-        });
-      } else if c == ':' {
-        let start = self.pos;
+    self.tokens.push(Token::Literal {
+      val: Literal::String(val),
+      start,
+      end: self.pos,
+    });
+  }
 
-        if !matches!(self.peek(), Some('=')) {
-          return Err(LexerError::new(self.pos, "Expected '=' after ':'"));
+  /// Splits the input into tokens in a single pass, with spans in bytes.
+  /// Malformed input is reported but skipped, so every error in the file is
+  /// collected at once.
+  pub fn lex(&mut self) -> Result<Vec<Token>, Vec<LexerError>> {
+    while let Some(c) = self.curr() {
+      if c.is_whitespace() {
+        if c == '\n' {
+          self.tokens.push(Token::Newline {
+            start: self.pos,
+            end:   self.pos + 1,
+          });
         }
+        self.next();
+      } else if c.is_ascii_digit() {
+        self.lex_number();
+      } else if c == '"' {
+        self.lex_string();
+      } else if c.is_alphabetic() || c == '_' {
+        let start = self.pos;
+        while matches!(self.curr(), Some(c) if c.is_alphanumeric() || c == '_') {
+          self.next();
+        }
+        let word = &self.input[start..self.pos];
 
-        self.next_n(2);
+        let end = self.pos;
 
-        self.tokens.push(Token::Symbol {
-          val: Symbol::ColonEquals,
-          start,
-          end: self.pos,
-        });
-      } else if c == '=' {
-        let start = self.pos;
+        let keyword = match word {
+          "exit" => Some(Keyword::Exit),
+          "if" => Some(Keyword::If),
+          "else" => Some(Keyword::Else),
+          "while" => Some(Keyword::While),
+          "fn" => Some(Keyword::Fn),
+          "return" => Some(Keyword::Return),
+          "struct" => Some(Keyword::Struct),
+          "enum" => Some(Keyword::Enum),
+          "match" => Some(Keyword::Match),
+          "as" => Some(Keyword::As),
+          _ => None,
+        };
 
+        let token = match keyword {
+          Some(val) => Token::Keyword { val, start, end },
+          None if word == "true" || word == "false" => Token::Literal {
+            val: Literal::Bool(word == "true"),
+            start,
+            end,
+          },
+          None => Token::Identifier {
+            val: word.to_string(),
+            start,
+            end,
+          },
+        };
+        self.tokens.push(token);
+      } else if c == '(' {
+        self.push_symbol(Symbol::LeftParen, 1);
+      } else if c == ')' {
+        self.push_symbol(Symbol::RightParen, 1);
+      } else if c == '{' {
+        self.push_symbol(Symbol::LeftBrace, 1);
+      } else if c == '}' {
+        self.push_symbol(Symbol::RightBrace, 1);
+      } else if c == '[' {
+        self.push_symbol(Symbol::LeftBracket, 1);
+      } else if c == ']' {
+        self.push_symbol(Symbol::RightBracket, 1);
+      } else if c == ';' {
+        self.push_symbol(Symbol::Semicolon, 1);
+      } else if c == '.' {
+        self.push_symbol(Symbol::Dot, 1);
+      } else if c == ',' {
+        self.push_symbol(Symbol::Comma, 1);
+      } else if c == ':' {
+        if matches!(self.peek(), Some('=')) {
+          self.push_symbol(Symbol::ColonEquals, 2);
+        } else if matches!(self.peek(), Some(':')) {
+          self.push_symbol(Symbol::ColonColon, 2);
+        } else {
+          self.push_symbol(Symbol::Colon, 1);
+        }
+      } else if c == '=' {
+        if matches!(self.peek(), Some('=')) {
+          self.push_symbol(Symbol::EqualsEquals, 2);
+        } else if matches!(self.peek(), Some('>')) {
+          self.push_symbol(Symbol::FatArrow, 2);
+        } else {
+          self.push_symbol(Symbol::Equals, 1);
+        }
+      } else if c == '!' {
+        if matches!(self.peek(), Some('=')) {
+          self.push_symbol(Symbol::BangEquals, 2);
+        } else {
+          self.push_symbol(Symbol::Bang, 1);
+        }
+      } else if c == '<' {
+        if matches!(self.peek(), Some('=')) {
+          self.push_symbol(Symbol::LessEquals, 2);
+        } else {
+          self.push_symbol(Symbol::Less, 1);
+        }
+      } else if c == '>' {
+        if matches!(self.peek(), Some('=')) {
+          self.push_symbol(Symbol::GreaterEquals, 2);
+        } else {
+          self.push_symbol(Symbol::Greater, 1);
+        }
+      } else if c == '&' {
+        if matches!(self.peek(), Some('&')) {
+          self.push_symbol(Symbol::AmpAmp, 2);
+        } else {
+          self.push_symbol(Symbol::Amp, 1);
+        }
+      } else if c == '|' {
+        if matches!(self.peek(), Some('|')) {
+          self.push_symbol(Symbol::PipePipe, 2);
+        } else {
+          self.errors.push(
+            LexerError::new(Span::new(self.pos, self.pos + 1), "Expected '|' after '|'")
+              .with_help("use '||' for logical or"),
+          );
+          self.push_symbol(Symbol::PipePipe, 1);
+        }
+      } else if c == '+' {
+        self.push_symbol(Symbol::Plus, 1);
+      } else if c == '-' {
+        if matches!(self.peek(), Some('>')) {
+          self.push_symbol(Symbol::Arrow, 2);
+        } else {
+          self.push_symbol(Symbol::Minus, 1);
+        }
+      } else if c == '*' {
+        self.push_symbol(Symbol::Star, 1);
+      } else if c == '/' {
+        if matches!(self.peek(), Some('/' | '*')) {
+          self.lex_comment();
+        } else {
+          self.push_symbol(Symbol::Slash, 1);
+        }
+      } else if c == '%' {
+        self.push_symbol(Symbol::Percent, 1);
+      } else if c == '#' {
+        self.errors.push(
+          LexerError::new(self.char_span(), "Unknown character: #")
+            .with_help("use '//' for comments"),
+        );
         self.next();
-
-        self.tokens.push(Token::Symbol {
-          val: Symbol::Equals,
-          start,
-          end: self.pos,
-        });
       } else {
-        return Err(LexerError::new(
-          self.pos,
+        self.errors.push(LexerError::new(
+          self.char_span(),
           &format!("Unknown character: {}", c),
         ));
+        self.next();
       }
     }
 
@@ -171,6 +460,89 @@ impl Lexer {
       end:   self.pos,
     });
 
+    if !self.errors.is_empty() {
+      return Err(std::mem::take(&mut self.errors));
+    }
+
     Ok(self.tokens.clone())
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn unknown_characters_are_skipped() {
+    let errors = Lexer::new("x := 1 $ 2\nexit(x ` 3)\n").lex().unwrap_err();
+    let messages = errors.iter().map(|err| err.msg()).collect::<Vec<_>>();
+
+    assert_eq!(messages, ["Unknown character: $", "Unknown character: `"]);
+  }
+
+  #[test]
+  fn integer_literals_cover_64_bits() {
+    let source = "42 0x2a 0x2A 0b10_1010 0o52 1_000_000 9223372036854775808 18446744073709551615";
+    let literals = Lexer::new(source)
+      .lex()
+      .unwrap()
+      .into_iter()
+      .filter_map(|token| match token {
+        Token::Literal { val, .. } => Some(val),
+        _ => None,
+      })
+      .collect::<Vec<_>>();
+    assert_eq!(literals, [
+      Literal::Integer(42),
+      Literal::Integer(42),
+      Literal::Integer(42),
+      Literal::Integer(42),
+      Literal::Integer(42),
+      Literal::Integer(1_000_000),
+      Literal::Unsigned(1 << 63),
+      Literal::Unsigned(u64::MAX),
+    ]);
+
+    let errors = Lexer::new("18446744073709551616 0x 99999999999999999999999")
+      .lex()
+      .unwrap_err();
+    let messages = errors.iter().map(|err| err.msg()).collect::<Vec<_>>();
+    assert_eq!(messages, [
+      "Integer literal out of range",
+      "Expected digits in hexadecimal literal",
+      "Integer literal out of range"
+    ]);
+  }
+
+  #[test]
+  fn unterminated_comments_point_at_their_start() {
+    let diagnostics = check("x := 1\n/* a /* b */\nexit(x)", "test.pn").unwrap_err();
+
+    assert_eq!(diagnostics.errors.len(), 1);
+    assert_eq!(diagnostics.errors[0].msg, "Unterminated block comment");
+    assert_eq!(diagnostics.errors[0].span, Some(Span::new(7, 9)));
+  }
+
+  #[test]
+  fn spans_are_byte_offsets_into_the_source() {
+    let source = "größe := \"héllo ✓\" // ünïcode\nexit(0)";
+    let tokens = Lexer::new(source).with_comments(true).lex().unwrap();
+    let text = tokens
+      .iter()
+      .map(|token| &source[token.start()..token.end()])
+      .collect::<Vec<_>>();
+    assert_eq!(text[..5], [
+      "größe",
+      ":=",
+      "\"héllo ✓\"",
+      "// ünïcode",
+      "\n"
+    ]);
+
+    let diagnostics = check("é := 1\nx := é ¤", "test.pn").unwrap_err();
+    assert_eq!(diagnostics.errors[0].span, Some(Span::new(16, 18)));
+    assert!(diagnostics.errors[0]
+      .rendered
+      .contains("2 | x := é ¤\n  |        ^"));
+  }
+}
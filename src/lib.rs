@@ -0,0 +1,273 @@
+//! The pecan compiler as a library. The front end stages are usable on their
+//! own:
+//!
+//! ```
+//! use pecan::*;
+//!
+//! let tokens = Lexer::new("exit(1 + 2)").lex().unwrap();
+//! let program = Parser::new(tokens).parse().unwrap();
+//! assert_eq!(program.stmts.len(), 1);
+//! ```
+//!
+//! [`check`] type checks a program, [`lower`] turns it into IR for
+//! [`Generator`], and [`compile`] runs them all at once and stops where asked:
+//!
+//! ```
+//! use pecan::*;
+//!
+//! let module = lower("exit(1 + 2)", &CompileOptions::default()).unwrap();
+//! assert!(Generator::new(module).generate().contains("_start:"));
+//!
+//! let artifact = compile("exit(7)", &CompileOptions::default()).unwrap();
+//! assert!(artifact.data.starts_with(b"\x7fELF"));
+//! ```
+
+mod source;
+pub use source::{Note, SourceMap, Span};
+
+mod error;
+use error::*;
+pub use error::{Diagnostic, Diagnostics, Error, LexerError, ParserError, RuntimeError, TypeError};
+
+mod token;
+pub use token::{Keyword, Literal, Symbol, Token};
+
+mod lexer;
+pub use lexer::Lexer;
+
+mod ast;
+pub use ast::Program;
+use ast::*;
+
+mod parser;
+pub use parser::Parser;
+
+mod checker;
+use checker::*;
+
+mod ir;
+pub use ir::Module;
+use ir::*;
+
+mod regalloc;
+use regalloc::*;
+
+mod optimize;
+pub use optimize::OptLevel;
+use optimize::*;
+
+mod codegen;
+pub use codegen::Generator;
+use codegen::*;
+
+mod assembler;
+use assembler::*;
+
+mod elf;
+use elf::*;
+
+mod interpreter;
+
+mod formatter;
+use formatter::*;
+
+mod dump;
+use dump::*;
+
+/// What the `pn` binary and the benches use beyond the library API, with no
+/// stability promises.
+#[doc(hidden)]
+pub mod internal {
+  pub use super::{
+    assembler::Assembler,
+    elf::write_executable,
+    interpreter::{Interpreter, INTERPRETER_STACK_SIZE},
+    ir::Lowerer,
+    regalloc::promote_locals,
+  };
+}
+
+/// The stage [`compile`] stops after.
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub enum Emit {
+  Tokens,
+  Ast,
+  Symbols,
+  Ir,
+  Asm,
+  Obj,
+  #[default]
+  Exe,
+}
+
+impl Emit {
+  pub fn from_name(name: &str) -> Option<Self> {
+    match name {
+      "tokens" => Some(Emit::Tokens),
+      "ast" => Some(Emit::Ast),
+      "symbols" => Some(Emit::Symbols),
+      "ir" => Some(Emit::Ir),
+      "asm" => Some(Emit::Asm),
+      "obj" => Some(Emit::Obj),
+      "exe" => Some(Emit::Exe),
+      _ => None,
+    }
+  }
+
+  /// Extension of the file written by default.
+  pub fn extension(&self) -> &'static str {
+    match self {
+      Emit::Tokens => "tokens",
+      Emit::Ast => "ast",
+      Emit::Symbols => "symbols",
+      Emit::Ir => "ir",
+      Emit::Asm => "asm",
+      Emit::Obj => "o",
+      Emit::Exe => "",
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompileOptions {
+  /// Name of the source in error messages.
+  pub file_name: String,
+  pub emit:      Emit,
+  pub opt_level: OptLevel,
+}
+
+impl Default for CompileOptions {
+  fn default() -> Self {
+    Self {
+      file_name: "<input>".to_string(),
+      emit:      Emit::Exe,
+      opt_level: OptLevel::default(),
+    }
+  }
+}
+
+/// The output of [`compile`]: a dump, IR, assembly, or the bytes of an ELF
+/// file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Artifact {
+  pub emit: Emit,
+  pub data: Vec<u8>,
+}
+
+/// Lexes `source`, reporting errors against `file_name`.
+pub fn lex(source: &str, file_name: &str) -> Result<Vec<Token>, Diagnostics> {
+  Lexer::new(source)
+    .lex()
+    .map_err(|errs| Diagnostics::from_errors(&errs, &SourceMap::new(file_name, source)))
+}
+
+/// Parses and type checks `source`, returning the program ready to be lowered
+/// or interpreted.
+pub fn check(source: &str, file_name: &str) -> Result<Program, Diagnostics> {
+  let map = SourceMap::new(file_name, source);
+  let tokens = lex(source, file_name)?;
+  let program = Parser::new(tokens)
+    .parse()
+    .map_err(|errs| Diagnostics::from_errors(&errs, &map))?;
+  TypeChecker::new(program)
+    .check()
+    .map_err(|errs| Diagnostics::from_errors(&errs, &map))
+}
+
+/// Formats `source`, keeping its comments. Sources with
+/// syntax errors are refused, their lines could be joined, e.g. after an
+/// unclosed `(`.
+pub fn format(source: &str, file_name: &str) -> Result<String, Diagnostics> {
+  let map = SourceMap::new(file_name, source);
+  Parser::new(lex(source, file_name)?)
+    .parse()
+    .map_err(|errs| Diagnostics::from_errors(&errs, &map))?;
+  let tokens = Lexer::new(source)
+    .with_comments(true)
+    .lex()
+    .map_err(|errs| Diagnostics::from_errors(&errs, &map))?;
+  Ok(Formatter::new(source, tokens).format())
+}
+
+/// Checks `source` and lowers it to IR optimized at `options.opt_level`.
+pub fn lower(source: &str, options: &CompileOptions) -> Result<Module, Diagnostics> {
+  let mut module = Lowerer::new(check(source, &options.file_name)?).lower();
+  optimize(&mut module, options.opt_level);
+  Ok(module)
+}
+
+/// Compiles `source` up to `options.emit`.
+pub fn compile(source: &str, options: &CompileOptions) -> Result<Artifact, Diagnostics> {
+  let data = match options.emit {
+    Emit::Tokens => {
+      let tokens = lex(source, &options.file_name)?;
+      dump_tokens(&tokens, &SourceMap::new(&options.file_name, source)).into_bytes()
+    },
+    Emit::Ast => dump_ast(&check(source, &options.file_name)?).into_bytes(),
+    Emit::Symbols => dump_symbols(&check(source, &options.file_name)?).into_bytes(),
+    Emit::Ir => lower(source, options)?.to_string().into_bytes(),
+    Emit::Asm | Emit::Obj | Emit::Exe => {
+      let asm = Generator::new(lower(source, options)?)
+        .with_registers(options.opt_level != OptLevel::O0)
+        .generate();
+      match options.emit {
+        Emit::Asm => asm.into_bytes(),
+        Emit::Obj => write_object(&Assembler::new(&asm).assemble()?),
+        _ => {
+          let object = Assembler::new(&asm).assemble()?;
+          write_executable(&object.image(), object.entry)
+        },
+      }
+    },
+  };
+
+  Ok(Artifact {
+    emit: options.emit,
+    data,
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn compiles_to_each_stage() {
+    let compile_to = |emit| {
+      let options = CompileOptions {
+        emit,
+        ..Default::default()
+      };
+      compile("x: u8 := 7\nexit(x)", &options).unwrap().data
+    };
+
+    assert!(compile_to(Emit::Exe).starts_with(b"\x7fELF\x02\x01\x01"));
+    assert!(compile_to(Emit::Obj).starts_with(b"\x7fELF\x02\x01\x01"));
+    assert!(String::from_utf8(compile_to(Emit::Asm))
+      .unwrap()
+      .contains("_start:"));
+    assert!(String::from_utf8(compile_to(Emit::Ast))
+      .unwrap()
+      .starts_with("decl x: u8"));
+  }
+
+  #[test]
+  fn reports_diagnostics() {
+    let options = CompileOptions {
+      file_name: "test.pn".to_string(),
+      ..Default::default()
+    };
+    let diagnostics = compile("x: u8 := 256\ny: bool := x\nexit(y)", &options).unwrap_err();
+
+    assert_eq!(diagnostics.errors.len(), 3);
+    assert_eq!(diagnostics.errors[0].kind, "Type error");
+    assert_eq!(
+      diagnostics.errors[0].msg,
+      "Integer literal out of range for u8"
+    );
+    assert!(diagnostics.errors[0].rendered.contains("test.pn"));
+    assert!(diagnostics
+      .to_string()
+      .ends_with("aborting due to 3 errors"));
+  }
+}
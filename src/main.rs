@@ -1,171 +1,271 @@
-use std::process::ExitCode;
+use std::{
+  io::Write,
+  os::unix::{fs::PermissionsExt, process::ExitStatusExt},
+  path::{Path, PathBuf},
+  process::ExitCode,
+};
 
-mod error;
-pub use error::*;
+use pecan::{internal::*, *};
 
-mod token;
-pub use token::*;
+mod cli;
+use cli::*;
 
-mod lexer;
-pub use lexer::*;
-
-mod ast;
-pub use ast::*;
+/// Runs an external program, printing the command line first if `verbose`.
+fn run_command(program: &str, args: &[&str], verbose: bool) -> Result<(), String> {
+  if verbose {
+    eprintln!("running {} {}", program, args.join(" "));
+  }
 
-mod parser;
-pub use parser::*;
+  let status = std::process::Command::new(program)
+    .args(args)
+    .status()
+    .map_err(|err| format!("Failed to execute {}: {}", program, err))?;
 
-mod codegen;
-pub use codegen::*;
+  if !status.success() {
+    return Err(format!("{} failed with {}", program, status));
+  }
 
-fn print_help(this: &str) {
-  println!(
-    "Usage: {} <run option> [options] <file>
+  Ok(())
+}
 
-Run options:
-  -c <file>       Compile to x86_64 linux executable
-  -i <file>       Interpret program
-  -h, --help      Print this help message
+fn write_file(path: &Path, contents: &[u8], verbose: bool) -> Result<(), String> {
+  if verbose {
+    eprintln!("writing {}", path.display());
+  }
+  std::fs::write(path, contents).map_err(|err| format!("{}: {}", path.display(), err))
+}
 
-Options:
-  -o <out file>   Output file name",
-    this
-  );
+/// Writes what `--emit` asked for to `out`, or to stdout if `out` is `-`.
+fn write_output(out: &Path, contents: &[u8], verbose: bool) -> Result<(), String> {
+  if out == Path::new("-") {
+    return std::io::stdout()
+      .write_all(contents)
+      .map_err(|err| err.to_string());
+  }
+  write_file(out, contents, verbose)
 }
 
-fn main() -> ExitCode {
-  let args = std::env::args().collect::<Vec<String>>();
+/// Compiles `source` to `out`, or stops at the stage `--emit` asks for.
+fn build(source: &str, out: &Path, options: &Options) -> Result<(), String> {
+  let compile_options = |emit| CompileOptions {
+    file_name: options.file.clone(),
+    emit,
+    opt_level: options.opt_level,
+  };
 
-  let mut out_file = "a";
+  // the intermediate files only exist when something needs them
+  if !options.nasm && !options.keep_temps || options.emit != Emit::Exe && options.emit != Emit::Obj
+  {
+    let artifact =
+      compile(source, &compile_options(options.emit)).map_err(|err| err.to_string())?;
+    write_output(out, &artifact.data, options.verbose)?;
+    if artifact.emit == Emit::Exe && out != Path::new("-") {
+      std::fs::set_permissions(out, std::fs::Permissions::from_mode(0o755))
+        .map_err(|err| format!("{}: {}", out.display(), err))?;
+    }
+    return Ok(());
+  }
 
-  let file = match args.len() {
-    1 | 2 => {
-      if args.len() == 2 && (args[1] == "-h" || args[1] == "--help") {
-        print_help(&args[0]);
-        return ExitCode::SUCCESS;
-      } else {
-        eprintln!("Usage: {} <run option> [options] <file>", args[0]);
-        return ExitCode::FAILURE;
-      }
-    },
-    3 => {
-      if args[1] == "-c" {
-        args[2].clone()
-      } else if args[1] == "-i" {
-        eprintln!("Interpret not implemented");
-        return ExitCode::FAILURE;
-      } else {
-        eprintln!("Invalid option: {}", args[1]);
-        return ExitCode::FAILURE;
+  let asm = compile(source, &compile_options(Emit::Asm))
+    .map_err(|err| err.to_string())?
+    .data;
+  let asm_file = out.with_extension("asm");
+  let obj_file = match options.emit {
+    Emit::Obj => out.to_path_buf(),
+    _ => out.with_extension("o"),
+  };
+
+  write_file(&asm_file, &asm, options.verbose)?;
+
+  if options.nasm {
+    let (asm_path, obj_path, out_path) = (
+      asm_file.to_string_lossy(),
+      obj_file.to_string_lossy(),
+      out.to_string_lossy(),
+    );
+    run_command(
+      "nasm",
+      &["-f", "elf64", "-o", &obj_path, &asm_path],
+      options.verbose,
+    )?;
+    if options.emit == Emit::Obj {
+      if !options.keep_temps {
+        std::fs::remove_file(&asm_file)
+          .map_err(|err| format!("Failed to remove {}: {}", asm_file.display(), err))?;
       }
-    },
-    5 => {
-      if args[1] == "-c" {
-        if args[2] == "-o" {
-          out_file = &args[3];
-          args[4].clone()
-        } else {
-          eprintln!("Invalid option: {}", args[3]);
-          return ExitCode::FAILURE;
-        }
-      } else {
-        eprintln!("Invalid option: {}", args[1]);
-        return ExitCode::FAILURE;
+      return Ok(());
+    }
+    run_command("ld", &["-s", "-o", &out_path, &obj_path], options.verbose)?;
+
+    if !options.keep_temps {
+      for file in [&asm_file, &obj_file] {
+        std::fs::remove_file(file)
+          .map_err(|err| format!("Failed to remove {}: {}", file.display(), err))?;
       }
-    },
-    _ => {
-      eprintln!("Usage: {} <run option> [options] [file]", args[0]);
+    }
+    return Ok(());
+  }
+
+  let object = compile(source, &compile_options(Emit::Obj)).map_err(|err| err.to_string())?;
+  write_file(&obj_file, &object.data, options.verbose)?;
+  if options.emit == Emit::Obj {
+    return Ok(());
+  }
+
+  let exe = compile(source, &compile_options(Emit::Exe)).map_err(|err| err.to_string())?;
+  write_file(out, &exe.data, options.verbose)?;
+  std::fs::set_permissions(out, std::fs::Permissions::from_mode(0o755))
+    .map_err(|err| format!("{}: {}", out.display(), err))
+}
+
+fn format_file(input: &str, options: &Options) -> ExitCode {
+  let output = match format(input, &options.file) {
+    Ok(output) => output,
+    Err(errs) => {
+      eprintln!("{}", errs);
       return ExitCode::FAILURE;
     },
   };
 
-  let asm_file = &format!("{}.asm", out_file);
-  let obj_file = &format!("{}.o", out_file);
-  let out_file = &format!("{}.out", out_file);
+  if output == input {
+    return ExitCode::SUCCESS;
+  }
 
-  let input = match std::fs::read_to_string(file) {
-    Ok(input) => input,
+  if options.check {
+    eprintln!("{} is not formatted", options.file);
+    return ExitCode::FAILURE;
+  }
+
+  match write_file(Path::new(&options.file), output.as_bytes(), options.verbose) {
+    Ok(()) => ExitCode::SUCCESS,
     Err(err) => {
-      eprintln!("{}", err.to_string());
+      eprintln!("{}", err);
+      ExitCode::FAILURE
+    },
+  }
+}
+
+/// Checks and interprets `input`, on a thread with [`INTERPRETER_STACK_SIZE`].
+fn interpret_file(input: &str, file: &str) -> ExitCode {
+  let program = match check(input, file) {
+    Ok(program) => program,
+    Err(errs) => {
+      eprintln!("{}", errs);
       return ExitCode::FAILURE;
     },
   };
 
-  let mut lexer = Lexer::new(&input);
-
-  let tokens = match lexer.lex() {
-    Ok(tokens) => {
-      // println!("Tokens: {:#?}", tokens);
-      tokens
-    },
+  match Interpreter::new(program).interpret() {
+    Ok(code) => ExitCode::from(code as u8),
     Err(err) => {
-      eprintln!("{}", err.to_string());
-      return ExitCode::FAILURE;
+      eprintln!("{}", err);
+      ExitCode::from(err.code())
     },
-  };
+  }
+}
 
-  let mut parser = Parser::new(tokens);
+fn main() -> ExitCode {
+  let args = std::env::args().skip(1).collect::<Vec<String>>();
 
-  let program = match parser.parse() {
-    Ok(program) => {
-      // println!("Program: {:#?}", program);
-      program
-    },
+  let options = match Options::parse(&args) {
+    Ok(options) => options,
     Err(err) => {
-      eprintln!("{}", err.to_string());
+      eprintln!("{}\n{}\nTry 'pn --help' for more information.", err, USAGE);
       return ExitCode::FAILURE;
     },
   };
 
-  let mut generator = Generator::new(program);
-
-  let output = generator.generate();
+  if options.help {
+    println!("{}", HELP);
+    return ExitCode::SUCCESS;
+  }
 
-  match std::fs::write(asm_file, output) {
-    Ok(_) => (),
+  let input = match std::fs::read_to_string(&options.file) {
+    Ok(input) => input,
     Err(err) => {
-      eprintln!("{}", err.to_string());
+      eprintln!("{}: {}", options.file, err);
       return ExitCode::FAILURE;
     },
-  }
-
-  let status = std::process::Command::new("nasm")
-    .arg("-f")
-    .arg("elf64")
-    .arg("-o")
-    .arg(obj_file)
-    .arg(asm_file)
-    .status()
-    .expect("Failed to execute nasm");
+  };
 
-  if !status.success() {
-    eprintln!("Failed to assemble");
-    return ExitCode::FAILURE;
+  if options.verbose && options.command != Command::Fmt {
+    eprintln!("checking {}", options.file);
   }
 
-  let status = std::process::Command::new("ld")
-    .arg("-s")
-    .arg("-o")
-    .arg(out_file)
-    .arg(obj_file)
-    .status()
-    .expect("Failed to execute ld");
+  match options.command {
+    Command::Fmt => format_file(&input, &options),
+    Command::Check => match check(&input, &options.file) {
+      Ok(_) => ExitCode::SUCCESS,
+      Err(errs) => {
+        eprintln!("{}", errs);
+        ExitCode::FAILURE
+      },
+    },
+    Command::Run if options.interpret => {
+      let file = options.file.clone();
+      std::thread::Builder::new()
+        .stack_size(INTERPRETER_STACK_SIZE)
+        .spawn(move || interpret_file(&input, &file))
+        .and_then(|thread| thread.join().map_err(|_| std::io::ErrorKind::Other.into()))
+        .unwrap_or_else(|err| {
+          eprintln!("Failed to run the interpreter: {}", err);
+          ExitCode::FAILURE
+        })
+    },
+    Command::Build => {
+      let out = match &options.out {
+        Some(out) => PathBuf::from(out),
+        None => Path::new(Path::new(&options.file).file_stem().unwrap())
+          .with_extension(options.emit.extension()),
+      };
+      // e.g. the executable of a source without an extension
+      if std::fs::canonicalize(&out).ok() == std::fs::canonicalize(&options.file).ok() {
+        eprintln!(
+          "Refusing to overwrite the input file {}, choose another output with -o",
+          options.file
+        );
+        return ExitCode::FAILURE;
+      }
 
-  if !status.success() {
-    eprintln!("Failed to link");
-    return ExitCode::FAILURE;
-  }
+      match build(&input, &out, &options) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+          eprintln!("{}", err);
+          ExitCode::FAILURE
+        },
+      }
+    },
+    Command::Run => {
+      let out = std::env::temp_dir().join(format!("pn-{}", std::process::id()));
 
-  let status = std::process::Command::new("rm")
-    .arg(asm_file)
-    .arg(obj_file)
-    .status()
-    .expect("Failed to execute rm");
+      if let Err(err) = build(&input, &out, &options) {
+        eprintln!("{}", err);
+        return ExitCode::FAILURE;
+      }
 
-  if !status.success() {
-    eprintln!("Failed to remove temporary files");
-    return ExitCode::FAILURE;
-  }
+      if options.verbose {
+        eprintln!("running {}", out.display());
+      }
+      let status = std::process::Command::new(&out).status();
+      if !options.keep_temps {
+        let _ = std::fs::remove_file(&out);
+      }
 
-  ExitCode::SUCCESS
+      match status {
+        Ok(status) => match (status.code(), status.signal()) {
+          (Some(code), _) => ExitCode::from(code as u8),
+          // exit like a shell does for a killed child
+          (None, Some(signal)) => {
+            eprintln!("{} was killed by signal {}", out.display(), signal);
+            ExitCode::from((128 + signal) as u8)
+          },
+          (None, None) => ExitCode::FAILURE,
+        },
+        Err(err) => {
+          eprintln!("Failed to execute {}: {}", out.display(), err);
+          ExitCode::FAILURE
+        },
+      }
+    },
+  }
 }
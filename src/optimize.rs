@@ -0,0 +1,484 @@
+use std::collections::{HashMap, HashSet};
+
+use super::*;
+
+#[derive(Debug, Copy, Clone, PartialEq, PartialOrd, Default)]
+pub enum OptLevel {
+  /// Every variable and temp stays in the frame, the IR is generated as is.
+  O0,
+  /// Variables and temps in registers, constants folded within blocks, dead
+  /// stores and unreachable blocks removed.
+  #[default]
+  O1,
+  /// Also propagates constants across blocks, folds branches on them and
+  /// merges blocks, until nothing changes.
+  O2,
+}
+
+impl OptLevel {
+  pub fn from_name(name: &str) -> Option<Self> {
+    match name {
+      "0" => Some(OptLevel::O0),
+      "1" => Some(OptLevel::O1),
+      "2" => Some(OptLevel::O2),
+      _ => None,
+    }
+  }
+}
+
+/// Result of `op` on constant operands of type `ty`, unless it would trap at
+/// run time, e.g. divisions by zero, which are left to happen.
+fn fold_binary(op: BinaryOp, ty: &Type, lhs: i64, rhs: i64) -> Option<i64> {
+  let (ul, ur) = (lhs as u64, rhs as u64);
+  let signed = ty.is_signed();
+  let val = match op {
+    BinaryOp::Add => ty.wrap(lhs.wrapping_add(rhs)),
+    BinaryOp::Sub => ty.wrap(lhs.wrapping_sub(rhs)),
+    BinaryOp::Mul => ty.wrap(lhs.wrapping_mul(rhs)),
+    BinaryOp::Div if signed => ty.wrap(lhs.checked_div(rhs)?),
+    BinaryOp::Div => ty.wrap(ul.checked_div(ur)? as i64),
+    BinaryOp::Mod if signed => ty.wrap(lhs.checked_rem(rhs)?),
+    BinaryOp::Mod => ty.wrap(ul.checked_rem(ur)? as i64),
+    BinaryOp::Eq => (lhs == rhs) as i64,
+    BinaryOp::Ne => (lhs != rhs) as i64,
+    BinaryOp::Lt => (if signed { lhs < rhs } else { ul < ur }) as i64,
+    BinaryOp::Le => (if signed { lhs <= rhs } else { ul <= ur }) as i64,
+    BinaryOp::Gt => (if signed { lhs > rhs } else { ul > ur }) as i64,
+    BinaryOp::Ge => (if signed { lhs >= rhs } else { ul >= ur }) as i64,
+    BinaryOp::And | BinaryOp::Or => return None,
+  };
+  Some(val)
+}
+
+/// Simplifies `inst` knowing the values of the temps in `consts`, and records
+/// the value it gives its destination, if constant.
+fn fold_inst(inst: &mut Inst, consts: &mut HashMap<Temp, i64>) {
+  for val in inst.values_mut() {
+    if let Value::Temp(temp) = val {
+      if let Some(c) = consts.get(temp) {
+        *val = Value::Const(*c);
+      }
+    }
+  }
+
+  let folded = match inst {
+    Inst::Binary(dst, op, ty, Value::Const(lhs), Value::Const(rhs)) => {
+      fold_binary(*op, ty, *lhs, *rhs).map(|val| Inst::Copy(*dst, Value::Const(val)))
+    },
+    // identities, the operand already has the type of the result
+    Inst::Binary(dst, BinaryOp::Add | BinaryOp::Sub, _, lhs, Value::Const(0))
+    | Inst::Binary(dst, BinaryOp::Mul | BinaryOp::Div, _, lhs, Value::Const(1))
+    | Inst::Binary(dst, BinaryOp::Add, _, Value::Const(0), lhs)
+    | Inst::Binary(dst, BinaryOp::Mul, _, Value::Const(1), lhs) => Some(Inst::Copy(*dst, *lhs)),
+    Inst::Unary(dst, UnaryOp::Neg, ty, Value::Const(val)) => {
+      Some(Inst::Copy(*dst, Value::Const(ty.wrap(val.wrapping_neg()))))
+    },
+    Inst::Unary(dst, UnaryOp::Not, _, Value::Const(val)) => {
+      Some(Inst::Copy(*dst, Value::Const((*val == 0) as i64)))
+    },
+    Inst::Cast(dst, ty, Value::Const(val)) => Some(Inst::Copy(*dst, Value::Const(ty.wrap(*val)))),
+    _ => None,
+  };
+  if let Some(folded) = folded {
+    *inst = folded;
+  }
+
+  if let Some(dst) = inst.dst() {
+    match inst {
+      Inst::Copy(_, Value::Const(c)) => consts.insert(dst, *c),
+      _ => consts.remove(&dst),
+    };
+  }
+}
+
+/// Constants known on entry to each block, `None` for blocks not reached.
+/// A temp is known if it has the same value on every path to the block.
+fn constants_in(function: &IrFunction) -> Vec<Option<HashMap<Temp, i64>>> {
+  let mut consts_in: Vec<Option<HashMap<Temp, i64>>> = vec![None; function.blocks.len()];
+  consts_in[0] = Some(HashMap::new());
+
+  let mut changed = true;
+  while changed {
+    changed = false;
+    for (i, block) in function.blocks.iter().enumerate() {
+      let Some(mut consts) = consts_in[i].clone() else {
+        continue;
+      };
+      for inst in block.insts.iter() {
+        fold_inst(&mut inst.clone(), &mut consts);
+      }
+
+      for succ in block.term.successors() {
+        let merged = match &consts_in[succ] {
+          None => consts.clone(),
+          Some(known) => known
+            .iter()
+            .filter(|(temp, c)| consts.get(temp) == Some(c))
+            .map(|(temp, c)| (*temp, *c))
+            .collect(),
+        };
+        if consts_in[succ].as_ref() != Some(&merged) {
+          consts_in[succ] = Some(merged);
+          changed = true;
+        }
+      }
+    }
+  }
+
+  consts_in
+}
+
+/// Replaces temps known to be constant with their value and evaluates
+/// instructions on constants, within each block or across blocks if `global`.
+/// Branches on constants become jumps.
+pub fn propagate_constants(function: &mut IrFunction, global: bool) {
+  let consts_in = if global {
+    constants_in(function)
+  } else {
+    Vec::new()
+  };
+
+  for (i, block) in function.blocks.iter_mut().enumerate() {
+    let mut consts = consts_in.get(i).cloned().flatten().unwrap_or_default();
+    for inst in block.insts.iter_mut() {
+      fold_inst(inst, &mut consts);
+    }
+
+    if let Some(val) = block.term.value_mut() {
+      if let Value::Temp(temp) = val {
+        if let Some(c) = consts.get(temp) {
+          *val = Value::Const(*c);
+        }
+      }
+    }
+    if let Terminator::Branch(Value::Const(c), then, other) = block.term {
+      block.term = Terminator::Jump(if c != 0 { then } else { other });
+    }
+  }
+}
+
+/// Whether `inst` is a division that can trap at run time, by zero or of the
+/// smallest integer by -1, and has to stay even if its result is never read.
+fn may_trap(inst: &Inst) -> bool {
+  match inst {
+    Inst::Binary(_, BinaryOp::Div | BinaryOp::Mod, ty, _, Value::Const(rhs)) => {
+      *rhs == 0 || ty.is_signed() && *rhs == -1
+    },
+    Inst::Binary(_, BinaryOp::Div | BinaryOp::Mod, ..) => true,
+    _ => false,
+  }
+}
+
+/// Removes instructions whose result is never read, including stores to
+/// variables overwritten before they're read, copies of a temp to itself and
+/// bounds checks of constant indexes that pass. Calls are kept for their side
+/// effects, and divisions that may trap.
+pub fn eliminate_dead_stores(function: &mut IrFunction) {
+  let live_in = function.live_in();
+
+  for block in function.blocks.iter_mut() {
+    let mut live = block
+      .term
+      .successors()
+      .into_iter()
+      .flat_map(|succ| live_in[succ].iter().copied())
+      .collect::<HashSet<_>>();
+    live.extend(block.term.uses());
+
+    let mut insts = Vec::new();
+    for inst in std::mem::take(&mut block.insts).into_iter().rev() {
+      let dead = match (&inst, inst.dst()) {
+        (inst, _) if inst.is_call() || may_trap(inst) => false,
+        (Inst::Copy(dst, Value::Temp(src)), _) if dst == src => true,
+        (Inst::Check(Value::Const(index), len), _) => (*index as u64) < *len as u64,
+        (_, Some(dst)) => !live.contains(&dst),
+        (_, None) => false,
+      };
+      if dead {
+        continue;
+      }
+
+      if let Some(dst) = inst.dst() {
+        live.remove(&dst);
+      }
+      live.extend(inst.uses());
+      insts.push(inst);
+    }
+
+    insts.reverse();
+    block.insts = insts;
+  }
+}
+
+/// Drops the blocks no path from the entry reaches, e.g. code after an `exit`
+/// or `return`, keeping the order of the others.
+pub fn remove_unreachable(function: &mut IrFunction) {
+  let mut reached = vec![false; function.blocks.len()];
+  let mut stack = vec![0];
+  while let Some(i) = stack.pop() {
+    if reached[i] {
+      continue;
+    }
+    reached[i] = true;
+    stack.extend(function.blocks[i].term.successors());
+  }
+
+  let mut ids = vec![0; function.blocks.len()];
+  let mut next = 0;
+  for (i, reached) in reached.iter().enumerate() {
+    if *reached {
+      ids[i] = next;
+      next += 1;
+    }
+  }
+
+  let blocks = std::mem::take(&mut function.blocks);
+  for (i, mut block) in blocks.into_iter().enumerate() {
+    if !reached[i] {
+      continue;
+    }
+    block.term = match block.term {
+      Terminator::Jump(target) => Terminator::Jump(ids[target]),
+      Terminator::Branch(cond, then, other) => Terminator::Branch(cond, ids[then], ids[other]),
+      term => term,
+    };
+    function.blocks.push(block);
+  }
+}
+
+/// Sends jumps to empty blocks straight to where those go, and merges blocks
+/// into their only predecessor when it always jumps to them.
+pub fn simplify_jumps(function: &mut IrFunction) {
+  // where an empty block ends up jumping, following chains of them
+  let forward = |blocks: &[BasicBlock], mut target: usize| {
+    let mut seen = HashSet::new();
+    while let (true, Terminator::Jump(next)) = (
+      target != 0 && blocks[target].insts.is_empty(),
+      &blocks[target].term,
+    ) {
+      if !seen.insert(target) {
+        break;
+      }
+      target = *next;
+    }
+    target
+  };
+
+  for i in 0..function.blocks.len() {
+    let term = match function.blocks[i].term.clone() {
+      Terminator::Jump(target) => Terminator::Jump(forward(&function.blocks, target)),
+      Terminator::Branch(cond, then, other) => {
+        let (then, other) = (
+          forward(&function.blocks, then),
+          forward(&function.blocks, other),
+        );
+        if then == other {
+          Terminator::Jump(then)
+        } else {
+          Terminator::Branch(cond, then, other)
+        }
+      },
+      term => term,
+    };
+    function.blocks[i].term = term;
+  }
+
+  let preds = function.predecessors();
+  for i in 0..function.blocks.len() {
+    // a merged block is emptied and left unreachable, chains merge into the
+    // first block one at a time
+    while let Terminator::Jump(target) = function.blocks[i].term {
+      if target == i || target == 0 || preds[target].len() != 1 {
+        break;
+      }
+      let merged = std::mem::replace(&mut function.blocks[target], BasicBlock {
+        insts: Vec::new(),
+        term:  Terminator::Jump(target),
+      });
+      function.blocks[i].insts.extend(merged.insts);
+      function.blocks[i].term = merged.term;
+    }
+  }
+
+  remove_unreachable(function);
+}
+
+/// Runs the passes of `level` over every function of `module`.
+pub fn optimize(module: &mut Module, level: OptLevel) {
+  if level == OptLevel::O0 {
+    return;
+  }
+
+  for function in module.functions_mut() {
+    promote_locals(function);
+
+    loop {
+      let before = function.clone();
+      propagate_constants(function, level == OptLevel::O2);
+      eliminate_dead_stores(function);
+      remove_unreachable(function);
+      if level == OptLevel::O1 {
+        break;
+      }
+
+      simplify_jumps(function);
+      if *function == before {
+        break;
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn optimized(input: &str, level: OptLevel) -> String {
+    let program = check(input, "test.pn").unwrap();
+    let mut module = Lowerer::new(program).lower();
+    optimize(&mut module, level);
+    module.to_string()
+  }
+
+  #[test]
+  fn overwritten_stores_are_removed() {
+    let input = "code := 100\ncode = 69\nexit(code)\n";
+
+    assert_eq!(
+      optimized(input, OptLevel::O1),
+      "fn main():
+b0:
+  exit 69
+"
+    );
+    assert_eq!(optimized(input, OptLevel::O0).matches("store").count(), 2);
+  }
+
+  #[test]
+  fn folds_with_the_semantics_of_the_type() {
+    let output = optimized(
+      "a: u8 := 200 + 100
+b: i8 := -128 / -1
+c: u32 := 0 - 1
+d := c > 5
+e := 7 / (a - 44)
+exit(e)
+",
+      OptLevel::O1,
+    );
+
+    assert_eq!(
+      output,
+      "fn main():
+b0:
+  %13 = div u8 7, 0
+  exit %13
+"
+    );
+  }
+
+  #[test]
+  fn casts_wrap_to_the_target_type() {
+    let input = "a: u8 := 200\nb := a as i8 as i64 + 300 as u8 as i64\nexit(b)\n";
+
+    assert!(optimized(input, OptLevel::O0).contains("%1 = cast i8 %0\n  %2 = cast i64 %1\n"));
+    assert_eq!(
+      optimized(input, OptLevel::O1),
+      "fn main():
+b0:
+  exit -12
+"
+    );
+  }
+
+  #[test]
+  fn constants_propagate_across_blocks() {
+    let input = "fn f(n) {
+  return n
+}
+x := 3
+if x > 2 {
+  x = f(x)
+} else {
+  exit(1)
+}
+while x > 10 {
+  x = x - 1
+}
+exit(x)
+";
+
+    assert_eq!(
+      optimized(input, OptLevel::O2),
+      "fn main():
+b0:
+  %9 = call f(3)
+  jump b1
+b1:
+  %5 = gt i64 %9, 10
+  branch %5, b2, b3
+b2:
+  %9 = sub i64 %9, 1
+  jump b1
+b3:
+  exit %9
+
+fn f(n.0: i64):
+b0:
+  %2 = param 0
+  return %2
+"
+    );
+
+    // without looking across blocks only the dead code goes
+    let o1 = optimized(input, OptLevel::O1);
+    assert!(o1.contains("branch"));
+    assert!(!o1.contains("exit 0"));
+  }
+
+  #[test]
+  fn only_failing_bounds_checks_remain() {
+    let output = optimized(
+      "a := [1, 2, 3]
+i := 2
+a[i] = 5
+exit(a[i + 1])
+",
+      OptLevel::O1,
+    );
+
+    assert_eq!(output.matches("check").count(), 1);
+    assert!(output.contains("check 3, 3"));
+    // the array stays in the frame
+    assert!(output.contains("addr a.1"));
+  }
+
+  #[test]
+  fn unused_divisions_are_kept_if_they_may_trap() {
+    let output = optimized(
+      "y := 0\nz := 5 / y\nw := 7 % y\nv := 9 / 2\nexit(3)\n",
+      OptLevel::O2,
+    );
+
+    assert!(output.contains("div i64 5, 0"));
+    assert!(output.contains("mod i64 7, 0"));
+    assert!(!output.contains("div i64 9, 2"));
+  }
+
+  #[test]
+  fn locals_behind_pointers_stay_in_memory() {
+    let output = optimized("x := 1\np := &x\n*p = 5\nexit(x)\n", OptLevel::O2);
+
+    assert_eq!(
+      output,
+      "fn main():
+b0:
+  store x.0, 1
+  %4 = addr x.0
+  write i64 %4, 5
+  %2 = load x.0
+  exit %2
+"
+    );
+  }
+}
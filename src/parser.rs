@@ -1,3 +1,5 @@
+use std::rc::Rc;
+
 use super::*;
 
 #[derive(Debug)]
@@ -5,6 +7,13 @@ pub struct Parser {
   tokens:  Vec<Token>,
   pos:     usize,
   program: Program,
+  /// Calls seen so far as `(name, argument count, span)`, checked once the
+  /// whole program is parsed so functions can be called before they're
+  /// declared.
+  calls:   Vec<(String, usize, Span)>,
+  /// Errors that didn't stop parsing, either because they aren't syntax errors
+  /// or because the parser recovered from them.
+  errors:  Vec<ParserError>,
 }
 
 impl Parser {
@@ -12,55 +21,42 @@ impl Parser {
     Self {
       pos: 0,
       program: Program::new(),
+      calls: Vec::new(),
+      errors: Vec::new(),
       tokens,
     }
   }
 
-  #[allow(dead_code)]
   fn curr(&self) -> &Token {
     if self.pos >= self.tokens.len() {
-      return self.tokens.last().unwrap();
+      self.tokens.last().unwrap()
     } else {
-      return self.tokens.get(self.pos).unwrap();
+      self.tokens.get(self.pos).unwrap()
     }
   }
 
-  #[allow(dead_code)]
   fn next(&mut self) {
     self.pos += 1;
   }
 
-  #[allow(dead_code)]
   fn next_n(&mut self, n: usize) {
     self.pos += n;
   }
 
-  #[allow(dead_code)]
-  fn peek(&self) -> &Token {
-    if self.pos + 1 >= self.tokens.len() {
-      return self.tokens.last().unwrap();
-    } else {
-      return self.tokens.get(self.pos + 1).unwrap();
-    }
-  }
-
-  #[allow(dead_code)]
   fn peek_n(&self, n: usize) -> &Token {
     if self.pos + n >= self.tokens.len() {
-      return self.tokens.last().unwrap();
+      self.tokens.last().unwrap()
     } else {
-      return self.tokens.get(self.pos + n).unwrap();
+      self.tokens.get(self.pos + n).unwrap()
     }
   }
 
-  #[allow(dead_code)]
   fn skip_newlines(&mut self) {
     while matches!(self.curr(), Token::Newline { .. }) {
       self.next();
     }
   }
 
-  #[allow(dead_code)]
   fn expect_keyword(&mut self, kw: Keyword) -> Result<(), ParserError> {
     loop {
       match self.curr() {
@@ -74,11 +70,11 @@ impl Parser {
         },
         t => {
           return Err(ParserError::new(
-            t.start(),
+            t.span(),
             &format!(
               "Unexpected {}, expected '{}'",
               self.curr().type_to_string(),
-              kw.to_string()
+              kw
             ),
           ))
         },
@@ -86,7 +82,6 @@ impl Parser {
     }
   }
 
-  #[allow(dead_code)]
   fn expect_identifier(&mut self) -> Result<String, ParserError> {
     loop {
       match self.curr() {
@@ -101,7 +96,7 @@ impl Parser {
         },
         t => {
           return Err(ParserError::new(
-            t.start(),
+            t.span(),
             &format!(
               "Unexpected {}, expected identifier",
               self.curr().type_to_string()
@@ -112,7 +107,6 @@ impl Parser {
     }
   }
 
-  #[allow(dead_code)]
   fn expect_literal(&mut self) -> Result<Literal, ParserError> {
     loop {
       match self.curr() {
@@ -127,7 +121,7 @@ impl Parser {
         },
         t => {
           return Err(ParserError::new(
-            t.start(),
+            t.span(),
             &format!(
               "Unexpected {}, expected literal",
               self.curr().type_to_string()
@@ -138,14 +132,17 @@ impl Parser {
     }
   }
 
-  #[allow(dead_code)]
-  fn expect_literal_int(&mut self) -> Result<i32, ParserError> {
+  fn expect_literal_int(&mut self) -> Result<i64, ParserError> {
     match self.expect_literal()? {
       Literal::Integer(i) => Ok(i),
+      Literal::Unsigned(u) => Ok(u as i64),
+      Literal::String(_) | Literal::Bool(_) => Err(ParserError::new(
+        self.tokens.get(self.pos - 1).unwrap().span(),
+        "Unexpected literal, expected integer",
+      )),
     }
   }
 
-  #[allow(dead_code)]
   fn expect_symbol(&mut self, sym: Symbol) -> Result<(), ParserError> {
     loop {
       match self.curr() {
@@ -159,11 +156,11 @@ impl Parser {
         },
         t => {
           return Err(ParserError::new(
-            t.start(),
+            t.span(),
             &format!(
               "Unexpected {}, expected symbol '{}'",
               self.curr().type_to_string(),
-              sym.to_string()
+              sym
             ),
           ))
         },
@@ -171,18 +168,481 @@ impl Parser {
     }
   }
 
-  fn type_of(&self, expr: &Expr) -> Type {
-    match expr {
-      Expr::Literal(lit) => match lit {
-        Literal::Integer(_) => Type::Integer,
+  /// Parses a type name such as `u8` or the name of a struct or enum, an array
+  /// type such as `[u8; 4]` or a pointer type such as `*u8`.
+  fn parse_type(&mut self) -> Result<Type, ParserError> {
+    if let Token::Symbol {
+      val: Symbol::Star, ..
+    } = self.curr()
+    {
+      self.next();
+      return Ok(Type::Pointer(Box::new(self.parse_type()?)));
+    }
+
+    if let Token::Symbol {
+      val: Symbol::LeftBracket,
+      ..
+    } = self.curr()
+    {
+      self.next();
+      let elem = self.parse_type()?;
+      self.expect_symbol(Symbol::Semicolon)?;
+      let span = self.curr().span();
+      let len = self.expect_literal_int()?;
+      self.expect_symbol(Symbol::RightBracket)?;
+
+      // the frame is addressed with 32-bit displacements
+      let len = usize::try_from(len).ok();
+      return match len.and_then(|len| elem.size().checked_mul(len)) {
+        Some(size) if size <= i32::MAX as usize => Ok(Type::Array(Box::new(elem), len.unwrap())),
+        _ => Err(
+          ParserError::new(span, "Array length out of range")
+            .with_note(&format!("arrays take at most {} bytes", i32::MAX)),
+        ),
+      };
+    }
+
+    let name = self.expect_identifier()?;
+
+    if let Some(def) = self.program.find_struct(&name) {
+      return Ok(Type::Struct(def.clone()));
+    }
+    if let Some(def) = self.program.find_enum(&name) {
+      return Ok(Type::Enum(def.clone()));
+    }
+    Type::from_name(&name).ok_or_else(|| {
+      ParserError::new(
+        self.tokens.get(self.pos - 1).unwrap().span(),
+        &format!("Unknown type '{}'", name),
+      )
+      .with_note(
+        "the types are i8, i16, i32, i64, u8, u16, u32, u64, bool, str, arrays, pointers and the \
+         structs and enums declared above",
+      )
+    })
+  }
+
+  /// Parses an optional `: type` annotation.
+  fn parse_annotation(&mut self) -> Result<Option<Type>, ParserError> {
+    match self.curr() {
+      Token::Symbol {
+        val: Symbol::Colon, ..
+      } => {
+        self.next();
+        Ok(Some(self.parse_type()?))
+      },
+      _ => Ok(None),
+    }
+  }
+
+  /// Parses the argument list of a call to `ident`, whose name at `span` was
+  /// just consumed.
+  fn parse_call(&mut self, ident: String, span: Span) -> Result<Expr, ParserError> {
+    let args = self.parse_args()?;
+    let span = span.to(self.tokens.get(self.pos - 1).unwrap().span());
+
+    if let Some(builtin) = Builtin::from_name(&ident) {
+      if !builtin.takes(args.len()) {
+        self.errors.push(ParserError::new(
+          span,
+          &format!(
+            "Builtin '{}' takes {} but {} were given",
+            builtin,
+            match builtin {
+              Builtin::Print => "1 argument",
+              Builtin::Println => "0 or 1 arguments",
+            },
+            args.len()
+          ),
+        ));
+      }
+      return Ok(Expr::new(ExprKind::Builtin(builtin, args), span));
+    }
+
+    self.calls.push((ident.clone(), args.len(), span));
+
+    Ok(Expr::new(ExprKind::Call(ident, args), span))
+  }
+
+  /// Parses a parenthesized, comma separated list of expressions.
+  fn parse_args(&mut self) -> Result<Vec<Expr>, ParserError> {
+    self.expect_symbol(Symbol::LeftParen)?;
+
+    let mut args = Vec::new();
+    self.skip_newlines();
+    if !matches!(self.curr(), Token::Symbol {
+      val: Symbol::RightParen,
+      ..
+    }) {
+      loop {
+        args.push(self.parse_expr()?);
+        self.skip_newlines();
+        if let Token::Symbol {
+          val: Symbol::Comma, ..
+        } = self.curr()
+        {
+          self.next();
+          continue;
+        }
+        break;
+      }
+    }
+
+    self.expect_symbol(Symbol::RightParen)?;
+    Ok(args)
+  }
+
+  /// Parses the `::Variant` following the name of an enum at `span`, giving
+  /// the enum and the index of the variant.
+  fn parse_path(&mut self, ident: &str, span: Span) -> Result<(Rc<Enum>, usize), ParserError> {
+    self.expect_symbol(Symbol::ColonColon)?;
+    let name = self.expect_identifier()?;
+    let name_span = self.tokens.get(self.pos - 1).unwrap().span();
+
+    let Some(def) = self.program.find_enum(ident).cloned() else {
+      return Err(ParserError::new(
+        span,
+        &format!("Enum '{}' not declared", ident),
+      ));
+    };
+    match def.variant(&name) {
+      Some(index) => Ok((def, index)),
+      None => Err(ParserError::new(
+        name_span,
+        &format!("Enum {} has no variant '{}'", def.name, name),
+      )),
+    }
+  }
+
+  /// Checks `given` values were written for the variant at `index`, reporting
+  /// the path at `span` otherwise.
+  fn check_variant_len(&mut self, def: &Enum, index: usize, given: usize, span: Span) {
+    let len = def.variants[index].fields.len();
+    if len != given {
+      self.errors.push(ParserError::new(
+        span,
+        &format!(
+          "Variant '{}' takes {} values but {} were given",
+          def.path(index),
+          len,
+          given
+        ),
+      ));
+    }
+  }
+
+  /// Parses `Enum::Variant` or `Enum::Variant(a, b)`, whose enum name at
+  /// `start` was just consumed, and declares the hidden variable it's built in.
+  fn parse_variant(&mut self, ident: &str, start: Span) -> Result<Expr, ParserError> {
+    let (def, index) = self.parse_path(ident, start)?;
+
+    let values = match self.curr() {
+      Token::Symbol {
+        val: Symbol::LeftParen,
+        ..
+      } => self.parse_args()?,
+      _ => Vec::new(),
+    };
+    let span = start.to(self.tokens.get(self.pos - 1).unwrap().span());
+    self.check_variant_len(&def, index, values.len(), span);
+
+    let var = self.declare_hidden(&def.path(index), Type::Enum(def.clone()));
+    Ok(Expr::new(ExprKind::Variant(var, def, index, values), span))
+  }
+
+  /// Parses the pattern of a match arm, declaring the variables it binds in the
+  /// current scope.
+  fn parse_pattern(&mut self) -> Result<Pattern, ParserError> {
+    self.skip_newlines();
+    let start = self.curr().span();
+
+    let kind = match self.curr().clone() {
+      Token::Identifier { val, .. } if val == "_" => {
+        self.next();
+        PatternKind::Wildcard
+      },
+      Token::Identifier { val, .. } => {
+        self.next();
+        if !matches!(self.curr(), Token::Symbol {
+          val: Symbol::ColonColon,
+          ..
+        }) {
+          return Err(
+            ParserError::new(start, "Unexpected identifier, expected pattern")
+              .with_help("use '_' to match any value"),
+          );
+        }
+        let (def, index) = self.parse_path(&val, start)?;
+
+        let mut bindings = Vec::new();
+        if let Token::Symbol {
+          val: Symbol::LeftParen,
+          ..
+        } = self.curr()
+        {
+          self.next();
+          let fields = def.variants[index].fields.clone();
+          loop {
+            self.skip_newlines();
+            if let Token::Symbol {
+              val: Symbol::RightParen,
+              ..
+            } = self.curr()
+            {
+              break;
+            }
+            let name = self.expect_identifier()?;
+            let span = self.tokens.get(self.pos - 1).unwrap().span();
+            let ty = fields.get(bindings.len()).cloned().unwrap_or(Type::I64);
+            bindings.push(self.declare_binding(name, ty, span));
+            self.skip_newlines();
+            match self.curr() {
+              Token::Symbol {
+                val: Symbol::Comma, ..
+              } => self.next(),
+              _ => break,
+            }
+          }
+          self.expect_symbol(Symbol::RightParen)?;
+        }
+        let span = start.to(self.tokens.get(self.pos - 1).unwrap().span());
+        self.check_variant_len(&def, index, bindings.len(), span);
+
+        PatternKind::Variant(def, index, bindings)
+      },
+      Token::Literal {
+        val: val @ (Literal::Integer(_) | Literal::Unsigned(_) | Literal::Bool(_)),
+        ..
+      } => {
+        self.next();
+        PatternKind::Literal(val)
+      },
+      Token::Symbol {
+        val: Symbol::Minus, ..
+      } => match self.parse_primary()?.kind {
+        ExprKind::Literal(lit) => PatternKind::Literal(lit),
+        _ => {
+          let span = start.to(self.tokens.get(self.pos - 1).unwrap().span());
+          return Err(ParserError::new(span, "Expected a literal pattern"));
+        },
       },
-      Expr::Identifier(ident) => {
-        todo!("type_of: {}", ident);
+      t => {
+        return Err(ParserError::new(
+          t.span(),
+          &format!("Unexpected {}, expected pattern", t.type_to_string()),
+        ))
       },
+    };
+
+    let span = start.to(self.tokens.get(self.pos - 1).unwrap().span());
+    Ok(Pattern { kind, span })
+  }
+
+  /// Declares a variable bound by a pattern, nothing for `_`.
+  fn declare_binding(&mut self, name: String, ty: Type, span: Span) -> Option<VarRef> {
+    if name == "_" {
+      return None;
     }
+    if self.program.find_entry(&name, true).is_some() {
+      self.errors.push(ParserError::new(
+        span,
+        &format!("Variable '{}' already bound in this pattern", name),
+      ));
+      return None;
+    }
+    self.program.push_entry(name.clone(), ty);
+    self.program.resolve(&name, true)
   }
 
-  fn parse_expr(&mut self) -> Result<Expr, ParserError> {
+  /// Parses the `{ pattern => arm ... }` of a match, each arm in its own scope
+  /// holding the variables the pattern binds. `parse_arm` parses what follows
+  /// the `=>` and closes the scope.
+  fn parse_arms<T>(
+    &mut self,
+    mut parse_arm: impl FnMut(&mut Self, usize) -> Result<T, ParserError>,
+  ) -> Result<Vec<(Pattern, T)>, ParserError> {
+    self.expect_symbol(Symbol::LeftBrace)?;
+
+    let mut arms = Vec::new();
+    loop {
+      self.skip_newlines();
+      if let Token::Symbol {
+        val: Symbol::RightBrace,
+        ..
+      } = self.curr()
+      {
+        break;
+      }
+
+      let table = self.program.push_scope(ScopeType::Block);
+      let pattern = match self
+        .parse_pattern()
+        .and_then(|pattern| self.expect_symbol(Symbol::FatArrow).map(|_| pattern))
+      {
+        Ok(pattern) => pattern,
+        Err(err) => {
+          self.program.pop_scope();
+          return Err(err);
+        },
+      };
+      arms.push((pattern, parse_arm(self, table)?));
+
+      match self.curr() {
+        Token::Symbol {
+          val: Symbol::Comma, ..
+        } => self.next(),
+        Token::Newline { .. }
+        | Token::Symbol {
+          val: Symbol::RightBrace,
+          ..
+        } => (),
+        t => {
+          return Err(ParserError::new(
+            t.span(),
+            &format!(
+              "Unexpected {}, expected ',' or newline after match arm",
+              t.type_to_string()
+            ),
+          ))
+        },
+      }
+    }
+
+    self.expect_symbol(Symbol::RightBrace)?;
+    Ok(arms)
+  }
+
+  /// Parses `match value { pattern => expr, ... }` used as a value.
+  fn parse_match(&mut self) -> Result<Expr, ParserError> {
+    let start = self.curr().span();
+    self.expect_keyword(Keyword::Match)?;
+
+    let value = self.parse_expr()?;
+    let arms = self.parse_arms(|parser, _| {
+      let expr = parser.parse_expr();
+      parser.program.pop_scope();
+      expr
+    })?;
+
+    let span = start.to(self.tokens.get(self.pos - 1).unwrap().span());
+    Ok(Expr::new(ExprKind::Match(Box::new(value), arms), span))
+  }
+
+  /// Parses `[a, b, c]` and declares the hidden variable the array is built in,
+  /// whose type the type checker fills in.
+  fn parse_array(&mut self) -> Result<Expr, ParserError> {
+    let start = self.curr().span();
+    self.expect_symbol(Symbol::LeftBracket)?;
+
+    let mut elems = Vec::new();
+    loop {
+      self.skip_newlines();
+      if let Token::Symbol {
+        val: Symbol::RightBracket,
+        ..
+      } = self.curr()
+      {
+        break;
+      }
+      elems.push(self.parse_expr()?);
+      self.skip_newlines();
+      match self.curr() {
+        Token::Symbol {
+          val: Symbol::Comma, ..
+        } => self.next(),
+        _ => break,
+      }
+    }
+
+    self.expect_symbol(Symbol::RightBracket)?;
+    let span = start.to(self.tokens.get(self.pos - 1).unwrap().span());
+
+    let var = self.declare_hidden("[]", Type::I64);
+    Ok(Expr::new(ExprKind::Array(var, elems), span))
+  }
+
+  /// Parses the `{ field: value, ... }` of a literal of the struct `def`, whose
+  /// name at `start` was just consumed, and declares the hidden variable it's
+  /// built in.
+  fn parse_struct_literal(&mut self, def: Rc<Struct>, start: Span) -> Result<Expr, ParserError> {
+    self.expect_symbol(Symbol::LeftBrace)?;
+
+    let mut fields = Vec::new();
+    loop {
+      self.skip_newlines();
+      if let Token::Symbol {
+        val: Symbol::RightBrace,
+        ..
+      } = self.curr()
+      {
+        break;
+      }
+      let name = self.expect_identifier()?;
+      self.expect_symbol(Symbol::Colon)?;
+      fields.push((name, self.parse_expr()?));
+      self.skip_newlines();
+      match self.curr() {
+        Token::Symbol {
+          val: Symbol::Comma, ..
+        } => self.next(),
+        _ => break,
+      }
+    }
+
+    self.expect_symbol(Symbol::RightBrace)?;
+    let span = start.to(self.tokens.get(self.pos - 1).unwrap().span());
+
+    let var = self.declare_hidden(&format!("{}{{}}", def.name), Type::Struct(def.clone()));
+    Ok(Expr::new(ExprKind::Struct(var, def, fields), span))
+  }
+
+  /// Declares a variable for a value built in place, named so no identifier
+  /// can refer to it.
+  fn declare_hidden(&mut self, name: &str, ty: Type) -> VarRef {
+    self.program.push_entry(name.to_string(), ty);
+    let table = self.program.curr_table;
+    VarRef {
+      name: name.to_string(),
+      table,
+      index: self.program.symbol_tables[table].entries.len() - 1,
+    }
+  }
+
+  /// Parses any `[index]` and `.field` following `expr`.
+  fn parse_accesses(&mut self, mut expr: Expr) -> Result<Expr, ParserError> {
+    loop {
+      let start = expr.span;
+      let kind = match self.curr() {
+        Token::Symbol {
+          val: Symbol::LeftBracket,
+          ..
+        } => {
+          self.next();
+          let index = self.parse_expr()?;
+          self.expect_symbol(Symbol::RightBracket)?;
+          ExprKind::Index(Box::new(expr), Box::new(index))
+        },
+        Token::Symbol {
+          val: Symbol::Dot, ..
+        } => {
+          self.next();
+          let field = self.expect_identifier()?;
+          ExprKind::Field(Box::new(expr), field)
+        },
+        _ => return Ok(expr),
+      };
+      let span = start.to(self.tokens.get(self.pos - 1).unwrap().span());
+      expr = Expr::new(kind, span);
+    }
+  }
+
+  /// Parses an operand and any indexes or fields following it.
+  fn parse_postfix(&mut self) -> Result<Expr, ParserError> {
+    let expr = self.parse_primary()?;
+    self.parse_accesses(expr)
+  }
+
+  fn parse_primary(&mut self) -> Result<Expr, ParserError> {
     loop {
       match self.curr() {
         Token::Newline { .. } => {
@@ -190,24 +650,130 @@ impl Parser {
           continue;
         },
         Token::Literal { .. } => {
+          let span = self.curr().span();
           let literal = self.expect_literal()?;
-          return Ok(Expr::Literal(literal));
+          return Ok(Expr::new(ExprKind::Literal(literal), span));
         },
         Token::Identifier { .. } => {
           let ident = self.expect_identifier()?;
+          let span = self.tokens.get(self.pos - 1).unwrap().span();
 
-          if let None = self.program.find_entry(&ident, false) {
-            return Err(ParserError::new(
-              self.tokens.get(self.pos - 1).unwrap().start(),
-              &format!("Variable '{}' not declared", ident),
-            ));
+          if let Token::Symbol {
+            val: Symbol::LeftParen,
+            ..
+          } = self.curr()
+          {
+            return self.parse_call(ident, span);
+          }
+          if let Token::Symbol {
+            val: Symbol::ColonColon,
+            ..
+          } = self.curr()
+          {
+            return self.parse_variant(&ident, span);
+          }
+          if let (
+            Token::Symbol {
+              val: Symbol::LeftBrace,
+              ..
+            },
+            Some(def),
+          ) = (self.curr(), self.program.find_struct(&ident))
+          {
+            let def = def.clone();
+            return self.parse_struct_literal(def, span);
           }
 
-          return Ok(Expr::Identifier(ident));
+          return match self.program.resolve(&ident, false) {
+            Some(var) => Ok(Expr::new(ExprKind::Identifier(var), span)),
+            None => {
+              self.errors.push(ParserError::new(
+                span,
+                &format!("Variable '{}' not declared", ident),
+              ));
+              // stand in for the variable so the rest of the statement is
+              // still checked
+              Ok(Expr::new(ExprKind::Literal(Literal::Integer(0)), span))
+            },
+          };
         },
+        Token::Symbol { val, .. } => {
+          let op = match val {
+            Symbol::LeftParen => {
+              let start = self.curr().span();
+              self.next();
+              let mut expr = self.parse_expr()?;
+              self.expect_symbol(Symbol::RightParen)?;
+              expr.span = start.to(self.tokens.get(self.pos - 1).unwrap().span());
+              return Ok(expr);
+            },
+            Symbol::LeftBracket => return self.parse_array(),
+            Symbol::Amp | Symbol::Star => {
+              let start = self.curr().span();
+              let is_ref = *val == Symbol::Amp;
+              self.next();
+              let expr = Box::new(self.parse_postfix()?);
+              let span = start.to(expr.span);
+              let kind = if is_ref {
+                ExprKind::Ref(expr)
+              } else {
+                ExprKind::Deref(expr)
+              };
+              return Ok(Expr::new(kind, span));
+            },
+            Symbol::Minus => UnaryOp::Neg,
+            Symbol::Bang => UnaryOp::Not,
+            _ => {
+              return Err(ParserError::new(
+                self.curr().span(),
+                &format!("Unexpected symbol '{}', expected expression", val),
+              ))
+            },
+          };
+
+          let start = self.curr().span();
+          self.next();
+
+          // fold negative literals so the smallest i64 can be written
+          if let (
+            UnaryOp::Neg,
+            Token::Literal {
+              val: val @ (Literal::Integer(_) | Literal::Unsigned(_)),
+              ..
+            },
+          ) = (op, self.curr())
+          {
+            let span = start.to(self.curr().span());
+            let lit = match *val {
+              Literal::Integer(i) => Some(-i),
+              Literal::Unsigned(u) if u == i64::MIN.unsigned_abs() => Some(i64::MIN),
+              _ => None,
+            };
+            self.next();
+
+            return match lit {
+              Some(i) => Ok(Expr::new(ExprKind::Literal(Literal::Integer(i)), span)),
+              None => Err(
+                ParserError::new(span, "Integer literal out of range")
+                  .with_note(&format!("the smallest integer is {}", i64::MIN)),
+              ),
+            };
+          }
+
+          let expr = self.parse_postfix()?;
+          let span = start.to(expr.span);
+          return Ok(Expr::new(ExprKind::Unary(op, Box::new(expr)), span));
+        },
+        Token::Keyword {
+          val: Keyword::If, ..
+        } => return self.parse_conditional(),
+        Token::Keyword {
+          val: Keyword::Match,
+          ..
+        } => return self.parse_match(),
         t => {
           return Err(ParserError::new(
-            t.start(),
+            t.span(),
             &format!(
               "Unexpected {}, expected expression",
               self.curr().type_to_string()
@@ -218,7 +784,597 @@ impl Parser {
     }
   }
 
+  /// Parses `if cond { then } else { other }` used as a value, an `else if`
+  /// nests another one in the `else` branch.
+  fn parse_conditional(&mut self) -> Result<Expr, ParserError> {
+    let start = self.curr().span();
+    self.expect_keyword(Keyword::If)?;
+
+    let cond = self.parse_expr()?;
+    let then = self.parse_branch()?;
+
+    if !self.skip_to_else() {
+      let span = start.to(self.tokens.get(self.pos - 1).unwrap().span());
+      return Err(
+        ParserError::new(span, "Conditional expression without 'else'")
+          .with_help("an 'if' used as a value needs an 'else' branch"),
+      );
+    }
+    self.expect_keyword(Keyword::Else)?;
+
+    let other = match self.curr() {
+      Token::Keyword {
+        val: Keyword::If, ..
+      } => self.parse_conditional()?,
+      _ => self.parse_branch()?,
+    };
+
+    let span = start.to(self.tokens.get(self.pos - 1).unwrap().span());
+    Ok(Expr::new(
+      ExprKind::If(Box::new(cond), Box::new(then), Box::new(other)),
+      span,
+    ))
+  }
+
+  /// Parses `{ expr }`, a branch of a conditional expression.
+  fn parse_branch(&mut self) -> Result<Expr, ParserError> {
+    self.expect_symbol(Symbol::LeftBrace)?;
+    let expr = self.parse_expr()?;
+    self.expect_symbol(Symbol::RightBrace)?;
+    Ok(expr)
+  }
+
+  /// Parses an operand and any `as type` conversions of it, which bind tighter
+  /// than binary operators but looser than unary ones.
+  fn parse_cast(&mut self) -> Result<Expr, ParserError> {
+    let mut expr = self.parse_postfix()?;
+    while let Token::Keyword {
+      val: Keyword::As, ..
+    } = self.curr()
+    {
+      self.next();
+      let ty = self.parse_type()?;
+      let span = expr.span.to(self.tokens.get(self.pos - 1).unwrap().span());
+      expr = Expr::new(ExprKind::Cast(Box::new(expr), ty), span);
+    }
+    Ok(expr)
+  }
+
+  /// Precedence climbing: parses operands and any binary operators binding at
+  /// least as tightly as `min_prec`.
+  fn parse_binary(&mut self, min_prec: u8) -> Result<Expr, ParserError> {
+    let mut lhs = self.parse_cast()?;
+
+    while let Token::Symbol { val, .. } = self.curr() {
+      if *val == Symbol::Amp {
+        return Err(
+          ParserError::new(self.curr().span(), "Unexpected symbol '&' after an operand")
+            .with_help("use '&&' for logical and"),
+        );
+      }
+      let op = match BinaryOp::from_symbol(val) {
+        Some(op) if op.precedence() >= min_prec => op,
+        _ => break,
+      };
+
+      self.next();
+
+      let rhs = self.parse_binary(op.precedence() + 1)?;
+      let span = lhs.span.to(rhs.span);
+      lhs = Expr::new(ExprKind::Binary(op, Box::new(lhs), Box::new(rhs)), span);
+    }
+
+    Ok(lhs)
+  }
+
+  fn parse_expr(&mut self) -> Result<Expr, ParserError> {
+    self.parse_binary(1)
+  }
+
+  fn parse_block(&mut self) -> Result<Block, ParserError> {
+    self.expect_symbol(Symbol::LeftBrace)?;
+
+    let table = self.program.push_scope(ScopeType::Block);
+
+    self.parse_block_body(table)
+  }
+
+  /// Parses statements up to the closing `}` into the already opened scope
+  /// `table`, then closes it.
+  fn parse_block_body(&mut self, table: usize) -> Result<Block, ParserError> {
+    let mut stmts = Vec::new();
+
+    loop {
+      self.skip_newlines();
+
+      match self.curr() {
+        Token::Symbol {
+          val: Symbol::RightBrace,
+          ..
+        } => {
+          self.next();
+          break;
+        },
+        t @ Token::EOF { .. } => {
+          let err = ParserError::new(t.span(), "Unexpected EOF, expected symbol '}'");
+          self.program.pop_scope();
+          return Err(err);
+        },
+        _ => match self.parse_stmt() {
+          Ok(Some(stmt)) => stmts.push(stmt),
+          Ok(None) => (),
+          Err(err) => {
+            self.errors.push(err);
+            self.synchronize();
+          },
+        },
+      }
+    }
+
+    self.program.pop_scope();
+
+    Ok(Block { stmts, table })
+  }
+
+  /// Skips the rest of a statement that failed to parse: everything up to the
+  /// next newline outside of braces, or up to the `}` closing the enclosing
+  /// block, which is left for the block to consume.
+  fn synchronize(&mut self) {
+    let mut depth = 0;
+
+    loop {
+      match self.curr() {
+        Token::EOF { .. } => return,
+        Token::Newline { .. } if depth == 0 => {
+          self.next();
+          return;
+        },
+        Token::Symbol {
+          val: Symbol::LeftBrace,
+          ..
+        } => depth += 1,
+        Token::Symbol {
+          val: Symbol::RightBrace,
+          ..
+        } => {
+          if depth == 0 {
+            return;
+          }
+          depth -= 1;
+        },
+        _ => (),
+      }
+      self.next();
+    }
+  }
+
+  /// Moves to an `else` keyword if it is the next token after any newlines,
+  /// leaving the position untouched otherwise.
+  fn skip_to_else(&mut self) -> bool {
+    let mut n = 0;
+    while matches!(self.peek_n(n), Token::Newline { .. }) {
+      n += 1;
+    }
+
+    if matches!(self.peek_n(n), Token::Keyword {
+      val: Keyword::Else,
+      ..
+    }) {
+      self.next_n(n);
+      true
+    } else {
+      false
+    }
+  }
+
+  fn parse_fn(&mut self) -> Result<Function, ParserError> {
+    self.expect_keyword(Keyword::Fn)?;
+
+    let name = self.expect_identifier()?;
+
+    if Builtin::from_name(&name).is_some() {
+      self.errors.push(ParserError::new(
+        self.tokens.get(self.pos - 1).unwrap().span(),
+        &format!("Function '{}' is a builtin", name),
+      ));
+    } else if self.program.find_function(&name).is_some() {
+      self.errors.push(ParserError::new(
+        self.tokens.get(self.pos - 1).unwrap().span(),
+        &format!("Function '{}' already declared", name),
+      ));
+    }
+
+    self.expect_symbol(Symbol::LeftParen)?;
+
+    let mut params: Vec<(String, Type)> = Vec::new();
+    self.skip_newlines();
+    if let Token::Identifier { .. } = self.curr() {
+      loop {
+        let param = self.expect_identifier()?;
+        let span = self.tokens.get(self.pos - 1).unwrap().span();
+        let ty = self.parse_annotation()?.unwrap_or(Type::I64);
+        if !ty.is_scalar() {
+          let span = span.to(self.tokens.get(self.pos - 1).unwrap().span());
+          self.errors.push(ParserError::new(
+            span,
+            &format!("Parameter '{}' can't be of type {}", param, ty),
+          ));
+        }
+
+        if params.iter().any(|(name, _)| *name == param) {
+          self.errors.push(ParserError::new(
+            span,
+            &format!("Parameter '{}' already declared", param),
+          ));
+        } else {
+          params.push((param, ty));
+        }
+        self.skip_newlines();
+        if let Token::Symbol {
+          val: Symbol::Comma, ..
+        } = self.curr()
+        {
+          self.next();
+          continue;
+        }
+        break;
+      }
+    }
+
+    self.expect_symbol(Symbol::RightParen)?;
+
+    let ret = match self.curr() {
+      Token::Symbol {
+        val: Symbol::Arrow, ..
+      } => {
+        self.next();
+        let start = self.curr().span();
+        let ty = self.parse_type()?;
+        if !ty.is_scalar() {
+          let span = start.to(self.tokens.get(self.pos - 1).unwrap().span());
+          self.errors.push(ParserError::new(
+            span,
+            &format!("Functions can't return {}", ty),
+          ));
+        }
+        ty
+      },
+      _ => Type::I64,
+    };
+
+    self.expect_symbol(Symbol::LeftBrace)?;
+
+    let table = self.program.push_scope(ScopeType::Function);
+    for (i, (param, ty)) in params.iter().enumerate() {
+      self.program.push_param(param.clone(), ty.clone(), i);
+    }
+
+    let body = self.parse_block_body(table)?;
+
+    self.expect_stmt_end()?;
+
+    Ok(Function {
+      name,
+      params,
+      ret,
+      body,
+    })
+  }
+
+  /// Parses `struct Name { field: type, ... }` and declares the struct, with
+  /// the fields parsed before any error so its uses don't report more.
+  fn parse_struct(&mut self) -> Result<(), ParserError> {
+    self.expect_keyword(Keyword::Struct)?;
+
+    let name = self.expect_identifier()?;
+    let span = self.tokens.get(self.pos - 1).unwrap().span();
+    self.check_type_name(&name, span);
+
+    // declared before its fields so they can point to it
+    let def = Rc::new(Struct::declare(name));
+    if self.program.find_struct(&def.name).is_none() {
+      self.program.structs.push(def.clone());
+    }
+
+    let mut fields = Vec::new();
+    let res = self.parse_fields(&def, &mut fields);
+    def.define(fields);
+    // the frame is addressed with 32-bit displacements
+    if def.size() > i32::MAX as usize {
+      self.errors.push(
+        ParserError::new(span, &format!("Struct '{}' is too large", def.name))
+          .with_note(&format!("structs take at most {} bytes", i32::MAX)),
+      );
+    }
+
+    res?;
+    self.expect_stmt_end()
+  }
+
+  /// Parses the `{ field: type, ... }` of the struct `def` into `fields`.
+  fn parse_fields(
+    &mut self,
+    def: &Struct,
+    fields: &mut Vec<(String, Type)>,
+  ) -> Result<(), ParserError> {
+    self.expect_symbol(Symbol::LeftBrace)?;
+
+    loop {
+      self.skip_newlines();
+      if let Token::Symbol {
+        val: Symbol::RightBrace,
+        ..
+      } = self.curr()
+      {
+        break;
+      }
+      let field = self.expect_identifier()?;
+      let field_span = self.tokens.get(self.pos - 1).unwrap().span();
+      self.expect_symbol(Symbol::Colon)?;
+      let start = self.curr().span();
+      let ty = self.parse_type()?;
+
+      if ty.holds(def) {
+        let span = start.to(self.tokens.get(self.pos - 1).unwrap().span());
+        self.errors.push(
+          ParserError::new(span, &format!("Struct '{}' contains itself", def.name))
+            .with_help(&format!("point to it instead, *{}", def.name)),
+        );
+      } else if fields.iter().any(|(name, _)| *name == field) {
+        self.errors.push(ParserError::new(
+          field_span,
+          &format!("Field '{}' already declared", field),
+        ));
+      } else {
+        fields.push((field, ty));
+      }
+      self.skip_newlines();
+      match self.curr() {
+        Token::Symbol {
+          val: Symbol::Comma, ..
+        } => self.next(),
+        _ => break,
+      }
+    }
+
+    self.expect_symbol(Symbol::RightBrace)?;
+    Ok(())
+  }
+
+  /// Reports a declared type named like a builtin or an earlier struct or enum.
+  fn check_type_name(&mut self, name: &str, span: Span) {
+    if Type::from_name(name).is_some()
+      || self.program.find_struct(name).is_some()
+      || self.program.find_enum(name).is_some()
+    {
+      self.errors.push(ParserError::new(
+        span,
+        &format!("Type '{}' already declared", name),
+      ));
+    }
+  }
+
+  /// Parses `enum Name { Variant(type, ...), ... }`, lays the enum out and
+  /// declares it, with the variants parsed before any error so its uses don't
+  /// report more.
+  fn parse_enum(&mut self) -> Result<(), ParserError> {
+    self.expect_keyword(Keyword::Enum)?;
+
+    let name = self.expect_identifier()?;
+    let span = self.tokens.get(self.pos - 1).unwrap().span();
+    self.check_type_name(&name, span);
+
+    let mut variants = Vec::new();
+    let res = self.parse_variants(&mut variants);
+
+    let max = 1 << (8 * Enum::TAG.size());
+    if variants.is_empty() && res.is_ok() {
+      self.errors.push(
+        ParserError::new(span, &format!("Enum '{}' has no variants", name))
+          .with_note("a value of the enum could never be made"),
+      );
+    } else if variants.len() > max {
+      self.errors.push(
+        ParserError::new(span, &format!("Enum '{}' has too many variants", name))
+          .with_note(&format!("enums have at most {} variants", max)),
+      );
+    }
+
+    // the frame is addressed with 32-bit displacements
+    let def = Enum::new(name, variants);
+    if def.size > i32::MAX as usize {
+      self.errors.push(
+        ParserError::new(span, &format!("Enum '{}' is too large", def.name))
+          .with_note(&format!("enums take at most {} bytes", i32::MAX)),
+      );
+    }
+    if self.program.find_enum(&def.name).is_none() {
+      self.program.enums.push(Rc::new(def));
+    }
+
+    res?;
+    self.expect_stmt_end()
+  }
+
+  /// Parses the `{ Variant(type, ...), ... }` of an enum into `variants`.
+  fn parse_variants(&mut self, variants: &mut Vec<(String, Vec<Type>)>) -> Result<(), ParserError> {
+    self.expect_symbol(Symbol::LeftBrace)?;
+
+    loop {
+      self.skip_newlines();
+      if let Token::Symbol {
+        val: Symbol::RightBrace,
+        ..
+      } = self.curr()
+      {
+        break;
+      }
+      let variant = self.expect_identifier()?;
+      let variant_span = self.tokens.get(self.pos - 1).unwrap().span();
+
+      let mut fields = Vec::new();
+      if let Token::Symbol {
+        val: Symbol::LeftParen,
+        ..
+      } = self.curr()
+      {
+        self.next();
+        loop {
+          self.skip_newlines();
+          if let Token::Symbol {
+            val: Symbol::RightParen,
+            ..
+          } = self.curr()
+          {
+            break;
+          }
+          fields.push(self.parse_type()?);
+          self.skip_newlines();
+          match self.curr() {
+            Token::Symbol {
+              val: Symbol::Comma, ..
+            } => self.next(),
+            _ => break,
+          }
+        }
+        self.expect_symbol(Symbol::RightParen)?;
+      }
+
+      if variants.iter().any(|(name, _)| *name == variant) {
+        self.errors.push(ParserError::new(
+          variant_span,
+          &format!("Variant '{}' already declared", variant),
+        ));
+      } else {
+        variants.push((variant, fields));
+      }
+      self.skip_newlines();
+      match self.curr() {
+        Token::Symbol {
+          val: Symbol::Comma, ..
+        } => self.next(),
+        _ => break,
+      }
+    }
+
+    self.expect_symbol(Symbol::RightBrace)?;
+    Ok(())
+  }
+
+  fn expect_stmt_end(&mut self) -> Result<(), ParserError> {
+    match self.curr() {
+      Token::Newline { .. } => {
+        self.next();
+        self.skip_newlines();
+      },
+      // the end of the file or of the enclosing block also ends a statement
+      Token::EOF { .. }
+      | Token::Symbol {
+        val: Symbol::RightBrace,
+        ..
+      } => (),
+      _ => {
+        return Err(ParserError::new(
+          self.curr().span(),
+          &format!(
+            "Unexpected {}, expected newline or EOF after statement",
+            self.curr().type_to_string()
+          ),
+        ));
+      },
+    }
+
+    Ok(())
+  }
+
   fn parse_stmt(&mut self) -> Result<Option<Stmt>, ParserError> {
+    fn _parse_return(parser: &mut Parser) -> Result<Stmt, ParserError> {
+      let span = parser.curr().span();
+
+      parser.expect_keyword(Keyword::Return)?;
+
+      let root = parser.program.frame_root(parser.program.curr_table);
+      if parser.program.symbol_tables[root].ty != ScopeType::Function {
+        parser.errors.push(
+          ParserError::new(span, "'return' outside of a function")
+            .with_help("use 'exit(...)' to end the program"),
+        );
+      }
+
+      match parser.curr() {
+        Token::Newline { .. }
+        | Token::EOF { .. }
+        | Token::Symbol {
+          val: Symbol::RightBrace,
+          ..
+        } => Ok(Stmt::Return(None)),
+        _ => Ok(Stmt::Return(Some(parser.parse_expr()?))),
+      }
+    }
+
+    fn _parse_if(parser: &mut Parser) -> Result<Stmt, ParserError> {
+      parser.expect_keyword(Keyword::If)?;
+
+      let mut branches = Vec::new();
+      let mut else_block = None;
+
+      loop {
+        let cond = parser.parse_expr()?;
+        let block = parser.parse_block()?;
+        branches.push((cond, block));
+
+        if !parser.skip_to_else() {
+          break;
+        }
+
+        parser.expect_keyword(Keyword::Else)?;
+
+        if matches!(parser.curr(), Token::Keyword {
+          val: Keyword::If,
+          ..
+        }) {
+          parser.next();
+          continue;
+        }
+
+        else_block = Some(parser.parse_block()?);
+        break;
+      }
+
+      Ok(Stmt::If(branches, else_block))
+    }
+
+    fn _parse_while(parser: &mut Parser) -> Result<Stmt, ParserError> {
+      parser.expect_keyword(Keyword::While)?;
+
+      let cond = parser.parse_expr()?;
+      let block = parser.parse_block()?;
+
+      Ok(Stmt::While(cond, block))
+    }
+
+    fn _parse_match(parser: &mut Parser) -> Result<Stmt, ParserError> {
+      parser.expect_keyword(Keyword::Match)?;
+
+      let value = parser.parse_expr()?;
+      let arms = parser.parse_arms(|parser, table| {
+        parser.expect_symbol(Symbol::LeftBrace)?;
+        parser.parse_block_body(table)
+      })?;
+
+      Ok(Stmt::Match(value, arms))
+    }
+
+    fn _parse_place_assign(parser: &mut Parser) -> Result<Stmt, ParserError> {
+      let place = parser.parse_expr()?;
+
+      parser.expect_symbol(Symbol::Equals)?;
+
+      let expr = parser.parse_expr()?;
+
+      Ok(Stmt::Assign(place, expr))
+    }
+
     fn _parse_exit(parser: &mut Parser) -> Result<Stmt, ParserError> {
       parser.expect_keyword(Keyword::Exit)?;
 
@@ -231,38 +1387,82 @@ impl Parser {
       Ok(Stmt::Exit(expr))
     }
 
-    fn _parse_var_decl(parser: &mut Parser, ident: &str) -> Result<Stmt, ParserError> {
+    fn _parse_var_decl(parser: &mut Parser, ident: &str, span: Span) -> Result<Stmt, ParserError> {
+      let ty = parser.parse_annotation()?;
+
       parser.expect_symbol(Symbol::ColonEquals)?;
 
-      let expr = parser.parse_expr()?;
+      let expr = parser.parse_expr();
 
-      if let Some(_) = parser.program.find_entry(ident, true) {
-        return Err(ParserError::new(
-          parser.tokens.get(parser.pos - 1).unwrap().start(),
-          &format!("Variable '{}' already declared in this scope", ident),
-        ));
+      if parser.program.find_entry(ident, true).is_some() {
+        parser.errors.push(
+          ParserError::new(
+            span,
+            &format!("Variable '{}' already declared in this scope", ident),
+          )
+          .with_help(&format!("use '{} = ...' to assign to it", ident)),
+        );
+      } else {
+        // declare the variable even if its value is broken so later uses of it
+        // don't report it as undeclared, the type checker infers the type of
+        // unannotated ones
+        parser
+          .program
+          .push_entry(ident.to_string(), ty.clone().unwrap_or(Type::I64));
       }
 
-      parser
-        .program
-        .push_entry(ident.to_string(), parser.type_of(&expr));
+      let expr = expr?;
+      let var = parser.program.resolve(ident, true).unwrap();
 
-      Ok(Stmt::VarDecl(ident.to_string(), expr))
+      Ok(Stmt::VarDecl(var, ty, expr))
     }
 
-    fn _parse_var_assign(parser: &mut Parser, ident: &str) -> Result<Stmt, ParserError> {
+    fn _parse_var_assign(
+      parser: &mut Parser,
+      ident: &str,
+      span: Span,
+    ) -> Result<Stmt, ParserError> {
       parser.expect_symbol(Symbol::Equals)?;
 
       let expr = parser.parse_expr()?;
 
-      if let None = parser.program.find_entry(ident, false) {
-        return Err(ParserError::new(
-          parser.tokens.get(parser.pos - 1).unwrap().start(),
+      match parser.program.resolve(ident, false) {
+        Some(var) => Ok(Stmt::VarAssign(var, expr)),
+        None => {
+          parser.errors.push(
+            ParserError::new(span, &format!("Variable '{}' not declared", ident))
+              .with_help(&format!("use '{} := ...' to declare it", ident)),
+          );
+          Ok(Stmt::Expr(expr))
+        },
+      }
+    }
+
+    fn _parse_assign(parser: &mut Parser, ident: &str, span: Span) -> Result<Stmt, ParserError> {
+      let var = parser.program.resolve(ident, false);
+      let declared = var.is_some();
+      if !declared {
+        parser.errors.push(ParserError::new(
+          span,
           &format!("Variable '{}' not declared", ident),
         ));
       }
+      // stand in for the variable so the rest of the statement is still parsed
+      let target = match var {
+        Some(var) => Expr::new(ExprKind::Identifier(var), span),
+        None => Expr::new(ExprKind::Literal(Literal::Integer(0)), span),
+      };
+      let place = parser.parse_accesses(target)?;
+
+      parser.expect_symbol(Symbol::Equals)?;
 
-      Ok(Stmt::VarAssign(ident.to_string(), expr))
+      let expr = parser.parse_expr()?;
+
+      if declared {
+        Ok(Stmt::Assign(place, expr))
+      } else {
+        Ok(Stmt::Expr(expr))
+      }
     }
 
     let stmt;
@@ -280,28 +1480,89 @@ impl Parser {
 
             break;
           },
+          Keyword::If => {
+            stmt = _parse_if(self)?;
+
+            break;
+          },
+          Keyword::While => {
+            stmt = _parse_while(self)?;
+
+            break;
+          },
+          Keyword::Return => {
+            stmt = _parse_return(self)?;
+
+            break;
+          },
+          Keyword::Match => {
+            stmt = _parse_match(self)?;
+
+            break;
+          },
+          Keyword::Else => {
+            return Err(ParserError::new(
+              self.curr().span(),
+              "Unexpected 'else' without a preceding 'if'",
+            ))
+          },
+          Keyword::Fn => {
+            return Err(ParserError::new(
+              self.curr().span(),
+              "Functions can only be declared at the top level",
+            ))
+          },
+          Keyword::Struct => {
+            return Err(ParserError::new(
+              self.curr().span(),
+              "Structs can only be declared at the top level",
+            ))
+          },
+          Keyword::Enum => {
+            return Err(ParserError::new(
+              self.curr().span(),
+              "Enums can only be declared at the top level",
+            ))
+          },
+          Keyword::As => {
+            return Err(ParserError::new(
+              self.curr().span(),
+              "Unexpected 'as' without a value to convert",
+            ))
+          },
         },
-        Token::Identifier { val, .. } => {
+        // `*p = v` or `(*p).x = v`
+        Token::Symbol {
+          val: Symbol::Star | Symbol::LeftParen,
+          ..
+        } => {
+          stmt = _parse_place_assign(self)?;
+
+          break;
+        },
+        t @ Token::Identifier { val, .. } => {
           let ident = val.clone();
+          let span = t.span();
 
           self.next();
           self.skip_newlines();
 
           stmt = match self.curr() {
             Token::Symbol { val, .. } => match val {
-              Symbol::ColonEquals => _parse_var_decl(self, &ident)?,
-              Symbol::Equals => _parse_var_assign(self, &ident)?,
-              Symbol::LeftParen => todo!("function calls not implemented"),
+              Symbol::Colon | Symbol::ColonEquals => _parse_var_decl(self, &ident, span)?,
+              Symbol::Equals => _parse_var_assign(self, &ident, span)?,
+              Symbol::LeftBracket | Symbol::Dot => _parse_assign(self, &ident, span)?,
+              Symbol::LeftParen => Stmt::Expr(self.parse_call(ident, span)?),
               _ => {
                 return Err(ParserError::new(
-                  self.curr().start(),
+                  self.curr().span(),
                   &format!("Unexpected {}", self.curr().type_to_string()),
                 ))
               },
             },
             t => {
               return Err(ParserError::new(
-                t.start(),
+                t.span(),
                 &format!(
                   "Unexpected {}, expected symbol",
                   self.curr().type_to_string()
@@ -314,7 +1575,7 @@ impl Parser {
         },
         t => {
           return Err(ParserError::new(
-            t.start(),
+            t.span(),
             &format!(
               "Unexpected {}, expected statement",
               self.curr().type_to_string()
@@ -324,31 +1585,110 @@ impl Parser {
       }
     }
 
-    if let Token::Newline { .. } = self.curr() {
-      self.next();
-      self.skip_newlines();
-    } else {
-      return Err(ParserError::new(
-        self.curr().start(),
-        &format!(
-          "Unexpected {}, expected newline or EOF after statement",
-          self.curr().type_to_string()
-        ),
-      ));
-    }
+    self.expect_stmt_end()?;
 
     Ok(Some(stmt))
   }
 
-  pub fn parse(&mut self) -> Result<Program, ParserError> {
-    while !matches!(self.curr(), Token::EOF { .. }) {
-      let stmt = self.parse_stmt()?;
+  /// Parses the whole program, recovering from errors at statement boundaries
+  /// so every error in the file is reported at once.
+  pub fn parse(&mut self) -> Result<Program, Vec<ParserError>> {
+    loop {
+      self.skip_newlines();
+
+      let res = match self.curr() {
+        Token::EOF { .. } => break,
+        Token::Keyword {
+          val: Keyword::Fn, ..
+        } => self.parse_fn().map(|function| {
+          self.program.functions.push(function);
+        }),
+        Token::Keyword {
+          val: Keyword::Struct,
+          ..
+        } => self.parse_struct(),
+        Token::Keyword {
+          val: Keyword::Enum, ..
+        } => self.parse_enum(),
+        _ => self.parse_stmt().map(|stmt| {
+          if let Some(stmt) = stmt {
+            self.program.push_stmt(stmt);
+          }
+        }),
+      };
+
+      if let Err(err) = res {
+        self.errors.push(err);
+        self.synchronize();
 
-      if stmt.is_some() {
-        self.program.push_stmt(stmt.unwrap());
+        // a stray '}' has no block to close at the top level
+        if let Token::Symbol {
+          val: Symbol::RightBrace,
+          ..
+        } = self.curr()
+        {
+          self.next();
+        }
       }
     }
 
+    for (name, argc, span) in self.calls.iter() {
+      match self.program.find_function(name) {
+        None => {
+          self.errors.push(ParserError::new(
+            *span,
+            &format!("Function '{}' not declared", name),
+          ));
+        },
+        Some(function) if function.params.len() != *argc => {
+          self.errors.push(ParserError::new(
+            *span,
+            &format!(
+              "Function '{}' takes {} arguments but {} were given",
+              name,
+              function.params.len(),
+              argc
+            ),
+          ));
+        },
+        _ => (),
+      }
+    }
+
+    if !self.errors.is_empty() {
+      self.errors.sort_by_key(|err| err.span().start);
+      return Err(std::mem::take(&mut self.errors));
+    }
+
     Ok(self.program.clone())
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn block_variables_are_not_visible_after_the_block() {
+    let tokens = Lexer::new("if 1 {\n  y := 2\n}\nexit(y)\n").lex().unwrap();
+    let errors = Parser::new(tokens).parse().unwrap_err();
+
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].msg(), "Variable 'y' not declared");
+  }
+
+  #[test]
+  fn every_syntax_error_is_reported() {
+    let source = "x := )\ny := 2\nexit(3 3)\nz := )\nexit(y)\n";
+    let map = SourceMap::new("test.pn", source);
+    let errors = Parser::new(Lexer::new(source).lex().unwrap())
+      .parse()
+      .unwrap_err();
+    let locations = errors
+      .iter()
+      .map(|err| map.location(err.span().start))
+      .collect::<Vec<_>>();
+
+    assert_eq!(locations, [(1, 6), (3, 8), (4, 6)]);
+  }
+}
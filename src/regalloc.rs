@@ -0,0 +1,462 @@
+use std::{collections::HashSet, fmt};
+
+use super::*;
+
+/// Registers calls preserve, the only ones that can hold a temp across a call.
+/// Functions save the ones they use.
+pub const CALLEE_SAVED: [&str; 5] = ["rbx", "r12", "r13", "r14", "r15"];
+
+/// Registers calls may overwrite. `rax`, `rcx` and `rdx` are left as scratch
+/// registers for the generated code, and the argument registers are left alone
+/// so arguments can be moved into them in any order.
+pub const CALLER_SAVED: [&str; 2] = ["r10", "r11"];
+
+/// Where a temp lives.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Loc {
+  Reg(&'static str),
+  /// 8-byte slot at the offset below `rbp`.
+  Slot(isize),
+}
+
+impl fmt::Display for Loc {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Loc::Reg(reg) => write!(f, "{}", reg),
+      Loc::Slot(offset) => write!(f, "qword {}", slot(*offset)),
+    }
+  }
+}
+
+/// Locations of the temps of a function.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Allocation {
+  pub locs:  Vec<Loc>,
+  /// Callee saved registers used, which a function restores before returning.
+  pub saved: Vec<&'static str>,
+  /// Bytes of the frame used by the locals and spilled temps.
+  pub frame: usize,
+}
+
+impl Allocation {
+  /// Puts every temp in its own slot below the locals.
+  pub fn spill_all(function: &IrFunction) -> Self {
+    Self {
+      locs:  (0..function.temps)
+        .map(|i| Loc::Slot((function.frame + 8 * (i + 1)) as isize))
+        .collect(),
+      saved: Vec::new(),
+      frame: function.frame + 8 * function.temps,
+    }
+  }
+
+  /// Offset of the slot the saved register at `index` of `saved` is kept in.
+  pub fn save_slot(&self, index: usize) -> isize {
+    (self.frame + 8 * (index + 1)) as isize
+  }
+
+  /// Bytes reserved below `rbp`, with room to save the callee saved registers
+  /// if `save`, rounded up so `rsp` stays 16-byte aligned.
+  pub fn frame_size(&self, save: bool) -> usize {
+    let saved = if save { self.saved.len() } else { 0 };
+    (self.frame + 8 * saved).next_multiple_of(16)
+  }
+}
+
+/// Turns every local whose address isn't taken into a temp, so variables can
+/// be kept in registers: loads and stores become copies. Copies of a local that
+/// are only read in the same block, before either side changes, are removed and
+/// their uses read the local's temp directly, and values computed only to be
+/// stored are computed into the local's temp.
+pub fn promote_locals(function: &mut IrFunction) {
+  let vars = (0..function.locals.len())
+    .map(|i| Temp(function.temps + i))
+    .collect::<Vec<_>>();
+  function.temps += vars.len();
+
+  let addressed = function
+    .blocks
+    .iter()
+    .flat_map(|block| block.insts.iter())
+    .filter_map(|inst| match inst {
+      Inst::Addr(_, local) => Some(*local),
+      _ => None,
+    })
+    .collect::<HashSet<_>>();
+
+  for block in function.blocks.iter_mut() {
+    for inst in block.insts.iter_mut() {
+      match inst {
+        Inst::Load(dst, local) if !addressed.contains(local) => {
+          *inst = Inst::Copy(*dst, Value::Temp(vars[*local]))
+        },
+        Inst::Store(local, src) if !addressed.contains(local) => {
+          *inst = Inst::Copy(vars[*local], *src)
+        },
+        _ => (),
+      }
+    }
+  }
+
+  for block in function.blocks.iter_mut() {
+    // copies still valid at this point of the block, by destination
+    let mut copies: Vec<(Temp, Temp)> = Vec::new();
+    for inst in block.insts.iter_mut() {
+      for val in inst.values_mut() {
+        if let Value::Temp(temp) = val {
+          if let Some((_, src)) = copies.iter().find(|(dst, _)| dst == temp) {
+            *temp = *src;
+          }
+        }
+      }
+      if let Some(dst) = inst.dst() {
+        copies.retain(|(d, s)| *d != dst && *s != dst);
+        if let Inst::Copy(dst, Value::Temp(src)) = inst {
+          if vars.contains(src) && !vars.contains(dst) {
+            copies.push((*dst, *src));
+          }
+        }
+      }
+    }
+    if let Some(Value::Temp(temp)) = block.term.value_mut() {
+      if let Some((_, src)) = copies.iter().find(|(dst, _)| dst == temp) {
+        *temp = *src;
+      }
+    }
+  }
+
+  // drop the copies nothing reads anymore
+  let mut used = HashSet::new();
+  for block in function.blocks.iter() {
+    for inst in block.insts.iter() {
+      used.extend(inst.uses());
+    }
+    used.extend(block.term.uses());
+  }
+  for block in function.blocks.iter_mut() {
+    block.insts.retain(|inst| match inst {
+      Inst::Copy(dst, Value::Temp(src)) => (used.contains(dst) || vars.contains(dst)) && dst != src,
+      _ => true,
+    });
+  }
+
+  // write values straight into the variable they're only computed for
+  let mut uses = vec![0; function.temps];
+  let mut defs = vec![0; function.temps];
+  for block in function.blocks.iter() {
+    for inst in block.insts.iter() {
+      for temp in inst.uses() {
+        uses[temp.0] += 1;
+      }
+      if let Some(dst) = inst.dst() {
+        defs[dst.0] += 1;
+      }
+    }
+    for temp in block.term.uses() {
+      uses[temp.0] += 1;
+    }
+  }
+  for block in function.blocks.iter_mut() {
+    let mut i = 1;
+    while i < block.insts.len() {
+      match block.insts[i] {
+        Inst::Copy(var, Value::Temp(src))
+          if vars.contains(&var)
+            && !vars.contains(&src)
+            && uses[src.0] == 1
+            && defs[src.0] == 1
+            && block.insts[i - 1].dst() == Some(src) =>
+        {
+          *block.insts[i - 1].dst_mut().unwrap() = var;
+          block.insts.remove(i);
+        },
+        _ => i += 1,
+      }
+    }
+  }
+
+  // none of the locals are in memory anymore, unless their address is taken
+  if addressed.is_empty() {
+    function.frame = 0;
+  }
+}
+
+/// Range of instruction positions a temp is live in, from its first
+/// definition to its last use, including any loop it's live around.
+#[derive(Debug, Clone)]
+struct Interval {
+  temp:  Temp,
+  start: usize,
+  end:   usize,
+  /// Whether a call happens while the temp is live, after which it's still
+  /// needed.
+  calls: bool,
+}
+
+fn intervals(function: &IrFunction) -> Vec<Interval> {
+  let live = function.live_in();
+  let mut ranges: Vec<Option<(usize, usize)>> = vec![None; function.temps];
+  let mut extend = |temp: Temp, pos: usize| {
+    let range = ranges[temp.0].get_or_insert((pos, pos));
+    *range = (range.0.min(pos), range.1.max(pos));
+  };
+
+  let mut calls = Vec::new();
+  let mut pos = 0;
+  for (i, block) in function.blocks.iter().enumerate() {
+    for temp in live[i].iter() {
+      extend(*temp, pos);
+    }
+    for inst in block.insts.iter() {
+      for temp in inst.uses().into_iter().chain(inst.dst()) {
+        extend(temp, pos);
+      }
+      if inst.is_call() {
+        calls.push(pos);
+      }
+      pos += 1;
+    }
+    for temp in block.term.uses() {
+      extend(temp, pos);
+    }
+    for succ in block.term.successors() {
+      for temp in live[succ].iter() {
+        extend(*temp, pos);
+      }
+    }
+    pos += 1;
+  }
+
+  let mut intervals = ranges
+    .into_iter()
+    .enumerate()
+    .filter_map(|(i, range)| {
+      range.map(|(start, end)| Interval {
+        temp: Temp(i),
+        start,
+        end,
+        calls: calls.iter().any(|call| start < *call && *call < end),
+      })
+    })
+    .collect::<Vec<_>>();
+  intervals.sort_by_key(|interval| interval.start);
+  intervals
+}
+
+/// For each temp copied from another, the temp it's copied from, so they can
+/// share a register and the copy disappears.
+fn hints(function: &IrFunction) -> Vec<Option<Temp>> {
+  let mut hints = vec![None; function.temps];
+  for block in function.blocks.iter() {
+    for inst in block.insts.iter() {
+      if let Inst::Copy(dst, Value::Temp(src)) = inst {
+        hints[dst.0] = Some(*src);
+      }
+    }
+  }
+  hints
+}
+
+/// Assigns registers to the temps of `function` by linear scan over their
+/// live intervals. When registers run out, whichever of the live temps is
+/// needed for the longest is spilled to the frame, below the locals.
+pub fn allocate(function: &IrFunction) -> Allocation {
+  let hints = hints(function);
+  let mut locs: Vec<Option<Loc>> = vec![None; function.temps];
+  let mut active: Vec<Interval> = Vec::new();
+  let mut spills = 0;
+  let mut saved = Vec::new();
+
+  let mut spill = |locs: &mut Vec<Option<Loc>>, temp: Temp| {
+    spills += 1;
+    locs[temp.0] = Some(Loc::Slot((function.frame + 8 * spills) as isize));
+  };
+
+  for interval in intervals(function) {
+    active.retain(|other| other.end > interval.start);
+
+    let allowed = if interval.calls {
+      CALLEE_SAVED.to_vec()
+    } else {
+      CALLER_SAVED
+        .iter()
+        .chain(CALLEE_SAVED.iter())
+        .copied()
+        .collect()
+    };
+    let taken = active
+      .iter()
+      .filter_map(|other| match locs[other.temp.0] {
+        Some(Loc::Reg(reg)) => Some(reg),
+        _ => None,
+      })
+      .collect::<Vec<_>>();
+    let hint = match hints[interval.temp.0].and_then(|src| locs[src.0]) {
+      Some(Loc::Reg(reg)) => Some(reg),
+      _ => None,
+    };
+
+    let free = hint
+      .into_iter()
+      .chain(allowed.iter().copied())
+      .find(|reg| allowed.contains(reg) && !taken.contains(reg));
+
+    let reg = match free {
+      Some(reg) => Some(reg),
+      None => {
+        // take the register of the temp needed the longest if it's needed
+        // longer than this one
+        let victim = active
+          .iter()
+          .enumerate()
+          .filter(|(_, other)| {
+            matches!(locs[other.temp.0], Some(Loc::Reg(reg)) if allowed.contains(&reg))
+          })
+          .max_by_key(|(_, other)| other.end)
+          .filter(|(_, other)| other.end > interval.end)
+          .map(|(i, _)| i);
+
+        victim.map(|i| {
+          let victim = active.remove(i);
+          let Some(Loc::Reg(reg)) = locs[victim.temp.0] else {
+            unreachable!()
+          };
+          spill(&mut locs, victim.temp);
+          reg
+        })
+      },
+    };
+
+    match reg {
+      Some(reg) => {
+        if CALLEE_SAVED.contains(&reg) && !saved.contains(&reg) {
+          saved.push(reg);
+        }
+        locs[interval.temp.0] = Some(Loc::Reg(reg));
+        active.push(interval);
+      },
+      None => spill(&mut locs, interval.temp),
+    }
+  }
+
+  Allocation {
+    // temps that are never defined nor used still need somewhere to be
+    locs: locs
+      .into_iter()
+      .map(|loc| loc.unwrap_or(Loc::Reg("rax")))
+      .collect(),
+    saved,
+    frame: function.frame + 8 * spills,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn lower(input: &str) -> Module {
+    let program = check(input, "test.pn").unwrap();
+    let mut module = Lowerer::new(program).lower();
+    promote_locals(&mut module.main);
+    for function in module.functions.iter_mut() {
+      promote_locals(function);
+    }
+    module
+  }
+
+  #[test]
+  fn promoted_locals_are_read_directly() {
+    let module = lower(
+      "x := 1
+y := x + 2
+while y > x {
+  y = y - 1
+}
+exit(y)
+",
+    );
+
+    assert_eq!(
+      module.main.to_string(),
+      "fn main():
+b0:
+  %8 = 1
+  %9 = add i64 %8, 2
+  jump b1
+b1:
+  %4 = gt i64 %9, %8
+  branch %4, b2, b3
+b2:
+  %9 = sub i64 %9, 1
+  jump b1
+b3:
+  exit %9
+b4:
+  exit 0
+"
+    );
+  }
+
+  #[test]
+  fn temps_live_across_calls_get_callee_saved_registers() {
+    let module = lower(
+      "fn f(a) {
+  return a
+}
+x := 1
+y := f(x) + f(2)
+exit(x + y)
+",
+    );
+
+    let function = &module.main;
+    let alloc = allocate(function);
+    // x is used after both calls, the first result only after the second
+    for block in function.blocks.iter() {
+      for inst in block.insts.iter() {
+        if let Inst::Binary(_, BinaryOp::Add, _, Value::Temp(lhs), _) = inst {
+          assert!(matches!(alloc.locs[lhs.0], Loc::Reg(reg) if CALLEE_SAVED.contains(&reg)));
+        }
+      }
+    }
+    assert!(alloc.saved.len() >= 2);
+  }
+
+  #[test]
+  fn spills_when_registers_run_out() {
+    // every operand is needed until the sum at the end
+    let module = lower(
+      "a := 1
+b := 2
+c := 3
+d := 4
+e := 5
+f := 6
+g := 7
+h := 8
+i := 9
+exit(a + b + c + d + e + f + g + h + i)
+",
+    );
+
+    let alloc = allocate(&module.main);
+    let regs = CALLEE_SAVED.len() + CALLER_SAVED.len();
+    let spilled = alloc
+      .locs
+      .iter()
+      .filter(|loc| matches!(loc, Loc::Slot(_)))
+      .count();
+    assert_eq!(spilled, 9 - regs);
+    assert_eq!(alloc.frame, 8 * spilled);
+
+    let regs = alloc
+      .locs
+      .iter()
+      .filter_map(|loc| match loc {
+        Loc::Reg(reg) => Some(reg),
+        _ => None,
+      })
+      .collect::<HashSet<_>>();
+    assert!(ARG_REGISTERS.iter().all(|reg| !regs.contains(reg)));
+  }
+}
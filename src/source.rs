@@ -0,0 +1,158 @@
+use std::fmt::Write;
+
+/// Half open range `[start, end)` of byte offsets in the source, so
+/// `&source[span.start..span.end]` is the text it covers.
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct Span {
+  pub start: usize,
+  pub end:   usize,
+}
+
+impl Span {
+  pub fn new(start: usize, end: usize) -> Self {
+    Self { start, end }
+  }
+
+  /// Smallest span covering both `self` and `other`.
+  pub fn to(&self, other: Span) -> Span {
+    Span::new(self.start.min(other.start), self.end.max(other.end))
+  }
+}
+
+/// A source file split into lines, used to turn positions into
+/// `file:line:col` locations and render the code they point at.
+#[derive(Debug, Clone)]
+pub struct SourceMap {
+  name:   String,
+  lines:  Vec<String>,
+  /// Byte offset of the start of every line.
+  starts: Vec<usize>,
+}
+
+impl SourceMap {
+  pub fn new(name: &str, source: &str) -> Self {
+    let lines = source.split('\n').map(str::to_string).collect::<Vec<_>>();
+    let mut starts = vec![0];
+    for line in lines.iter() {
+      starts.push(starts.last().unwrap() + line.len() + 1);
+    }
+    starts.pop();
+
+    Self {
+      name: name.to_string(),
+      lines,
+      starts,
+    }
+  }
+
+  pub fn name(&self) -> &str {
+    &self.name
+  }
+
+  /// 1-based line and column of the byte offset `pos`, counting columns in
+  /// characters.
+  pub fn location(&self, pos: usize) -> (usize, usize) {
+    let line = match self.starts.binary_search(&pos) {
+      Ok(line) => line,
+      Err(next) => next - 1,
+    };
+    let offset = (pos - self.starts[line]).min(self.lines[line].len());
+    (line + 1, self.lines[line][..offset].chars().count() + 1)
+  }
+
+  /// Text of the 1-based `line`, without its newline.
+  pub fn line(&self, line: usize) -> &str {
+    &self.lines[line - 1]
+  }
+
+  /// Renders a diagnostic: the message, its location and the offending line
+  /// with the span underlined, followed by any notes.
+  ///
+  /// ```text
+  /// Parser error: Variable 'x' not declared
+  ///  --> hello.pn:3:6
+  ///   |
+  /// 3 | exit(x)
+  ///   |      ^
+  ///   = help: declare it first with 'x := ...'
+  /// ```
+  pub fn render(&self, kind: &str, span: Span, msg: &str, notes: &[Note]) -> String {
+    let (line, col) = self.location(span.start);
+    let text = self.line(line);
+    let gutter = " ".repeat(line.to_string().len());
+
+    let mut out = String::new();
+    writeln!(out, "{}: {}", kind, msg).unwrap();
+    writeln!(out, "{}--> {}:{}:{}", gutter, self.name, line, col).unwrap();
+    writeln!(out, "{} |", gutter).unwrap();
+    writeln!(out, "{} | {}", line, text).unwrap();
+
+    // keep tabs so the carets line up with the text above them
+    let indent = text
+      .chars()
+      .take(col - 1)
+      .map(|c| if c == '\t' { '\t' } else { ' ' })
+      .collect::<String>();
+    // underline up to the end of the span or of the line, in characters
+    let start = (span.start - self.starts[line - 1]).min(text.len());
+    let end = span
+      .end
+      .saturating_sub(self.starts[line - 1])
+      .clamp(start, text.len());
+    let width = text[start..end].chars().count();
+    write!(out, "{} | {}{}", gutter, indent, "^".repeat(width.max(1))).unwrap();
+
+    for note in notes.iter() {
+      write!(out, "\n{} = {}", gutter, note).unwrap();
+    }
+
+    out
+  }
+}
+
+/// Extra information attached to a diagnostic.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Note {
+  Note(String),
+  Help(String),
+}
+
+impl std::fmt::Display for Note {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    match self {
+      Note::Note(msg) => write!(f, "note: {}", msg),
+      Note::Help(msg) => write!(f, "help: {}", msg),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::*;
+
+  #[test]
+  fn diagnostics_point_at_the_source() {
+    let source = "x := 1\ny = x + 2\nexit(y)\n";
+    let map = SourceMap::new("test.pn", source);
+    let errors = Parser::new(Lexer::new(source).lex().unwrap())
+      .parse()
+      .unwrap_err();
+    assert_eq!(
+      errors[0].render(&map),
+      "Parser error: Variable 'y' not declared
+ --> test.pn:2:1
+  |
+2 | y = x + 2
+  | ^
+  = help: use 'y := ...' to declare it"
+    );
+
+    let notes = [
+      Note::Note("first".to_string()),
+      Note::Help("second".to_string()),
+    ];
+    assert!(map
+      .render("Lexer error", Span::new(11, 16), "Oops", &notes)
+      .ends_with("2 | y = x + 2\n  |     ^^^^^\n  = note: first\n  = help: second"));
+  }
+}
@@ -1,25 +1,69 @@
+use std::fmt;
+
+use super::*;
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum Keyword {
   Exit,
+  If,
+  Else,
+  While,
+  Fn,
+  Return,
+  Struct,
+  Enum,
+  Match,
+  As,
 }
 
-impl ToString for Keyword {
-  fn to_string(&self) -> String {
+impl fmt::Display for Keyword {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
     match self {
-      Keyword::Exit => "exit".to_string(),
+      Keyword::Exit => write!(f, "exit"),
+      Keyword::If => write!(f, "if"),
+      Keyword::Else => write!(f, "else"),
+      Keyword::While => write!(f, "while"),
+      Keyword::Fn => write!(f, "fn"),
+      Keyword::Return => write!(f, "return"),
+      Keyword::Struct => write!(f, "struct"),
+      Keyword::Enum => write!(f, "enum"),
+      Keyword::Match => write!(f, "match"),
+      Keyword::As => write!(f, "as"),
     }
   }
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Literal {
-  Integer(i32),
+  Integer(i64),
+  /// Integer literal too large for `i64`.
+  Unsigned(u64),
+  /// String literal with its escape sequences resolved.
+  String(String),
+  Bool(bool),
 }
 
-impl ToString for Literal {
-  fn to_string(&self) -> String {
+impl fmt::Display for Literal {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
     match self {
-      Literal::Integer(val) => val.to_string(),
+      Literal::Integer(val) => write!(f, "{}", val),
+      Literal::Unsigned(val) => write!(f, "{}", val),
+      Literal::Bool(val) => write!(f, "{}", val),
+      Literal::String(val) => {
+        write!(f, "\"")?;
+        for c in val.chars() {
+          match c {
+            '\n' => write!(f, "\\n")?,
+            '\t' => write!(f, "\\t")?,
+            '\r' => write!(f, "\\r")?,
+            '\0' => write!(f, "\\0")?,
+            '\\' | '"' => write!(f, "\\{}", c)?,
+            c if c.is_ascii_control() => write!(f, "\\x{:02x}", c as u32)?,
+            c => write!(f, "{}", c)?,
+          }
+        }
+        write!(f, "\"")
+      },
     }
   }
 }
@@ -28,17 +72,69 @@ impl ToString for Literal {
 pub enum Symbol {
   LeftParen,
   RightParen,
+  LeftBrace,
+  RightBrace,
+  LeftBracket,
+  RightBracket,
+  Comma,
+  Semicolon,
+  Dot,
   Equals,
+  Colon,
+  ColonColon,
   ColonEquals,
+  Plus,
+  Minus,
+  Arrow,
+  FatArrow,
+  Star,
+  Slash,
+  Percent,
+  EqualsEquals,
+  BangEquals,
+  Less,
+  LessEquals,
+  Greater,
+  GreaterEquals,
+  Amp,
+  AmpAmp,
+  PipePipe,
+  Bang,
 }
 
-impl ToString for Symbol {
-  fn to_string(&self) -> String {
+impl fmt::Display for Symbol {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
     match self {
-      Symbol::LeftParen => "(".to_string(),
-      Symbol::RightParen => ")".to_string(),
-      Symbol::Equals => "=".to_string(),
-      Symbol::ColonEquals => ":=".to_string(),
+      Symbol::LeftParen => write!(f, "("),
+      Symbol::RightParen => write!(f, ")"),
+      Symbol::LeftBrace => write!(f, "{{"),
+      Symbol::RightBrace => write!(f, "}}"),
+      Symbol::LeftBracket => write!(f, "["),
+      Symbol::RightBracket => write!(f, "]"),
+      Symbol::Comma => write!(f, ","),
+      Symbol::Semicolon => write!(f, ";"),
+      Symbol::Dot => write!(f, "."),
+      Symbol::Equals => write!(f, "="),
+      Symbol::Colon => write!(f, ":"),
+      Symbol::ColonColon => write!(f, "::"),
+      Symbol::ColonEquals => write!(f, ":="),
+      Symbol::Plus => write!(f, "+"),
+      Symbol::Minus => write!(f, "-"),
+      Symbol::Arrow => write!(f, "->"),
+      Symbol::FatArrow => write!(f, "=>"),
+      Symbol::Star => write!(f, "*"),
+      Symbol::Slash => write!(f, "/"),
+      Symbol::Percent => write!(f, "%"),
+      Symbol::EqualsEquals => write!(f, "=="),
+      Symbol::BangEquals => write!(f, "!="),
+      Symbol::Less => write!(f, "<"),
+      Symbol::LessEquals => write!(f, "<="),
+      Symbol::Greater => write!(f, ">"),
+      Symbol::GreaterEquals => write!(f, ">="),
+      Symbol::Amp => write!(f, "&"),
+      Symbol::AmpAmp => write!(f, "&&"),
+      Symbol::PipePipe => write!(f, "||"),
+      Symbol::Bang => write!(f, "!"),
     }
   }
 }
@@ -69,6 +165,13 @@ pub enum Token {
     start: usize,
     end:   usize,
   },
+  /// A `//` or `/* */` comment, delimiters included. Only kept when lexing for
+  /// the formatter, see [`Lexer::with_comments`].
+  Comment {
+    val:   String,
+    start: usize,
+    end:   usize,
+  },
   EOF {
     start: usize,
     end:   usize,
@@ -83,6 +186,7 @@ impl Token {
       Token::Literal { .. } => "Literal".to_string(),
       Token::Symbol { .. } => "Symbol".to_string(),
       Token::Newline { .. } => "Newline".to_string(),
+      Token::Comment { .. } => "Comment".to_string(),
       Token::EOF { .. } => "EOF".to_string(),
     }
   }
@@ -94,10 +198,15 @@ impl Token {
       Token::Literal { start, .. } => *start,
       Token::Symbol { start, .. } => *start,
       Token::Newline { start, .. } => *start,
+      Token::Comment { start, .. } => *start,
       Token::EOF { start, .. } => *start,
     }
   }
 
+  pub fn span(&self) -> Span {
+    Span::new(self.start(), self.end())
+  }
+
   pub fn end(&self) -> usize {
     match self {
       Token::Keyword { end, .. } => *end,
@@ -105,20 +214,22 @@ impl Token {
       Token::Literal { end, .. } => *end,
       Token::Symbol { end, .. } => *end,
       Token::Newline { end, .. } => *end,
+      Token::Comment { end, .. } => *end,
       Token::EOF { end, .. } => *end,
     }
   }
 }
 
-impl ToString for Token {
-  fn to_string(&self) -> String {
+impl fmt::Display for Token {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
     match self {
-      Token::Keyword { val, .. } => val.to_string(),
-      Token::Identifier { val, .. } => val.clone(),
-      Token::Literal { val, .. } => val.to_string(),
-      Token::Symbol { val, .. } => val.to_string(),
-      Token::Newline { .. } => "<NEWLINE>".to_string(),
-      Token::EOF { .. } => "<EOF>".to_string(),
+      Token::Keyword { val, .. } => write!(f, "{}", val),
+      Token::Identifier { val, .. } => write!(f, "{}", val),
+      Token::Literal { val, .. } => write!(f, "{}", val),
+      Token::Symbol { val, .. } => write!(f, "{}", val),
+      Token::Newline { .. } => write!(f, "<NEWLINE>"),
+      Token::Comment { val, .. } => write!(f, "{}", val),
+      Token::EOF { .. } => write!(f, "<EOF>"),
     }
   }
 }
@@ -0,0 +1,207 @@
+//! Golden tests: every `tests/golden/*.pn` is compiled and its outputs compared
+//! with the snapshots next to it.
+//!
+//! - `.tokens`: the tokens, when the source lexes
+//! - `.ast`: the checked AST, when it type checks
+//! - `.err`: the diagnostics, when it doesn't
+//! - `.run`: the exit code, stdout and any stderr, the same for the interpreter
+//!   and the executable at every `-O` level
+//!
+//! ```sh
+//! cargo test --test golden [filter]
+//! BLESS=1 cargo test --test golden  # rewrite the snapshots
+//! ```
+
+use std::{
+  os::unix::{fs::PermissionsExt, process::ExitStatusExt},
+  path::{Path, PathBuf},
+  process::{Command, ExitCode, Output},
+};
+
+use pecan::*;
+
+const DIR: &str = "tests/golden";
+const SNAPSHOTS: [&str; 4] = ["tokens", "ast", "err", "run"];
+
+/// Exit code, or signal, stdout and any stderr of a finished program.
+fn describe(output: Output) -> String {
+  let status = match output.status.code() {
+    Some(code) => format!("exit: {}", code),
+    None => format!("signal: {}", output.status.signal().unwrap_or_default()),
+  };
+  let mut description = format!("{}\n{}", status, String::from_utf8_lossy(&output.stdout));
+  if !output.stderr.is_empty() {
+    description += &format!("stderr:\n{}", String::from_utf8_lossy(&output.stderr));
+  }
+  description
+}
+
+/// Runs `path` with the interpreter.
+fn interpret(path: &Path) -> Result<String, String> {
+  let output = Command::new(env!("CARGO_BIN_EXE_pn"))
+    .args(["run", "-i"])
+    .arg(path)
+    .output()
+    .map_err(|err| err.to_string())?;
+  Ok(describe(output))
+}
+
+/// Compiles `source` to an executable at `opt_level` and runs it.
+fn execute(source: &str, name: &str, opt_level: OptLevel) -> Result<String, String> {
+  let options = CompileOptions {
+    file_name: name.to_string(),
+    opt_level,
+    ..Default::default()
+  };
+  let artifact = compile(source, &options).map_err(|err| err.to_string())?;
+
+  let exe = std::env::temp_dir().join(format!(
+    "pn-golden-{}-{:?}-{}",
+    name,
+    opt_level,
+    std::process::id()
+  ));
+  std::fs::write(&exe, &artifact.data).map_err(|err| err.to_string())?;
+  std::fs::set_permissions(&exe, std::fs::Permissions::from_mode(0o755))
+    .map_err(|err| err.to_string())?;
+  let output = Command::new(&exe).output().map_err(|err| err.to_string());
+  std::fs::remove_file(&exe).ok();
+  Ok(describe(output?))
+}
+
+/// The snapshots `path` should have, by extension.
+fn outputs(path: &Path) -> Result<Vec<(&'static str, String)>, String> {
+  let source = std::fs::read_to_string(path).map_err(|err| err.to_string())?;
+  let name = path.file_name().unwrap().to_string_lossy().to_string();
+  let mut outputs = vec![];
+
+  let stage = |emit| {
+    let options = CompileOptions {
+      file_name: name.clone(),
+      emit,
+      ..Default::default()
+    };
+    compile(&source, &options).map(|artifact| String::from_utf8(artifact.data).unwrap())
+  };
+
+  match stage(Emit::Tokens) {
+    Ok(tokens) => outputs.push(("tokens", tokens)),
+    Err(diagnostics) => return Ok(vec![("err", diagnostics.to_string())]),
+  }
+  match stage(Emit::Ast) {
+    Ok(ast) => outputs.push(("ast", ast)),
+    Err(diagnostics) => {
+      outputs.push(("err", diagnostics.to_string()));
+      return Ok(outputs);
+    },
+  }
+
+  let run = interpret(path)?;
+  for opt_level in [OptLevel::O0, OptLevel::O1, OptLevel::O2] {
+    let native = execute(&source, &name, opt_level)?;
+    if native != run {
+      return Err(format!(
+        "the executable at {:?} differs from the interpreter:\n{}\ninterpreter:\n{}",
+        opt_level, native, run
+      ));
+    }
+  }
+  outputs.push(("run", run));
+
+  Ok(outputs)
+}
+
+/// Where `expected` and `actual` first differ.
+fn difference(expected: &str, actual: &str) -> String {
+  let line = expected
+    .lines()
+    .zip(actual.lines())
+    .position(|(expected, actual)| expected != actual)
+    .unwrap_or(expected.lines().count().min(actual.lines().count()));
+  format!(
+    "first difference on line {}:\n  expected: {:?}\n  actual:   {:?}",
+    line + 1,
+    expected.lines().nth(line).unwrap_or("<end>"),
+    actual.lines().nth(line).unwrap_or("<end>"),
+  )
+}
+
+/// Compares the outputs of `path` with its snapshots, or rewrites them if
+/// `bless`.
+fn test(path: &Path, bless: bool) -> Result<(), String> {
+  let outputs = outputs(path)?;
+
+  for ext in SNAPSHOTS {
+    let snapshot = path.with_extension(ext);
+    let actual = outputs.iter().find(|(e, _)| *e == ext).map(|(_, o)| o);
+
+    if bless {
+      match actual {
+        Some(actual) => std::fs::write(&snapshot, actual).map_err(|err| err.to_string())?,
+        None if snapshot.exists() => {
+          std::fs::remove_file(&snapshot).map_err(|err| err.to_string())?
+        },
+        None => {},
+      }
+      continue;
+    }
+
+    match (actual, std::fs::read_to_string(&snapshot).ok()) {
+      (Some(actual), Some(expected)) if *actual != expected => {
+        return Err(format!(
+          "{} differs, {}",
+          snapshot.display(),
+          difference(&expected, actual)
+        ));
+      },
+      (Some(_), None) => return Err(format!("{} is missing", snapshot.display())),
+      (None, Some(_)) => return Err(format!("{} is not produced anymore", snapshot.display())),
+      _ => {},
+    }
+  }
+
+  Ok(())
+}
+
+fn main() -> ExitCode {
+  let bless = std::env::var_os("BLESS").is_some();
+  let filter = std::env::args()
+    .skip(1)
+    .find(|arg| !arg.starts_with('-'))
+    .unwrap_or_default();
+
+  let mut cases = std::fs::read_dir(DIR)
+    .unwrap()
+    .map(|entry| entry.unwrap().path())
+    .filter(|path| path.extension().is_some_and(|ext| ext == "pn"))
+    .filter(|path| path.to_string_lossy().contains(&filter))
+    .collect::<Vec<PathBuf>>();
+  cases.sort();
+
+  let mut failed = 0;
+  for case in &cases {
+    match test(case, bless) {
+      Ok(()) => println!("golden {} ... ok", case.display()),
+      Err(err) => {
+        println!("golden {} ... FAILED\n{}\n", case.display(), err);
+        failed += 1;
+      },
+    }
+  }
+
+  println!(
+    "\n{} passed, {} failed{}",
+    cases.len() - failed,
+    failed,
+    if failed > 0 && !bless {
+      ", run with BLESS=1 to update the snapshots"
+    } else {
+      ""
+    }
+  );
+  if failed > 0 {
+    ExitCode::FAILURE
+  } else {
+    ExitCode::SUCCESS
+  }
+}